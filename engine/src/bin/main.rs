@@ -15,14 +15,24 @@ use sdl2::keyboard::Keycode;
 use awlib::AnotherWorldEngine;
 use awlib::opcodes::ActionRequest;
 use awlib::defines::{FRAME_BUFFER_WIDTH, FRAME_BUFFER_HEIGHT};
+use awlib::color::PixelFormat;
+use awlib::capture::CaptureFormat;
 
 // to run: cargo run --features game
+// options: --capture <path> records the frames shown (a directory for numbered pngs, a .gif file, or a .png or .apng
+// file for an animated png), and --capture-frames <first>-<last> only records the frames of the range (from 0)
 // Althoug the target is the javascript version, I´ve been using this quick and dirty rust version to debug the engine. The engine works fine, but the polygons are rendered broken, I don´t know why.
 
 const WINDOW_WIDTH: i32  = 960;
 const WINDOW_HEIGHT: i32 = 600;
+const SLICE_BUDGET: u32 = 20000; // instructions a thread can run without yielding
+const FRAME_BUDGET: u32 = 200000; // instructions all the threads can run in a frame
 
 fn main() {
+  let args: Vec<String> = std::env::args().collect();
+  let capture_path = get_option(&args, "--capture");
+  let (first_capture_frame, last_capture_frame) = get_option(&args, "--capture-frames").map_or((0, None), |range| parse_frame_range(&range));
+
   // load zip file
   let filename = format!("./game.zip");
   let path = Path::new(&filename);
@@ -103,10 +113,16 @@ fn main() {
 
   engine.set_game_data(&game_data);
   engine.init();
+  engine.set_instruction_budget(SLICE_BUDGET, FRAME_BUDGET);
   engine.vm_restart(1); // 0xff = protection screen
 
+  if capture_path.is_some() {
+    engine.start_capture(first_capture_frame, last_capture_frame);
+  }
+
   let mut event_pump = sdl.event_pump().unwrap();
   let mut paused = false;
+  let mut frame = vec![0u8; FRAME_BUFFER_WIDTH as usize * FRAME_BUFFER_HEIGHT as usize * 4];
 
   'main: loop {
     for event in event_pump.poll_iter() {
@@ -118,6 +134,9 @@ fn main() {
           Event::KeyDown { keycode: Some(Keycode::Down), repeat: false, .. } => engine.on_key_down(4),
           Event::KeyDown { keycode: Some(Keycode::Left), repeat: false, .. } => engine.on_key_down(8),
           Event::KeyDown { keycode: Some(Keycode::Space), repeat: false, .. } => engine.on_key_down(16),
+          Event::KeyDown { keycode: Some(Keycode::Backspace), .. } => engine.on_char(8),
+          Event::KeyDown { keycode: Some(Keycode::Return), .. } => engine.on_char(0x0d),
+          Event::TextInput { text, .. } => text.bytes().for_each(|c| engine.on_char(c)),
           Event::KeyUp { keycode: Some(Keycode::Up), repeat: false, .. } => engine.on_key_up(1),
           Event::KeyUp { keycode: Some(Keycode::Right), repeat: false, .. } => engine.on_key_up(2),
           Event::KeyUp { keycode: Some(Keycode::Down), repeat: false, .. } => engine.on_key_up(4),
@@ -130,22 +149,58 @@ fn main() {
     if !paused {
       let action_requested = engine.vm_step();
 
-      if (action_requested >> 24) as u8 == ActionRequest::Blit as u8 {
-        let frame_buffer_ptr = engine.get_frame_buffer();
+      if (action_requested >> 24) as u8 == ActionRequest::BudgetExceeded as u8 {
+        // the thread is probably in an endless loop, it continues in the next frame
+        eprintln!("thread {:02X} exceeded the instruction budget at {:04X}", (action_requested >> 16) & 0xff, action_requested & 0xffff);
+        engine.vm_force_yield();
+      } else if (action_requested >> 24) as u8 == ActionRequest::InvalidPart as u8 {
+        eprintln!("thread {:02X} tried to load the part {:04X} that doesn't exist", (action_requested >> 16) & 0xff, action_requested & 0xffff);
+      } else if (action_requested >> 24) as u8 == ActionRequest::Blit as u8 {
+        engine.write_frame(&mut frame, FRAME_BUFFER_WIDTH as usize * 4, PixelFormat::Rgba8888).unwrap();
 
         unsafe {
-          gl::TexImage2D(gl::TEXTURE_2D, 0, gl::RGB as i32, FRAME_BUFFER_WIDTH as i32, FRAME_BUFFER_HEIGHT as i32, 0, gl::RGBA, gl::UNSIGNED_BYTE, frame_buffer_ptr as *const c_void);
+          gl::TexImage2D(gl::TEXTURE_2D, 0, gl::RGB as i32, FRAME_BUFFER_WIDTH as i32, FRAME_BUFFER_HEIGHT as i32, 0, gl::RGBA, gl::UNSIGNED_BYTE, frame.as_ptr() as *const c_void);
           gl::BlitFramebuffer(0, 0, FRAME_BUFFER_WIDTH as i32, FRAME_BUFFER_HEIGHT as i32, 0, WINDOW_HEIGHT, WINDOW_WIDTH, 0, gl::COLOR_BUFFER_BIT, gl::NEAREST);
         }
 
         window.gl_swap_window();
 
+        if let (true, Some(path)) = (engine.is_capture_finished(), &capture_path) {
+          save_capture(&mut engine, path);
+        }
+
         thread::sleep(time::Duration::from_millis(16));
       }
     }
   }
 
+  if let Some(path) = &capture_path {
+    save_capture(&mut engine, path);
+  }
+
   unsafe {
     gl::DeleteFramebuffers(1, &mut fbo_id);
   }
+}
+
+fn get_option(args: &[String], name: &str) -> Option<String> {
+  args.iter().position(|arg| arg == name).and_then(|idx| args.get(idx + 1)).cloned()
+}
+
+// "first-last", or "first-" until the game is closed
+fn parse_frame_range(range: &str) -> (u32, Option<u32>) {
+  let mut parts = range.splitn(2, '-');
+  let first = parts.next().and_then(|first| first.trim().parse().ok()).unwrap_or(0);
+  let last = parts.next().and_then(|last| last.trim().parse().ok());
+
+  (first, last)
+}
+
+fn save_capture(engine: &mut AnotherWorldEngine, path: &str) {
+  if let Some(capture) = engine.stop_capture() {
+    match capture.save(path, CaptureFormat::from_path(path)) {
+      Ok(()) => println!("{} frames saved to {}", capture.frames.len(), path),
+      Err(message) => eprintln!("can't save the capture: {}", message)
+    }
+  }
 }
\ No newline at end of file
@@ -0,0 +1,610 @@
+use std::collections::HashMap;
+use std::fmt;
+use crate::instruction::{Instruction, Condition, Value, DEFAULT_ZOOM, decode};
+use crate::opcodes::Opcodes;
+
+// Assembler for the syntax generated by the disassembler (get_asm_code), plus:
+// - comments starting with ';'
+// - labels ("name:") that can be used instead of the addresses of CALL, JMP, JNZ, CJxx, SETVEC and .dw
+// - the directives .org ADDR, .db BYTE, ..., .dw WORD, ... and .equ NAME, VALUE
+// Numbers are hexadecimal, with or without the 0x prefix. The DRAWPOLY and CJxx operands are stored using
+// the most compact encoding unless they are written with 4 digits, which forces the 16 bit form.
+// DRAWPOLYS OFFSET, X, Y is the short form of DRAWPOLY1 with its bytes as they are (see Instruction::DrawPolyShort),
+// and DRAWPOLYL is a DRAWPOLY1 that is never stored in the short form.
+
+#[derive(Debug, PartialEq)]
+pub struct AssemblerError {
+  pub line: usize,
+  pub message: String
+}
+
+impl fmt::Display for AssemblerError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "line {}: {}", self.line, self.message)
+  }
+}
+
+enum Addr {
+  Value(u16),
+  Label(String)
+}
+
+enum Item {
+  Instruction(Instruction, Option<Addr>),
+  Bytes(Vec<u8>),
+  Words(Vec<Addr>),
+  Org(u16)
+}
+
+struct Number {
+  value: i32,
+  wide: bool // written with 4 or more digits
+}
+
+pub fn assemble(source: &str) -> Result<Vec<u8>, AssemblerError> {
+  let mut items: Vec<(usize, Item)> = Vec::new();
+  let mut labels: HashMap<String, u16> = HashMap::new();
+  let mut constants: HashMap<String, i32> = HashMap::new();
+  let mut pc: u32 = 0;
+
+  // first pass: parse the lines and assign an address to the labels
+  for (idx, raw_line) in source.lines().enumerate() {
+    let line_num = idx + 1;
+    let error = |message: String| AssemblerError { line: line_num, message };
+    let mut line = raw_line.split(';').next().unwrap().trim();
+
+    if let Some(colon) = line.find(':') {
+      let label = line[..colon].trim();
+
+      if is_identifier(label) {
+        if parse_hex(label).is_some() {
+          return Err(error(format!("label '{}' is also a valid number", label)));
+        }
+
+        if labels.insert(label.to_string(), pc as u16).is_some() {
+          return Err(error(format!("label '{}' is already defined", label)));
+        }
+
+        line = line[colon + 1..].trim();
+      }
+    }
+
+    if line.is_empty() {
+      continue;
+    }
+
+    let (mnemonic, operands) = split_operands(line);
+    let mnemonic = mnemonic.to_ascii_uppercase();
+
+    let item = match mnemonic.as_str() {
+      ".ORG" => {
+        let addr = expect_operands(&operands, 1).and_then(|_| parse_u16(operands[0], &constants)).map_err(error)?;
+
+        if (addr as u32) < pc {
+          return Err(error(format!(".org {:04X} is behind the current address {:04X}", addr, pc)));
+        }
+
+        Item::Org(addr)
+      },
+      ".DB" => {
+        let bytes = operands.iter().map(|o| parse_u8(o, &constants)).collect::<Result<Vec<u8>, String>>().map_err(error)?;
+        Item::Bytes(bytes)
+      },
+      ".DW" => {
+        let words = operands.iter().map(|o| parse_addr(o, &constants)).collect::<Result<Vec<Addr>, String>>().map_err(error)?;
+        Item::Words(words)
+      },
+      ".EQU" => {
+        expect_operands(&operands, 2).map_err(error)?;
+
+        if !is_identifier(operands[0]) || parse_hex(operands[0]).is_some() {
+          return Err(error(format!("invalid constant name '{}'", operands[0])));
+        }
+
+        let value = parse_number(operands[1], &constants).map_err(error)?.value;
+        constants.insert(operands[0].to_string(), value);
+        continue;
+      },
+      _ => {
+        let (instruction, addr) = parse_instruction(&mnemonic, &operands, &constants).map_err(error)?;
+        Item::Instruction(instruction, addr)
+      }
+    };
+
+    pc = match &item {
+      Item::Instruction(instruction, _) => pc + instruction.encode().map_err(error)?.len() as u32,
+      Item::Bytes(bytes) => pc + bytes.len() as u32,
+      Item::Words(words) => pc + words.len() as u32 * 2,
+      Item::Org(addr) => *addr as u32
+    };
+
+    if pc > 0x10000 {
+      return Err(error("the script is bigger than 64Kb".to_string()));
+    }
+
+    items.push((line_num, item));
+  }
+
+  // second pass: resolve the labels and generate the bytecode
+  let mut output = Vec::with_capacity(pc as usize);
+
+  for (line_num, item) in items {
+    let error = |message: String| AssemblerError { line: line_num, message };
+
+    match item {
+      Item::Instruction(instruction, addr) => {
+        let final_instruction = match addr {
+          Some(addr) => with_addr(instruction, resolve(&addr, &labels).map_err(error)?),
+          None => instruction
+        };
+
+        output.extend_from_slice(&final_instruction.encode().map_err(error)?);
+      },
+      Item::Bytes(bytes) => output.extend_from_slice(&bytes),
+      Item::Words(words) => {
+        for word in words {
+          output.extend_from_slice(&resolve(&word, &labels).map_err(error)?.to_be_bytes());
+        }
+      },
+      Item::Org(addr) => output.resize(addr as usize, 0)
+    }
+  }
+
+  Ok(output)
+}
+
+// assembles a single instruction, without labels
+pub fn assemble_instruction(text: &str) -> Result<Vec<u8>, String> {
+  let (mnemonic, operands) = split_operands(text.trim());
+  let (instruction, addr) = parse_instruction(&mnemonic.to_ascii_uppercase(), &operands, &HashMap::new())?;
+
+  match addr {
+    Some(Addr::Label(label)) => Err(format!("unknown label '{}'", label)),
+    Some(Addr::Value(value)) => with_addr(instruction, value).encode(),
+    None => instruction.encode()
+  }
+}
+
+// builds a source that assembles back to the same bytes
+pub fn build_source(script: &[u8], opcodes: &Opcodes) -> String {
+  let mut source = String::new();
+  let mut pc: usize = 0;
+
+  while pc < script.len() {
+    let (text, comment, len) = source_instruction(script, pc as u16, opcodes);
+
+    match comment {
+      Some(comment) => source.push_str(&format!("  {:<40}; {:04X} {}\n", text, pc, comment)),
+      None => source.push_str(&format!("  {:<40}; {:04X}\n", text, pc))
+    }
+
+    pc += len as usize;
+  }
+
+  source
+}
+
+// returns the source of the instruction at pc, an optional comment and the instruction length. The bytes that can't be
+// decoded are returned as .db. All the instructions that can be decoded assemble back to the same bytes, but if the
+// disassembled text of one doesn't, it's also returned as .db, with the text in the comment to tell it apart
+pub fn source_instruction(script: &[u8], pc: u16, opcodes: &Opcodes) -> (String, Option<String>, u16) {
+  match decode(script, pc) {
+    Some((_, len)) => {
+      let bytes = &script[pc as usize..(pc + len) as usize];
+      let asm_code = (opcodes.get(bytes[0]).get_asm_code)(pc + 1, script);
+
+      if assemble_instruction(&asm_code).map(|b| b == bytes).unwrap_or(false) {
+        (asm_code, None, len)
+      } else {
+        (format_bytes(bytes), Some(format!("{} (not reassembled)", asm_code)), len)
+      }
+    },
+    None => (format_bytes(&script[pc as usize..pc as usize + 1]), Some("invalid opcode".to_string()), 1)
+  }
+}
+
+fn format_bytes(bytes: &[u8]) -> String {
+  let values: Vec<String> = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+  format!(".db {}", values.join(", "))
+}
+
+fn parse_instruction(mnemonic: &str, operands: &[&str], constants: &HashMap<String, i32>) -> Result<(Instruction, Option<Addr>), String> {
+  let num_operands = match mnemonic {
+    "RET" | "YIELD" | "KILL" => 0,
+    "CALL" | "JMP" | "SETPAL" | "SETVIDPAG" | "BLIT" | "LDRES" => 1,
+    "MOV" | "ADD" | "SUB" | "AND" | "OR" | "SHL" | "SHR" | "SETVEC" | "JNZ" | "FILLVIDPAG" | "CPVIDPAG" => 2,
+    "CJZ" | "CJNZ" | "CJG" | "CJGE" | "CJL" | "CJLE" | "RESET" | "MUSIC" | "DRAWPOLYS" => 3,
+    "DRAWSTR" | "SND" | "DRAWPOLY1" | "DRAWPOLY2" | "DRAWPOLYL" => 4,
+    _ => return Err(format!("unknown mnemonic '{}'", mnemonic))
+  };
+
+  expect_operands(operands, num_operands)?;
+
+  let reg = |idx: usize| parse_register(operands[idx]).ok_or_else(|| format!("'{}' is not a register", operands[idx]));
+  let byte = |idx: usize| parse_u8(operands[idx], constants);
+  let word = |idx: usize| parse_u16(operands[idx], constants);
+  let addr = |idx: usize| parse_addr(operands[idx], constants);
+
+  let result = match mnemonic {
+    "MOV" | "ADD" => {
+      let dst = reg(0)?;
+
+      match parse_register(operands[1]) {
+        Some(src) => (if mnemonic == "MOV" { Instruction::Mov { dst, src } } else { Instruction::Add { dst, src } }, None),
+        None => {
+          let value = word(1)? as i16;
+          (if mnemonic == "MOV" { Instruction::MovConst { dst, value } } else { Instruction::AddConst { dst, value } }, None)
+        }
+      }
+    },
+    "SUB" => (Instruction::Sub { dst: reg(0)?, src: reg(1)? }, None),
+    "AND" => (Instruction::And { dst: reg(0)?, value: word(1)? }, None),
+    "OR" => (Instruction::Or { dst: reg(0)?, value: word(1)? }, None),
+    "SHL" => (Instruction::Shl { dst: reg(0)?, value: word(1)? }, None),
+    "SHR" => (Instruction::Shr { dst: reg(0)?, value: word(1)? }, None),
+    "CALL" => (Instruction::Call { addr: 0 }, Some(addr(0)?)),
+    "RET" => (Instruction::Ret, None),
+    "YIELD" => (Instruction::Yield, None),
+    "KILL" => (Instruction::Kill, None),
+    "JMP" => (Instruction::Jmp { addr: 0 }, Some(addr(0)?)),
+    "SETVEC" => (Instruction::SetVec { thread_id: byte(0)?, addr: 0 }, Some(addr(1)?)),
+    "JNZ" => (Instruction::Jnz { reg: reg(0)?, addr: 0 }, Some(addr(1)?)),
+    "CJZ" | "CJNZ" | "CJG" | "CJGE" | "CJL" | "CJLE" => {
+      let condition = match mnemonic {
+        "CJZ" => Condition::Equal,
+        "CJNZ" => Condition::NotEqual,
+        "CJG" => Condition::Greater,
+        "CJGE" => Condition::GreaterOrEqual,
+        "CJL" => Condition::Less,
+        _ => Condition::LessOrEqual
+      };
+
+      (Instruction::CondJmp { condition, reg: reg(0)?, operand: parse_value(operands[1], constants)?, addr: 0 }, Some(addr(2)?))
+    },
+    "SETPAL" => (Instruction::SetPalette { palette: word(0)? }, None),
+    "RESET" => {
+      let action = match operands[2].to_ascii_uppercase().as_str() {
+        "NONE" => 0,
+        "YIELD" => 1,
+        "KILL" => 2,
+        _ => byte(2)?
+      };
+
+      (Instruction::ResetThreads { first: byte(0)?, last: byte(1)?, action }, None)
+    },
+    "SETVIDPAG" => (Instruction::SelectPage { page: byte(0)? }, None),
+    "FILLVIDPAG" => (Instruction::FillPage { page: byte(0)?, color: byte(1)? }, None),
+    "CPVIDPAG" => (Instruction::CopyPage { src: byte(0)?, dst: byte(1)? }, None),
+    "BLIT" => (Instruction::Blit { page: byte(0)? }, None),
+    "DRAWSTR" => (Instruction::DrawString { string_id: word(0)?, x: byte(1)?, y: byte(2)?, color: byte(3)? }, None),
+    "SND" => (Instruction::PlaySound { resource_id: word(0)?, freq: byte(1)?, volume: byte(2)?, channel: byte(3)? }, None),
+    "LDRES" => (Instruction::LoadResource { resource_id: word(0)? }, None),
+    "MUSIC" => (Instruction::PlayMusic { resource_id: word(0)?, delay: word(1)?, position: byte(2)? }, None),
+    "DRAWPOLYS" => (Instruction::DrawPolyShort { offset: word(0)?, x: byte(1)?, y: byte(2)? }, None),
+    _ => {
+      let buffer = if mnemonic == "DRAWPOLY2" { 2 } else { 1 };
+      let zoom = parse_value(operands[3], constants)?;
+
+      if buffer == 2 && zoom != Value::Const(DEFAULT_ZOOM) {
+        return Err(format!("DRAWPOLY2 only supports the default zoom ({:02X})", DEFAULT_ZOOM));
+      }
+
+      let (x, y, long_form) = (parse_value(operands[1], constants)?, parse_value(operands[2], constants)?, mnemonic == "DRAWPOLYL");
+
+      (Instruction::DrawPoly { buffer, offset: word(0)?, x, y, zoom, long_form }, None)
+    }
+  };
+
+  Ok(result)
+}
+
+fn with_addr(instruction: Instruction, new_addr: u16) -> Instruction {
+  match instruction {
+    Instruction::Call { .. } => Instruction::Call { addr: new_addr },
+    Instruction::Jmp { .. } => Instruction::Jmp { addr: new_addr },
+    Instruction::SetVec { thread_id, .. } => Instruction::SetVec { thread_id, addr: new_addr },
+    Instruction::Jnz { reg, .. } => Instruction::Jnz { reg, addr: new_addr },
+    Instruction::CondJmp { condition, reg, operand, .. } => Instruction::CondJmp { condition, reg, operand, addr: new_addr },
+    _ => instruction
+  }
+}
+
+fn resolve(addr: &Addr, labels: &HashMap<String, u16>) -> Result<u16, String> {
+  match addr {
+    Addr::Value(value) => Ok(*value),
+    Addr::Label(label) => labels.get(label).copied().ok_or_else(|| format!("unknown label '{}'", label))
+  }
+}
+
+fn split_operands(line: &str) -> (&str, Vec<&str>) {
+  match line.find(char::is_whitespace) {
+    Some(idx) => {
+      let operands = line[idx..].split(',').map(|o| o.trim()).collect();
+      (&line[..idx], operands)
+    },
+    None => (line, Vec::new())
+  }
+}
+
+fn expect_operands(operands: &[&str], num: usize) -> Result<(), String> {
+  if operands.len() != num || operands.iter().any(|o| o.is_empty()) {
+    return Err(format!("expected {} operands, found {}", num, operands.len()));
+  }
+
+  Ok(())
+}
+
+// a name that can be used as a label in the source
+pub fn is_valid_label(text: &str) -> bool {
+  is_identifier(text) && parse_hex(text).is_none()
+}
+
+fn is_identifier(text: &str) -> bool {
+  let mut chars = text.chars();
+
+  match chars.next() {
+    Some(c) if c.is_ascii_alphabetic() || c == '_' => chars.all(|c| c.is_ascii_alphanumeric() || c == '_'),
+    _ => false
+  }
+}
+
+fn parse_hex(text: &str) -> Option<Number> {
+  let (negative, unsigned) = match text.strip_prefix('-') {
+    Some(rest) => (true, rest),
+    None => (false, text)
+  };
+
+  let digits = unsigned.strip_prefix("0x").or_else(|| unsigned.strip_prefix("0X")).unwrap_or(unsigned);
+
+  if digits.is_empty() || digits.len() > 8 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+    return None;
+  }
+
+  let value = i64::from_str_radix(digits, 16).ok()?;
+
+  Some(Number {
+    value: (if negative { -value } else { value }) as i32,
+    wide: digits.len() >= 4
+  })
+}
+
+fn parse_number(text: &str, constants: &HashMap<String, i32>) -> Result<Number, String> {
+  if let Some(value) = constants.get(text) {
+    return Ok(Number { value: *value, wide: false });
+  }
+
+  parse_hex(text).ok_or_else(|| format!("'{}' is not a valid number", text))
+}
+
+fn parse_u8(text: &str, constants: &HashMap<String, i32>) -> Result<u8, String> {
+  let value = parse_number(text, constants)?.value;
+
+  if !(0..=0xff).contains(&value) {
+    return Err(format!("'{}' doesn't fit in a byte", text));
+  }
+
+  Ok(value as u8)
+}
+
+fn parse_u16(text: &str, constants: &HashMap<String, i32>) -> Result<u16, String> {
+  let value = parse_number(text, constants)?.value;
+
+  if !(-0x8000..=0xffff).contains(&value) {
+    return Err(format!("'{}' doesn't fit in a word", text));
+  }
+
+  Ok(value as u16)
+}
+
+fn parse_addr(text: &str, constants: &HashMap<String, i32>) -> Result<Addr, String> {
+  if constants.contains_key(text) || parse_hex(text).is_some() {
+    return Ok(Addr::Value(parse_u16(text, constants)?));
+  }
+
+  if !is_identifier(text) {
+    return Err(format!("'{}' is not a valid address", text));
+  }
+
+  Ok(Addr::Label(text.to_string()))
+}
+
+fn parse_register(text: &str) -> Option<u8> {
+  let lower = text.to_ascii_lowercase();
+  let id = lower.strip_prefix("r[")?.strip_suffix(']')?;
+
+  if id.is_empty() || id.len() > 2 {
+    return None;
+  }
+
+  u8::from_str_radix(id, 16).ok()
+}
+
+fn parse_value(text: &str, constants: &HashMap<String, i32>) -> Result<Value, String> {
+  if let Some(reg) = parse_register(text) {
+    return Ok(Value::Register(reg));
+  }
+
+  let number = parse_number(text, constants)?;
+
+  if !(-0x8000..=0xffff).contains(&number.value) {
+    return Err(format!("'{}' doesn't fit in a word", text));
+  }
+
+  if number.wide {
+    Ok(Value::Word(number.value as u16 as i16))
+  } else {
+    Ok(Value::Const(number.value as u16 as i16))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::instruction::tests::FORMS;
+  use crate::resources_manager::{ResourcesManager, ResourceType};
+
+  fn build_script(forms: &[&[u8]]) -> Vec<u8> {
+    forms.iter().flat_map(|bytes| bytes.iter().copied()).collect()
+  }
+
+  #[test]
+  fn disassembled_text_assembles_to_the_same_bytes() {
+    let opcodes = Opcodes::new();
+
+    for bytes in FORMS {
+      let asm_code = (opcodes.get(bytes[0]).get_asm_code)(1, bytes);
+      assert_eq!(assemble_instruction(&asm_code).as_deref(), Ok(*bytes), "{}", asm_code);
+    }
+  }
+
+  #[test]
+  fn built_source_assembles_to_the_same_bytes() {
+    let script = build_script(FORMS);
+    let source = build_source(&script, &Opcodes::new());
+
+    assert!(!source.contains(".db"), "{}", source);
+    assert_eq!(assemble(&source).unwrap(), script);
+  }
+
+  #[test]
+  fn undecodable_bytes_are_kept() {
+    let script = [0x1b, 0x06, 0x1c];
+    let source = build_source(&script, &Opcodes::new());
+
+    assert_eq!(source.matches(".db").count(), 2);
+    assert_eq!(assemble(&source).unwrap(), script);
+  }
+
+  // every opcode, with labels, constants and the directives
+  const SOURCE: &str = "
+    .equ THREAD, 3F
+    .equ COUNT, 0005
+  start:
+    MOV r[10], COUNT
+    MOV r[11], r[10]
+    ADD r[11], r[10]
+    ADD r[11], FFFF
+    SUB r[11], r[10]
+    AND r[11], 00FF
+    OR r[11], 0100
+    SHL r[11], 0001
+    SHR r[11], 0002
+    SETVEC THREAD, thread
+  loop:
+    CALL draw
+    CJZ r[11], 00, skip
+    CJNZ r[11], r[10], skip
+    CJG r[11], 1234, skip
+    CJGE r[11], 01, skip
+    CJL r[11], 02, skip
+    CJLE r[11], 03, skip
+  skip:
+    BLIT FF
+    YIELD
+    JNZ r[10], loop
+    RESET 00, THREAD, KILL
+    JMP end
+  draw:                         ; a function
+    SETPAL 0001
+    SETVIDPAG 00
+    FILLVIDPAG 00, 02
+    CPVIDPAG 00, 01
+    DRAWSTR 0001, 02, 03, 04
+    DRAWPOLYS 0040, 10, 20
+    DRAWPOLY1 0200, r[10], 20, r[11]
+    DRAWPOLY2 0200, 110, 0020, 40
+    RET
+  thread:
+    SND 0010, 20, 3F, 00
+    MUSIC 0011, 0000, 00
+    LDRES 0012
+    KILL
+  table:
+    .dw loop, draw, thread
+    .db 01, 02
+    .org 0098
+  end: KILL
+  ";
+
+  const BYTECODE: &[u8] = &[
+    0x00, 0x10, 0x00, 0x05, // 0000
+    0x01, 0x11, 0x10,
+    0x02, 0x11, 0x10,
+    0x03, 0x11, 0xff, 0xff,
+    0x13, 0x11, 0x10,
+    0x14, 0x11, 0x00, 0xff,
+    0x15, 0x11, 0x01, 0x00,
+    0x16, 0x11, 0x00, 0x01,
+    0x17, 0x11, 0x00, 0x02,
+    0x08, 0x3f, 0x00, 0x7d,
+    0x04, 0x00, 0x5b, // 0025 loop
+    0x0a, 0x00, 0x11, 0x00, 0x00, 0x4d,
+    0x0a, 0x81, 0x11, 0x10, 0x00, 0x4d,
+    0x0a, 0x42, 0x11, 0x12, 0x34, 0x00, 0x4d,
+    0x0a, 0x03, 0x11, 0x01, 0x00, 0x4d,
+    0x0a, 0x04, 0x11, 0x02, 0x00, 0x4d,
+    0x0a, 0x05, 0x11, 0x03, 0x00, 0x4d,
+    0x10, 0xff, // 004D skip
+    0x06,
+    0x09, 0x10, 0x00, 0x25,
+    0x0c, 0x00, 0x3f, 0x02,
+    0x07, 0x00, 0x98,
+    0x0b, 0x00, 0x01, // 005B draw
+    0x0d, 0x00,
+    0x0e, 0x00, 0x02,
+    0x0f, 0x00, 0x01,
+    0x12, 0x00, 0x01, 0x02, 0x03, 0x04,
+    0x80, 0x20, 0x10, 0x20,
+    0x59, 0x01, 0x00, 0x10, 0x20, 0x11,
+    0x73, 0x01, 0x00, 0x10, 0x00, 0x20,
+    0x05,
+    0x18, 0x00, 0x10, 0x20, 0x3f, 0x00, // 007D thread
+    0x1a, 0x00, 0x11, 0x00, 0x00, 0x00,
+    0x19, 0x00, 0x12,
+    0x11,
+    0x00, 0x25, 0x00, 0x5b, 0x00, 0x7d, // 008D table
+    0x01, 0x02,
+    0x00, 0x00, 0x00,
+    0x11 // 0098 end
+  ];
+
+  #[test]
+  fn script_with_labels_and_directives_assembles() {
+    assert_eq!(assemble(SOURCE).unwrap(), BYTECODE);
+
+    let source = build_source(BYTECODE, &Opcodes::new());
+    assert_eq!(assemble(&source).unwrap(), BYTECODE, "{}", source);
+  }
+
+  // AW_GAME_DATA is a directory with memlist.bin and the banks of the game. Every script has to be reassembled from
+  // the disassembled text, without .db
+  #[test]
+  #[ignore = "needs the game data in AW_GAME_DATA"]
+  fn game_scripts_assemble_to_the_same_bytes() {
+    let dir = std::path::PathBuf::from(std::env::var("AW_GAME_DATA").expect("AW_GAME_DATA is not set"));
+
+    let mut game_data = Vec::new();
+    let filenames = std::iter::once("memlist.bin".to_string()).chain((1..14).map(|i| format!("bank0{:x}", i)));
+
+    for filename in filenames {
+      let content = std::fs::read(dir.join(&filename)).unwrap_or_else(|e| panic!("can't read {}: {}", filename, e));
+      game_data.extend_from_slice(&(content.len() as u32).to_le_bytes());
+      game_data.extend_from_slice(&content);
+    }
+
+    let mut resources_manager = ResourcesManager::new();
+    let opcodes = Opcodes::new();
+    resources_manager.init(&game_data);
+
+    for (file_id, file) in resources_manager.files.iter().enumerate() {
+      if file.ftype != ResourceType::Script as u8 || file.content.is_empty() {
+        continue;
+      }
+
+      let source = build_source(&file.content, &opcodes);
+
+      assert!(!source.contains(".db"), "the script {:02X} has bytes that are not reassembled", file_id);
+      assert_eq!(assemble(&source).unwrap(), file.content, "the script {:02X}", file_id);
+    }
+  }
+}
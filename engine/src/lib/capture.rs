@@ -0,0 +1,215 @@
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::Path;
+use crate::color::NUM_PALETTE_COLORS;
+use crate::defines::{FRAME_BUFFER_WIDTH, FRAME_BUFFER_HEIGHT};
+
+// Capture of the frames shown by the game (a frame is captured in every BLIT), to make clips of the game. The frames
+// are kept with their palette and the pause of the BLIT (the time the frame is shown), and they are saved when the
+// capture finishes as numbered pngs, an animated gif or an apng.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CaptureFormat {
+  PngSequence, // path is a directory, the files are frame_00000.png, frame_00001.png...
+  Gif,
+  Apng
+}
+
+impl CaptureFormat {
+  // by the extension of the path: .gif, .png or .apng (an animated png), and a directory for the png sequence
+  pub fn from_path(path: &str) -> CaptureFormat {
+    match Path::new(path).extension().and_then(|ext| ext.to_str()).map(|ext| ext.to_ascii_lowercase()) {
+      Some(ext) if ext == "gif" => CaptureFormat::Gif,
+      Some(ext) if ext == "png" || ext == "apng" => CaptureFormat::Apng,
+      _ => CaptureFormat::PngSequence
+    }
+  }
+}
+
+// ms of the pause of a BLIT, that waits PauseSlices slices of 20ms
+pub fn pause_to_duration(pause_slices: i16) -> u16 {
+  (pause_slices as u16).wrapping_mul(20)
+}
+
+// the delays of the gif frames are in 1/100 s
+fn gif_delay(duration: u16) -> u16 {
+  ((duration as u32 + 5) / 10) as u16
+}
+
+pub struct CapturedFrame {
+  pub pixels: Vec<u8>, // color indexes
+  pub colors: [[u8; 3]; NUM_PALETTE_COLORS],
+  pub duration: u16 // ms
+}
+
+pub struct FrameCapture {
+  pub frames: Vec<CapturedFrame>,
+  first_frame: u32,
+  last_frame: Option<u32>, // included
+  num_blits: u32
+}
+
+impl FrameCapture {
+  // the frames are numbered from 0, the first blit after the capture starts
+  pub fn new(first_frame: u32, last_frame: Option<u32>) -> FrameCapture {
+    FrameCapture {
+      frames: Vec::new(),
+      first_frame,
+      last_frame,
+      num_blits: 0
+    }
+  }
+
+  pub fn add_frame(&mut self, pixels: &[u8], colors: &[[u8; 3]; NUM_PALETTE_COLORS], duration: u16) {
+    let frame = self.num_blits;
+    self.num_blits += 1;
+
+    if frame >= self.first_frame && self.last_frame.is_none_or(|last| frame <= last) {
+      self.frames.push(CapturedFrame { pixels: pixels.to_vec(), colors: *colors, duration });
+    }
+  }
+
+  // true when the last frame of the range has been captured
+  pub fn is_finished(&self) -> bool {
+    self.last_frame.is_some_and(|last| self.num_blits > last)
+  }
+
+  pub fn save(&self, path: &str, format: CaptureFormat) -> Result<(), String> {
+    if self.frames.is_empty() {
+      return Err("no frames captured".to_string());
+    }
+
+    match format {
+      CaptureFormat::PngSequence => self.save_png_sequence(path),
+      CaptureFormat::Gif => self.save_gif(path),
+      CaptureFormat::Apng => self.save_apng(path)
+    }
+  }
+
+  fn save_png_sequence(&self, path: &str) -> Result<(), String> {
+    fs::create_dir_all(path).map_err(|e| format!("can't create {}: {}", path, e))?;
+
+    for (idx, frame) in self.frames.iter().enumerate() {
+      let file_path = Path::new(path).join(format!("frame_{:05}.png", idx));
+      let file = File::create(&file_path).map_err(|e| format!("can't create {}: {}", file_path.display(), e))?;
+      let mut encoder = png::Encoder::new(BufWriter::new(file), FRAME_BUFFER_WIDTH as u32, FRAME_BUFFER_HEIGHT as u32);
+
+      encoder.set_color(png::ColorType::Indexed);
+      encoder.set_depth(png::BitDepth::Eight);
+      encoder.set_palette(frame.colors.concat());
+
+      let mut writer = encoder.write_header().map_err(|e| e.to_string())?;
+      writer.write_image_data(&frame.pixels).map_err(|e| e.to_string())?;
+    }
+
+    Ok(())
+  }
+
+  // every frame has its palette
+  fn save_gif(&self, path: &str) -> Result<(), String> {
+    let file = File::create(path).map_err(|e| format!("can't create {}: {}", path, e))?;
+    let mut encoder = gif::Encoder::new(BufWriter::new(file), FRAME_BUFFER_WIDTH, FRAME_BUFFER_HEIGHT, &[]).map_err(|e| e.to_string())?;
+
+    encoder.set_repeat(gif::Repeat::Infinite).map_err(|e| e.to_string())?;
+
+    for frame in &self.frames {
+      let mut gif_frame = gif::Frame::from_palette_pixels(FRAME_BUFFER_WIDTH, FRAME_BUFFER_HEIGHT, frame.pixels.as_slice(), frame.colors.concat(), None);
+      gif_frame.delay = gif_delay(frame.duration);
+
+      encoder.write_frame(&gif_frame).map_err(|e| e.to_string())?;
+    }
+
+    Ok(())
+  }
+
+  // the palette of an apng is the same for all the frames, so the frames are saved in rgb
+  fn save_apng(&self, path: &str) -> Result<(), String> {
+    let file = File::create(path).map_err(|e| format!("can't create {}: {}", path, e))?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), FRAME_BUFFER_WIDTH as u32, FRAME_BUFFER_HEIGHT as u32);
+
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_animated(self.frames.len() as u32, 0).map_err(|e| e.to_string())?;
+
+    let mut writer = encoder.write_header().map_err(|e| e.to_string())?;
+
+    for frame in &self.frames {
+      let rgb: Vec<u8> = frame.pixels.iter().flat_map(|color_idx| frame.colors[*color_idx as usize & 0xf]).collect();
+
+      writer.set_frame_delay(frame.duration, 1000).map_err(|e| e.to_string())?;
+      writer.write_image_data(&rgb).map_err(|e| e.to_string())?;
+    }
+
+    writer.finish().map_err(|e| e.to_string())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const COLORS: [[u8; 3]; NUM_PALETTE_COLORS] = [[0; 3]; NUM_PALETTE_COLORS];
+
+  // adds num_blits frames with their number in the pixels
+  fn add_frames(capture: &mut FrameCapture, num_blits: u32) {
+    for _ in 0..num_blits {
+      let frame = capture.num_blits as u8;
+      capture.add_frame(&[frame; 4], &COLORS, 20);
+    }
+  }
+
+  #[test]
+  fn frames_of_the_range_are_captured() {
+    let mut capture = FrameCapture::new(2, Some(4));
+
+    add_frames(&mut capture, 4);
+    assert!(!capture.is_finished());
+
+    add_frames(&mut capture, 3);
+    assert!(capture.is_finished());
+
+    let frames: Vec<u8> = capture.frames.iter().map(|frame| frame.pixels[0]).collect();
+    assert_eq!(frames, [2, 3, 4]);
+  }
+
+  #[test]
+  fn capture_without_last_frame_never_finishes() {
+    let mut capture = FrameCapture::new(0, None);
+
+    add_frames(&mut capture, 100);
+
+    assert!(!capture.is_finished());
+    assert_eq!(capture.frames.len(), 100);
+  }
+
+  #[test]
+  fn pause_slices_are_20ms() {
+    assert_eq!(pause_to_duration(0), 0);
+    assert_eq!(pause_to_duration(5), 100);
+    assert_eq!(pause_to_duration(-1), 0xffff_u16.wrapping_mul(20));
+  }
+
+  #[test]
+  fn gif_delays_are_rounded() {
+    assert_eq!(gif_delay(0), 0);
+    assert_eq!(gif_delay(20), 2);
+    assert_eq!(gif_delay(44), 4);
+    assert_eq!(gif_delay(45), 5);
+    assert_eq!(gif_delay(0xffff), 6554);
+  }
+
+  #[test]
+  fn formats_are_chosen_by_the_extension() {
+    assert_eq!(CaptureFormat::from_path("clip.gif"), CaptureFormat::Gif);
+    assert_eq!(CaptureFormat::from_path("clip.GIF"), CaptureFormat::Gif);
+    assert_eq!(CaptureFormat::from_path("clip.png"), CaptureFormat::Apng);
+    assert_eq!(CaptureFormat::from_path("clip.apng"), CaptureFormat::Apng);
+    assert_eq!(CaptureFormat::from_path("frames"), CaptureFormat::PngSequence);
+    assert_eq!(CaptureFormat::from_path("frames/"), CaptureFormat::PngSequence);
+  }
+
+  #[test]
+  fn empty_captures_are_not_saved() {
+    assert!(FrameCapture::new(0, None).save("clip.gif", CaptureFormat::Gif).is_err());
+  }
+}
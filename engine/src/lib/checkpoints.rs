@@ -0,0 +1,241 @@
+use std::collections::BTreeMap;
+use std::fs;
+use serde::{Serialize, Deserialize};
+use crate::defines::*;
+
+// The game parts, and the checkpoints where the game can be restarted: the part to load and the values of the
+// registers. The default checkpoints are the ones of the game (the protection screen, the intro, the 15 levels, the
+// final part and the password screen), and custom checkpoints can be loaded from a json file like this one (the
+// registers are in hex):
+// [ { "name": "before the pool", "part": "City", "code": "ABCD", "registers": { "00": 33, "F2": 4000 } } ]
+// The levels have the codes the player types in the password screen of the game to start in a level, and a custom
+// checkpoint can have a code too.
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum GamePart {
+  Protection,
+  Intro,
+  Water,
+  Jail,
+  City,
+  Arena,
+  Luxe,
+  Final,
+  Password
+}
+
+pub const GAME_PARTS: [GamePart; 9] = [
+  GamePart::Protection, GamePart::Intro, GamePart::Water, GamePart::Jail, GamePart::City, GamePart::Arena, GamePart::Luxe,
+  GamePart::Final, GamePart::Password
+];
+
+impl GamePart {
+  pub fn from_index(index: u8) -> Option<GamePart> {
+    GAME_PARTS.get(index as usize).copied()
+  }
+
+  // the part that runs the script
+  pub fn from_script_file_id(script_file_id: u8) -> Option<GamePart> {
+    PARTS_FILE_IDS.iter().position(|ids| ids[1] == script_file_id).map(|index| GAME_PARTS[index])
+  }
+
+  pub fn index(self) -> u8 {
+    self as u8
+  }
+
+  // the resource id used by LDRES to load the part
+  pub fn resource_id(self) -> u16 {
+    BASE_PART_ID + self as u16
+  }
+
+  // [palette, script, polys 1, polys 2]
+  pub fn file_ids(self) -> [u8; 4] {
+    PARTS_FILE_IDS[self as usize]
+  }
+
+  pub fn name(self) -> &'static str {
+    match self {
+      GamePart::Protection => "Protection screen",
+      GamePart::Intro => "Intro",
+      GamePart::Water => "Water",
+      GamePart::Jail => "Jail",
+      GamePart::City => "City",
+      GamePart::Arena => "Arena",
+      GamePart::Luxe => "Luxe",
+      GamePart::Final => "Final",
+      GamePart::Password => "Password screen"
+    }
+  }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Checkpoint {
+  pub name: String,
+  pub part: GamePart,
+  pub code: Option<String>,
+  pub registers: Vec<(u8, i16)>
+}
+
+const LAST_LEVEL: u8 = 15;
+
+// (name, part, code, registers). The codes are the ones the player types in the password screen of the game (they
+// are also in the game strings, from 0x15e)
+type DefaultCheckpoint = (&'static str, GamePart, Option<&'static str>, &'static [[i16; 2]]);
+
+const DEFAULT_CHECKPOINTS: [DefaultCheckpoint; 19] = [
+  ("Protection screen", GamePart::Protection, None, &[]),
+  ("Intro", GamePart::Intro, None, &LEVEL_00_INITIAL_REGISTERS_VALUES),
+  ("Level 1 (Arrival)", GamePart::Water, Some("LDKD"), &LEVEL_01_INITIAL_REGISTERS_VALUES),
+  ("Level 2 (Jail)", GamePart::Jail, Some("HTDC"), &LEVEL_02_INITIAL_REGISTERS_VALUES),
+  ("Level 3 (City)", GamePart::City, Some("CLLD"), &LEVEL_03_INITIAL_REGISTERS_VALUES),
+  ("Level 4 (Caves)", GamePart::City, Some("FXLC"), &LEVEL_04_INITIAL_REGISTERS_VALUES),
+  ("Level 5", GamePart::City, Some("KRFK"), &LEVEL_05_INITIAL_REGISTERS_VALUES),
+  ("Level 6", GamePart::City, Some("XDDJ"), &LEVEL_06_INITIAL_REGISTERS_VALUES),
+  ("Level 7", GamePart::City, Some("LBKG"), &LEVEL_07_INITIAL_REGISTERS_VALUES),
+  ("Level 8", GamePart::City, Some("KLFB"), &LEVEL_08_INITIAL_REGISTERS_VALUES),
+  ("Level 9", GamePart::City, Some("TTCT"), &LEVEL_09_INITIAL_REGISTERS_VALUES),
+  ("Level 10", GamePart::City, Some("DDRX"), &LEVEL_10_INITIAL_REGISTERS_VALUES),
+  ("Level 11", GamePart::City, Some("TBHK"), &LEVEL_11_INITIAL_REGISTERS_VALUES),
+  ("Level 12", GamePart::Luxe, Some("BRTD"), &LEVEL_12_INITIAL_REGISTERS_VALUES),
+  ("Level 13 (Palais)", GamePart::Luxe, Some("CKJL"), &LEVEL_13_INITIAL_REGISTERS_VALUES),
+  ("Level 14 (Arena)", GamePart::Arena, Some("LFCK"), &LEVEL_14_INITIAL_REGISTERS_VALUES),
+  ("Level 15 (Baths)", GamePart::Luxe, Some("BFLX"), &LEVEL_15_INITIAL_REGISTERS_VALUES),
+  ("Final", GamePart::Final, None, &[]),
+  ("Password screen", GamePart::Password, None, &[])
+];
+
+// layout of the json file
+#[derive(Serialize, Deserialize)]
+struct CheckpointFile {
+  name: String,
+  part: GamePart,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  code: Option<String>,
+  #[serde(default)]
+  registers: BTreeMap<String, i16>
+}
+
+pub struct CheckpointTable {
+  pub checkpoints: Vec<Checkpoint>,
+  num_default: usize
+}
+
+impl Default for CheckpointTable {
+  fn default() -> CheckpointTable {
+    CheckpointTable::new()
+  }
+}
+
+impl CheckpointTable {
+  pub fn new() -> CheckpointTable {
+    let checkpoints: Vec<Checkpoint> = DEFAULT_CHECKPOINTS.iter().map(|(name, part, code, registers)| Checkpoint {
+      name: name.to_string(),
+      part: *part,
+      code: code.map(|code| code.to_string()),
+      registers: registers.iter().map(|reg| (reg[0] as u8, reg[1])).collect()
+    }).collect();
+
+    CheckpointTable {
+      num_default: checkpoints.len(),
+      checkpoints
+    }
+  }
+
+  // the game levels are numbered from 0 (the intro), and 0xff is the protection screen. The levels after the last one
+  // restart the protection screen too, like the game does
+  pub fn get_level(&self, level: u8) -> &Checkpoint {
+    match level {
+      0..=LAST_LEVEL => &self.checkpoints[level as usize + 1],
+      _ => &self.checkpoints[0]
+    }
+  }
+
+  // the codes aren't case sensitive
+  pub fn find_code(&self, code: &str) -> Option<usize> {
+    let code = code.trim().to_ascii_uppercase();
+    self.checkpoints.iter().position(|checkpoint| checkpoint.code.as_deref() == Some(code.as_str()))
+  }
+
+  // (code, index of the checkpoint) of the checkpoints with a code
+  pub fn get_codes(&self) -> Vec<(&str, usize)> {
+    self.checkpoints.iter().enumerate().filter_map(|(idx, checkpoint)| checkpoint.code.as_deref().map(|code| (code, idx))).collect()
+  }
+
+  pub fn get_custom(&self) -> &[Checkpoint] {
+    &self.checkpoints[self.num_default..]
+  }
+
+  // returns the index of the checkpoint in the table
+  pub fn add(&mut self, mut checkpoint: Checkpoint) -> Result<usize, String> {
+    // the names are sent to javascript with a byte for their length
+    if checkpoint.name.is_empty() || checkpoint.name.len() > 0xff {
+      return Err(format!("invalid checkpoint name '{}'", checkpoint.name));
+    }
+
+    if self.checkpoints.iter().any(|c| c.name == checkpoint.name) {
+      return Err(format!("the checkpoint '{}' already exists", checkpoint.name));
+    }
+
+    if let Some(code) = &checkpoint.code {
+      let code = code.to_ascii_uppercase();
+
+      if code.is_empty() || code.len() > 0xff || !code.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return Err(format!("invalid code '{}' for the checkpoint '{}'", code, checkpoint.name));
+      }
+
+      if self.find_code(&code).is_some() {
+        return Err(format!("the code '{}' is already used", code));
+      }
+
+      checkpoint.code = Some(code);
+    }
+
+    self.checkpoints.push(checkpoint);
+
+    Ok(self.checkpoints.len() - 1)
+  }
+
+  pub fn remove_custom(&mut self) {
+    self.checkpoints.truncate(self.num_default);
+  }
+
+  pub fn load_file(&mut self, path: &str) -> Result<(), String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("can't read {}: {}", path, e))?;
+    self.load_json(&text)
+  }
+
+  // adds the checkpoints of the file to the table. If a checkpoint is invalid, none is added
+  pub fn load_json(&mut self, text: &str) -> Result<(), String> {
+    let files: Vec<CheckpointFile> = serde_json::from_str(text).map_err(|e| e.to_string())?;
+    let num_checkpoints = self.checkpoints.len();
+
+    for file in files {
+      let result = parse_registers(&file.registers).and_then(|registers| self.add(Checkpoint { name: file.name, part: file.part, code: file.code, registers }));
+
+      if let Err(message) = result {
+        self.checkpoints.truncate(num_checkpoints);
+        return Err(message);
+      }
+    }
+
+    Ok(())
+  }
+
+  // only the custom checkpoints are saved
+  pub fn to_json(&self) -> String {
+    let files: Vec<CheckpointFile> = self.get_custom().iter().map(|checkpoint| CheckpointFile {
+      name: checkpoint.name.clone(),
+      part: checkpoint.part,
+      code: checkpoint.code.clone(),
+      registers: checkpoint.registers.iter().map(|(reg_id, value)| (format!("{:02X}", reg_id), *value)).collect()
+    }).collect();
+
+    serde_json::to_string_pretty(&files).unwrap()
+  }
+}
+
+fn parse_registers(registers: &BTreeMap<String, i16>) -> Result<Vec<(u8, i16)>, String> {
+  registers.iter().map(|(key, value)| {
+    u8::from_str_radix(key, 16).map(|reg_id| (reg_id, *value)).map_err(|_| format!("invalid register '{}'", key))
+  }).collect()
+}
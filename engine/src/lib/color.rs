@@ -0,0 +1,132 @@
+// Colors of the palettes. A palette has 16 colors of 2 bytes (0x0RGB, 4 bits per component), and the components are
+// expanded to 8 bits like the original game does on the Amiga. The ColorTable converts the colors of a palette once,
+// when the palette changes, to all the formats, so the pages are written in the format of the host with a lookup per
+// pixel. The rgba and bgra colors are opaque (alpha 0xff, the frame buffer had alpha 0 before), so the frames can be
+// drawn in a canvas or saved to an image as they are.
+
+pub const NUM_PALETTE_COLORS: usize = 16;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PixelFormat {
+  Rgba8888,
+  Bgra8888,
+  Rgb565, // little endian
+  Indexed // the color index, the colors are in ColorTable::colors
+}
+
+impl PixelFormat {
+  // the format codes used by the host: 0 rgba, 1 bgra, 2 rgb565, 3 indexed
+  pub fn from_code(code: u8) -> Option<PixelFormat> {
+    match code {
+      0 => Some(PixelFormat::Rgba8888),
+      1 => Some(PixelFormat::Bgra8888),
+      2 => Some(PixelFormat::Rgb565),
+      3 => Some(PixelFormat::Indexed),
+      _ => None
+    }
+  }
+
+  pub fn bytes_per_pixel(self) -> usize {
+    match self {
+      PixelFormat::Rgba8888 | PixelFormat::Bgra8888 => 4,
+      PixelFormat::Rgb565 => 2,
+      PixelFormat::Indexed => 1
+    }
+  }
+}
+
+// c1 and c2 are the 2 bytes of the color in the palette
+pub fn palette_color_to_rgb(c1: u8, c2: u8) -> [u8; 3] {
+  [
+    (((c1 & 0x0f) << 2) | ((c1 & 0x0f) >> 2)) << 2,
+    (((c2 & 0xf0) >> 2) | ((c2 & 0xf0) >> 6)) << 2,
+    (((c2 & 0x0f) >> 2) | ((c2 & 0x0f) << 2)) << 2
+  ]
+}
+
+pub struct ColorTable {
+  palette: [u8; NUM_PALETTE_COLORS * 2],
+  pub colors: [[u8; 3]; NUM_PALETTE_COLORS],
+  rgba: [[u8; 4]; NUM_PALETTE_COLORS],
+  bgra: [[u8; 4]; NUM_PALETTE_COLORS],
+  rgb565: [[u8; 2]; NUM_PALETTE_COLORS]
+}
+
+impl Default for ColorTable {
+  fn default() -> ColorTable {
+    ColorTable::new()
+  }
+}
+
+impl ColorTable {
+  pub fn new() -> ColorTable {
+    // the table of a palette with all the colors black
+    ColorTable {
+      palette: [0; NUM_PALETTE_COLORS * 2],
+      colors: [[0; 3]; NUM_PALETTE_COLORS],
+      rgba: [[0, 0, 0, 0xff]; NUM_PALETTE_COLORS],
+      bgra: [[0, 0, 0, 0xff]; NUM_PALETTE_COLORS],
+      rgb565: [[0; 2]; NUM_PALETTE_COLORS]
+    }
+  }
+
+  // palette: the 32 bytes of the palette. The colors are only converted if they have changed
+  pub fn set_palette(&mut self, palette: &[u8]) {
+    if self.palette[..] == palette[..NUM_PALETTE_COLORS * 2] {
+      return;
+    }
+
+    self.palette.copy_from_slice(&palette[..NUM_PALETTE_COLORS * 2]);
+
+    for i in 0..NUM_PALETTE_COLORS {
+      let [r, g, b] = palette_color_to_rgb(palette[i * 2], palette[i * 2 + 1]);
+      let rgb565 = ((r as u16 >> 3) << 11) | ((g as u16 >> 2) << 5) | (b as u16 >> 3);
+
+      self.colors[i] = [r, g, b];
+      self.rgba[i] = [r, g, b, 0xff];
+      self.bgra[i] = [b, g, r, 0xff];
+      self.rgb565[i] = rgb565.to_le_bytes();
+    }
+  }
+
+  // writes the rows of width pixels to output, with stride bytes from the start of a row to the next one. The last row
+  // only needs room for its pixels
+  pub fn write_rows(&self, pixels: &[u8], width: usize, format: PixelFormat, output: &mut [u8], stride: usize) -> Result<(), String> {
+    if width == 0 {
+      return Err("the rows have no pixels".to_string());
+    }
+
+    let row_len = width * format.bytes_per_pixel();
+    let height = pixels.len() / width;
+
+    if stride < row_len {
+      return Err(format!("the stride {} is less than a row of {} bytes", stride, row_len));
+    }
+
+    if height > 0 && output.len() < (height - 1) * stride + row_len {
+      return Err(format!("the output of {} bytes is too small for {} rows with the stride {}", output.len(), height, stride));
+    }
+
+    for (y, row) in pixels.chunks_exact(width).enumerate() {
+      self.write_pixels(row, format, &mut output[y * stride..]);
+    }
+
+    Ok(())
+  }
+
+  // writes the pixels (color indexes) to output, that must have room for them in the format
+  pub fn write_pixels(&self, pixels: &[u8], format: PixelFormat, output: &mut [u8]) {
+    match format {
+      PixelFormat::Rgba8888 => write_with_table(pixels, &self.rgba, output),
+      PixelFormat::Bgra8888 => write_with_table(pixels, &self.bgra, output),
+      PixelFormat::Rgb565 => write_with_table(pixels, &self.rgb565, output),
+      PixelFormat::Indexed => output[..pixels.len()].copy_from_slice(pixels)
+    }
+  }
+}
+
+fn write_with_table<const N: usize>(pixels: &[u8], table: &[[u8; N]; NUM_PALETTE_COLORS], output: &mut [u8]) {
+  for (color_idx, out) in pixels.iter().zip(output.chunks_exact_mut(N)) {
+    out.copy_from_slice(&table[*color_idx as usize & 0xf]);
+  }
+}
@@ -0,0 +1,449 @@
+use std::collections::{BTreeMap, BTreeSet};
+use crate::instruction::{Instruction, decode};
+use crate::assembler::{source_instruction, is_valid_label};
+use crate::opcodes::Opcodes;
+use crate::symbols::PartSymbols;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum XrefKind {
+  Jump,
+  ConditionalJump,
+  Call,
+  SetVec
+}
+
+impl XrefKind {
+  pub fn name(self) -> &'static str {
+    match self {
+      XrefKind::Jump => "jump",
+      XrefKind::ConditionalJump => "branch",
+      XrefKind::Call => "call",
+      XrefKind::SetVec => "setvec"
+    }
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Xref {
+  pub from: u16,
+  pub kind: XrefKind
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct BasicBlock {
+  pub start: u16,
+  pub end: u16, // address after the last instruction
+  pub successors: Vec<u16>
+}
+
+// control flow of a script, built following all the paths from the entry point of the thread 0 (the first
+// instruction), the CALL targets (functions) and the SETVEC targets (entry points of the other threads)
+pub struct ControlFlow {
+  pub instructions: BTreeMap<u16, (Instruction, u16)>,
+  pub blocks: BTreeMap<u16, BasicBlock>,
+  pub functions: BTreeSet<u16>,
+  pub thread_entries: BTreeMap<u16, BTreeSet<u8>>,
+  pub xrefs: BTreeMap<u16, Vec<Xref>>,
+  pub labels: BTreeMap<u16, String>,
+  pub comments: BTreeMap<u16, String>
+}
+
+impl ControlFlow {
+  pub fn new(script: &[u8]) -> ControlFlow {
+    let mut control_flow = ControlFlow {
+      instructions: BTreeMap::new(),
+      blocks: BTreeMap::new(),
+      functions: BTreeSet::new(),
+      thread_entries: BTreeMap::new(),
+      xrefs: BTreeMap::new(),
+      labels: BTreeMap::new(),
+      comments: BTreeMap::new()
+    };
+
+    control_flow.thread_entries.entry(0).or_default().insert(0);
+    control_flow.follow_paths(script);
+    control_flow.build_blocks();
+    control_flow.build_labels();
+
+    control_flow
+  }
+
+  // addresses of the blocks reachable from entry without following the calls
+  pub fn reachable_blocks(&self, entry: u16) -> Vec<u16> {
+    let mut visited = BTreeSet::new();
+    let mut pending = vec![entry];
+
+    while let Some(addr) = pending.pop() {
+      if !self.blocks.contains_key(&addr) || !visited.insert(addr) {
+        continue;
+      }
+
+      pending.extend_from_slice(&self.blocks[&addr].successors);
+    }
+
+    visited.into_iter().collect()
+  }
+
+  // replaces the generated labels by the names of the symbols file, and adds its comments. To keep the listing
+  // assembling, the names that aren't valid labels or that are already the label of another address are skipped, and
+  // the comments are kept in a single line
+  pub fn apply_part_symbols(&mut self, symbols: &PartSymbols) {
+    for (addr, label) in &symbols.labels {
+      if is_valid_label(label) && !self.labels.iter().any(|(a, l)| a != addr && l == label) {
+        self.labels.insert(*addr, label.clone());
+      }
+    }
+
+    for (addr, comment) in &symbols.comments {
+      self.comments.insert(*addr, comment.replace(['\r', '\n'], " "));
+    }
+  }
+
+  pub fn get_label(&self, addr: u16) -> Option<&str> {
+    self.labels.get(&addr).map(|label| label.as_str())
+  }
+
+  // listing of the whole script (in the same linear order as the disassembler) with labels, xrefs and the
+  // thread entry points. It can be assembled back to the same bytes
+  pub fn build_listing(&self, script: &[u8], opcodes: &Opcodes) -> String {
+    let boundaries = linear_boundaries(script);
+    let mut listing = String::new();
+    let mut pc: u16 = 0;
+
+    while (pc as usize) < script.len() {
+      if let Some(label) = self.labels.get(&pc) {
+        listing.push('\n');
+
+        if let Some(threads) = self.thread_entries.get(&pc) {
+          let ids: Vec<String> = threads.iter().map(|id| format!("{:02X}", id)).collect();
+          listing.push_str(&format!("; entry point of the threads {}\n", ids.join(", ")));
+        }
+
+        if let Some(xrefs) = self.xrefs.get(&pc) {
+          let refs: Vec<String> = xrefs.iter().map(|x| format!("{:04X} ({})", x.from, x.kind.name())).collect();
+          listing.push_str(&format!("; xrefs: {}\n", refs.join(", ")));
+        }
+
+        listing.push_str(&format!("{}:\n", label));
+      } else if self.blocks.contains_key(&pc) {
+        listing.push('\n');
+      }
+
+      let (text, mut comment, len) = self.instruction_text(script, pc, opcodes, &boundaries);
+
+      if let Some(user_comment) = self.comments.get(&pc) {
+        comment = Some(match comment {
+          Some(comment) => format!("{} - {}", comment, user_comment),
+          None => user_comment.clone()
+        });
+      }
+
+      match comment {
+        Some(comment) => listing.push_str(&format!("  {:<40}; {:04X} {}\n", text, pc, comment)),
+        None => listing.push_str(&format!("  {:<40}; {:04X}\n", text, pc))
+      }
+
+      pc = match pc.checked_add(len) {
+        Some(next) => next,
+        None => break
+      };
+    }
+
+    listing
+  }
+
+  // graphviz graph with a node per basic block. Calls are drawn as dashed edges and SETVECs as dotted edges
+  pub fn build_dot(&self, script: &[u8], opcodes: &Opcodes) -> String {
+    let boundaries = linear_boundaries(script);
+    let mut dot = String::from("digraph script {\n  node [shape=box, fontname=\"monospace\"];\n");
+
+    for block in self.blocks.values() {
+      let mut text = String::new();
+
+      if let Some(label) = self.labels.get(&block.start) {
+        text.push_str(&format!("{}:\\l", label));
+      }
+
+      let mut pc = block.start;
+
+      while pc < block.end {
+        let (asm_code, comment, len) = self.instruction_text(script, pc, opcodes, &boundaries);
+        text.push_str(&format!("{:04X}  {}\\l", pc, escape_dot(&comment.unwrap_or(asm_code))));
+        pc += len;
+      }
+
+      dot.push_str(&format!("  b{:04X} [label=\"{}\"];\n", block.start, text));
+
+      for successor in &block.successors {
+        dot.push_str(&format!("  b{:04X} -> b{:04X};\n", block.start, successor));
+      }
+    }
+
+    for (addr, xrefs) in &self.xrefs {
+      for xref in xrefs {
+        let style = match xref.kind {
+          XrefKind::Call => "dashed",
+          XrefKind::SetVec => "dotted",
+          _ => continue
+        };
+
+        if let Some(from) = self.block_containing(xref.from) {
+          dot.push_str(&format!("  b{:04X} -> b{:04X} [style={}];\n", from, addr, style));
+        }
+      }
+    }
+
+    dot.push_str("}\n");
+    dot
+  }
+
+  fn block_containing(&self, addr: u16) -> Option<u16> {
+    self.blocks.range(..=addr).next_back().filter(|(_, block)| addr < block.end).map(|(start, _)| *start)
+  }
+
+  // text of an instruction with the target address replaced by its label, when the label is in the listing
+  fn instruction_text(&self, script: &[u8], pc: u16, opcodes: &Opcodes, boundaries: &BTreeSet<u16>) -> (String, Option<String>, u16) {
+    let (text, comment, len) = source_instruction(script, pc, opcodes);
+
+    if comment.is_some() {
+      return (text, comment, len);
+    }
+
+    let target = match decode(script, pc) {
+      Some((Instruction::SetVec { addr, .. }, _)) => Some(addr),
+      Some((instruction, _)) => instruction.branch_target(),
+      None => None
+    };
+
+    if let Some(label) = target.filter(|addr| boundaries.contains(addr)).and_then(|addr| self.labels.get(&addr)) {
+      if let Some(idx) = text.rfind(' ') {
+        return (format!("{}{}", &text[..idx + 1], label), None, len);
+      }
+    }
+
+    (text, None, len)
+  }
+
+  fn follow_paths(&mut self, script: &[u8]) {
+    let mut pending = vec![0u16];
+
+    while let Some(pc) = pending.pop() {
+      if self.instructions.contains_key(&pc) {
+        continue;
+      }
+
+      let (instruction, len) = match decode(script, pc) {
+        Some(decoded) => decoded,
+        None => continue
+      };
+
+      self.instructions.insert(pc, (instruction, len));
+
+      let next = pc as usize + len as usize;
+      let mut fall_through = next < script.len();
+
+      match instruction {
+        Instruction::Jmp { addr } => {
+          self.add_xref(addr, pc, XrefKind::Jump);
+          pending.push(addr);
+          fall_through = false;
+        },
+        Instruction::Jnz { addr, .. } | Instruction::CondJmp { addr, .. } => {
+          self.add_xref(addr, pc, XrefKind::ConditionalJump);
+          pending.push(addr);
+        },
+        Instruction::Call { addr } => {
+          self.add_xref(addr, pc, XrefKind::Call);
+          self.functions.insert(addr);
+          pending.push(addr);
+        },
+        Instruction::SetVec { thread_id, addr } => {
+          self.add_xref(addr, pc, XrefKind::SetVec);
+          self.thread_entries.entry(addr).or_default().insert(thread_id);
+          pending.push(addr);
+        },
+        Instruction::Ret | Instruction::Kill => fall_through = false,
+        _ => {}
+      }
+
+      if fall_through {
+        pending.push(next as u16);
+      }
+    }
+
+    // targets outside the script can't be followed
+    let script_len = script.len();
+    self.functions.retain(|addr| (*addr as usize) < script_len);
+    self.thread_entries.retain(|addr, _| (*addr as usize) < script_len);
+  }
+
+  fn add_xref(&mut self, addr: u16, from: u16, kind: XrefKind) {
+    self.xrefs.entry(addr).or_default().push(Xref { from, kind });
+  }
+
+  fn build_blocks(&mut self) {
+    let mut leaders: BTreeSet<u16> = self.xrefs.keys().copied().collect();
+    leaders.insert(0);
+
+    for (pc, (instruction, len)) in &self.instructions {
+      if ends_block(instruction) {
+        leaders.insert(pc + len);
+      }
+    }
+
+    let mut current: Option<BasicBlock> = None;
+    let mut falls_through = false;
+
+    for (pc, (instruction, len)) in &self.instructions {
+      let block_continues = match &current {
+        Some(block) => block.end == *pc && !leaders.contains(pc),
+        None => false
+      };
+
+      if !block_continues {
+        if let Some(mut block) = current.take() {
+          // the block ends because the next instruction is a jump target
+          if falls_through && block.end == *pc {
+            block.successors.push(*pc);
+          }
+
+          self.blocks.insert(block.start, block);
+        }
+
+        current = Some(BasicBlock { start: *pc, end: *pc, successors: Vec::new() });
+      }
+
+      let block = current.as_mut().unwrap();
+      block.end = pc + len;
+      falls_through = !ends_block(instruction);
+
+      if ends_block(instruction) {
+        if let Some(addr) = instruction.branch_target() {
+          block.successors.push(addr);
+        }
+
+        if let Instruction::Jnz { .. } | Instruction::CondJmp { .. } = instruction {
+          if self.instructions.contains_key(&block.end) {
+            block.successors.push(block.end);
+          }
+        }
+      }
+    }
+
+    if let Some(block) = current {
+      self.blocks.insert(block.start, block);
+    }
+
+    for block in self.blocks.values_mut() {
+      let instructions = &self.instructions;
+      block.successors.retain(|addr| instructions.contains_key(addr));
+      block.successors.dedup();
+    }
+  }
+
+  fn build_labels(&mut self) {
+    for addr in self.xrefs.keys().chain(self.thread_entries.keys()) {
+      let label = if self.thread_entries.contains_key(addr) {
+        format!("thread_{:04X}", addr)
+      } else if self.functions.contains(addr) {
+        format!("sub_{:04X}", addr)
+      } else {
+        format!("loc_{:04X}", addr)
+      };
+
+      self.labels.insert(*addr, label);
+    }
+  }
+}
+
+// CALL doesn't end a block, the execution continues after it when the function returns
+fn ends_block(instruction: &Instruction) -> bool {
+  matches!(instruction, Instruction::Jmp { .. } | Instruction::Jnz { .. } | Instruction::CondJmp { .. } | Instruction::Ret | Instruction::Kill)
+}
+
+// addresses of the instructions found walking the script linearly, as the disassembler does
+fn linear_boundaries(script: &[u8]) -> BTreeSet<u16> {
+  let mut boundaries = BTreeSet::new();
+  let mut pc: usize = 0;
+
+  while pc < script.len() {
+    boundaries.insert(pc as u16);
+    pc += decode(script, pc as u16).map(|(_, len)| len as usize).unwrap_or(1);
+  }
+
+  boundaries
+}
+
+fn escape_dot(text: &str) -> String {
+  text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::assembler::assemble;
+
+  const SOURCE: &str = "
+      SETVEC 01, thread
+      CALL func
+    loop:
+      CJZ r[10], 00, done
+      ADD r[10], FFFF
+      JMP loop
+    done:
+      KILL
+    func:
+      MOV r[11], 0001
+      RET
+    thread:
+      YIELD
+      JMP thread
+  ";
+
+  fn block(start: u16, end: u16, successors: &[u16]) -> (u16, BasicBlock) {
+    (start, BasicBlock { start, end, successors: successors.to_vec() })
+  }
+
+  #[test]
+  fn blocks_labels_and_xrefs_are_found() {
+    let control_flow = ControlFlow::new(&assemble(SOURCE).unwrap());
+
+    let blocks = BTreeMap::from([
+      block(0x00, 0x07, &[0x07]),
+      block(0x07, 0x0d, &[0x14, 0x0d]),
+      block(0x0d, 0x14, &[0x07]),
+      block(0x14, 0x15, &[]),
+      block(0x15, 0x1a, &[]),
+      block(0x1a, 0x1e, &[0x1a])
+    ]);
+
+    assert_eq!(control_flow.blocks, blocks);
+    assert_eq!(control_flow.functions, BTreeSet::from([0x15]));
+    assert_eq!(control_flow.thread_entries, BTreeMap::from([(0x00, BTreeSet::from([0])), (0x1a, BTreeSet::from([1]))]));
+
+    let labels: Vec<(u16, &str)> = control_flow.labels.iter().map(|(addr, label)| (*addr, label.as_str())).collect();
+    assert_eq!(labels, [(0x00, "thread_0000"), (0x07, "loc_0007"), (0x14, "loc_0014"), (0x15, "sub_0015"), (0x1a, "thread_001A")]);
+
+    let xrefs = BTreeMap::from([
+      (0x07, vec![Xref { from: 0x11, kind: XrefKind::Jump }]),
+      (0x14, vec![Xref { from: 0x07, kind: XrefKind::ConditionalJump }]),
+      (0x15, vec![Xref { from: 0x04, kind: XrefKind::Call }]),
+      (0x1a, vec![Xref { from: 0x00, kind: XrefKind::SetVec }, Xref { from: 0x1b, kind: XrefKind::Jump }])
+    ]);
+
+    assert_eq!(control_flow.xrefs, xrefs);
+    assert_eq!(control_flow.reachable_blocks(0x00), [0x00, 0x07, 0x0d, 0x14]);
+  }
+
+  #[test]
+  fn listing_assembles_to_the_same_bytes() {
+    let script = assemble(SOURCE).unwrap();
+    let listing = ControlFlow::new(&script).build_listing(&script, &Opcodes::new());
+
+    for text in ["SETVEC 01, thread_001A", "CALL sub_0015", "CJZ r[10], 00, loc_0014", "JMP loc_0007", "; xrefs: 0000 (setvec), 001B (jump)"] {
+      assert!(listing.contains(text), "{} isn't in\n{}", text, listing);
+    }
+
+    assert_eq!(assemble(&listing).unwrap(), script);
+  }
+}
@@ -0,0 +1,466 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use crate::control_flow::{ControlFlow, XrefKind};
+use crate::instruction::{Instruction, Condition, Value, DEFAULT_ZOOM};
+use crate::symbols::{SymbolTable, PartSymbols};
+use crate::game_strings::init_game_strings;
+use crate::defines::BASE_PART_ID;
+
+// Turns the bytecode of a script into pseudocode. Every thread entry point and every function is decompiled on its
+// own, recovering:
+// - if/else from the forward CJxx (and JNZ), with a JMP at the end of the "then" block jumping over the "else" block
+// - do/while loops from the backward JNZ and CJxx, and infinite loops from the backward JMP
+// - calls, and thread spawns from SETVEC and RESET
+// Everything else is emitted as a goto to a label. The registers, labels and comments are taken from the symbols.
+
+enum Line {
+  Label(u16),
+  Code(usize, String)
+}
+
+struct Decompiler<'a> {
+  control_flow: &'a ControlFlow,
+  symbols: &'a SymbolTable,
+  comments: Option<&'a BTreeMap<u16, String>>,
+  game_strings: HashMap<u16, &'static str>,
+  addrs: Vec<u16>,
+  lines: Vec<Line>,
+  gotos: BTreeSet<u16>
+}
+
+pub fn decompile(script: &[u8], symbols: &SymbolTable, part_symbols: Option<&PartSymbols>) -> String {
+  let mut control_flow = ControlFlow::new(script);
+  let mut game_strings = HashMap::new();
+  let mut output = String::new();
+
+  init_game_strings(&mut game_strings);
+
+  if let Some(part_symbols) = part_symbols {
+    control_flow.apply_part_symbols(part_symbols);
+  }
+
+  let entries: BTreeSet<u16> = control_flow.thread_entries.keys().chain(control_flow.functions.iter()).copied().collect();
+
+  for entry in entries {
+    let mut decompiler = Decompiler {
+      control_flow: &control_flow,
+      symbols,
+      comments: part_symbols.map(|part_symbols| &part_symbols.comments),
+      game_strings: game_strings.clone(),
+      addrs: entry_instructions(&control_flow, entry),
+      lines: Vec::new(),
+      gotos: BTreeSet::new()
+    };
+
+    let name = control_flow.get_label(entry).unwrap_or("");
+
+    match control_flow.thread_entries.get(&entry) {
+      Some(threads) => {
+        let ids: Vec<String> = threads.iter().map(|id| format!("{:02X}", id)).collect();
+        output.push_str(&format!("thread {}() {{ // threads {}\n", name, ids.join(", ")));
+      },
+      None => output.push_str(&format!("function {}() {{\n", name))
+    }
+
+    decompiler.emit_range(0, decompiler.addrs.len(), 1);
+    output.push_str(&decompiler.render());
+    output.push_str("}\n\n");
+  }
+
+  output
+}
+
+// sorted addresses of the instructions reachable from the entry point, without following the calls
+fn entry_instructions(control_flow: &ControlFlow, entry: u16) -> Vec<u16> {
+  let mut addrs = Vec::new();
+
+  for block_addr in control_flow.reachable_blocks(entry) {
+    let block = &control_flow.blocks[&block_addr];
+    addrs.extend(control_flow.instructions.range(block.start..block.end).map(|(pc, _)| *pc));
+  }
+
+  addrs.sort_unstable();
+  addrs.dedup();
+  addrs
+}
+
+impl<'a> Decompiler<'a> {
+  fn instruction(&self, idx: usize) -> Instruction {
+    self.control_flow.instructions[&self.addrs[idx]].0
+  }
+
+  fn index_of(&self, addr: u16, first: usize, last: usize) -> Option<usize> {
+    self.addrs[first..last].binary_search(&addr).ok().map(|idx| idx + first)
+  }
+
+  fn push(&mut self, indent: usize, code: String) {
+    self.lines.push(Line::Code(indent, code));
+  }
+
+  fn emit_range(&mut self, first: usize, last: usize, indent: usize) {
+    let mut idx = first;
+
+    while idx < last {
+      let pc = self.addrs[idx];
+
+      let is_jump_target = self.control_flow.xrefs.get(&pc)
+        .map(|xrefs| xrefs.iter().any(|x| x.kind == XrefKind::Jump || x.kind == XrefKind::ConditionalJump))
+        .unwrap_or(false);
+
+      if is_jump_target {
+        self.lines.push(Line::Label(pc));
+      }
+
+      if let Some(comment) = self.comments.and_then(|comments| comments.get(&pc)) {
+        self.push(indent, format!("// {}", comment));
+      }
+
+      // the farthest backward jump to this instruction closes a loop
+      let loop_end = (idx..last).rev().find(|k| {
+        let instruction = self.instruction(*k);
+        instruction.branch_target() == Some(pc) && !matches!(instruction, Instruction::Call { .. })
+      });
+
+      // a jump to itself is a loop without body
+      if loop_end == Some(idx) {
+        let statement = match self.instruction(idx) {
+          Instruction::Jnz { reg, .. } => format!("while (--{} != 0);", self.register_name(reg)),
+          Instruction::CondJmp { condition, reg, operand, .. } => format!("while ({});", self.format_condition(condition, reg, operand)),
+          _ => "while (true);".to_string()
+        };
+
+        self.push(indent, statement);
+        idx += 1;
+        continue;
+      }
+
+      if let Some(end) = loop_end {
+        let instruction = self.instruction(end);
+
+        match instruction {
+          Instruction::Jmp { .. } => self.push(indent, "while (true) {".to_string()),
+          _ => self.push(indent, "do {".to_string())
+        }
+
+        // the label and the comment of the header are already emitted
+        let next = self.emit_instruction(idx, end, indent + 1);
+        self.emit_range(next, end, indent + 1);
+
+        match instruction {
+          Instruction::Jnz { reg, .. } => self.push(indent, format!("}} while (--{} != 0);", self.register_name(reg))),
+          Instruction::CondJmp { condition, reg, operand, .. } => self.push(indent, format!("}} while ({});", self.format_condition(condition, reg, operand))),
+          _ => self.push(indent, "}".to_string())
+        }
+
+        idx = end + 1;
+        continue;
+      }
+
+      idx = self.emit_instruction(idx, last, indent);
+    }
+  }
+
+  // emits the instruction at idx and returns the index of the next instruction to emit
+  fn emit_instruction(&mut self, idx: usize, last: usize, indent: usize) -> usize {
+    let pc = self.addrs[idx];
+    let instruction = self.instruction(idx);
+
+    // (condition of the jump, negated condition)
+    let conditions = match instruction {
+      Instruction::CondJmp { condition, reg, operand, .. } => Some((self.format_condition(condition, reg, operand), self.format_condition(negate(condition), reg, operand))),
+      Instruction::Jnz { reg, .. } => Some((format!("--{} != 0", self.register_name(reg)), format!("--{} == 0", self.register_name(reg)))),
+      _ => None
+    };
+
+    if let (Some((condition, negated_condition)), Some(target)) = (conditions, instruction.branch_target()) {
+      if target > pc {
+        if let Some(target_idx) = self.index_of(target, idx + 1, last) {
+          self.push(indent, format!("if ({}) {{", negated_condition));
+
+          // a jump over the code that follows the target at the end of the "then" block is an "else"
+          let else_end = match self.instruction(target_idx - 1) {
+            Instruction::Jmp { addr } if addr > target && target_idx - 1 > idx => self.index_of(addr, target_idx, last),
+            _ => None
+          };
+
+          match else_end {
+            Some(end) => {
+              self.emit_range(idx + 1, target_idx - 1, indent + 1);
+              self.push(indent, "} else {".to_string());
+              self.emit_range(target_idx, end, indent + 1);
+              self.push(indent, "}".to_string());
+              return end;
+            },
+            None => {
+              self.emit_range(idx + 1, target_idx, indent + 1);
+              self.push(indent, "}".to_string());
+              return target_idx;
+            }
+          }
+        }
+      }
+
+      // the branch can't be structured
+      self.gotos.insert(target);
+      self.push(indent, format!("if ({}) goto {};", condition, self.label(target)));
+      return idx + 1;
+    }
+
+    let statement = match instruction {
+      Instruction::Jmp { addr } => {
+        self.gotos.insert(addr);
+        format!("goto {};", self.label(addr))
+      },
+      _ => self.format_statement(&instruction)
+    };
+
+    self.push(indent, statement);
+    idx + 1
+  }
+
+  fn label(&self, addr: u16) -> String {
+    match self.control_flow.get_label(addr) {
+      Some(label) => label.to_string(),
+      None => format!("loc_{:04X}", addr)
+    }
+  }
+
+  fn format_statement(&self, instruction: &Instruction) -> String {
+    match *instruction {
+      Instruction::MovConst { dst, value } => format!("{} = {};", self.register_name(dst), value),
+      Instruction::Mov { dst, src } => format!("{} = {};", self.register_name(dst), self.register_name(src)),
+      Instruction::Add { dst, src } => format!("{} += {};", self.register_name(dst), self.register_name(src)),
+      Instruction::AddConst { dst, value } if value < 0 => format!("{} -= {};", self.register_name(dst), -(value as i32)),
+      Instruction::AddConst { dst, value } => format!("{} += {};", self.register_name(dst), value),
+      Instruction::Sub { dst, src } => format!("{} -= {};", self.register_name(dst), self.register_name(src)),
+      Instruction::And { dst, value } => format!("{} &= 0x{:04X};", self.register_name(dst), value),
+      Instruction::Or { dst, value } => format!("{} |= 0x{:04X};", self.register_name(dst), value),
+      Instruction::Shl { dst, value } => format!("{} <<= {};", self.register_name(dst), value),
+      Instruction::Shr { dst, value } => format!("{} >>= {};", self.register_name(dst), value),
+      Instruction::Call { addr } => format!("{}();", self.label(addr)),
+      Instruction::Ret => "return;".to_string(),
+      Instruction::Yield => "yield();".to_string(),
+      Instruction::Kill => "kill();".to_string(),
+      Instruction::SetVec { thread_id, addr } => format!("start_thread(0x{:02X}, {});", thread_id, self.label(addr)),
+      Instruction::ResetThreads { first, last, action } => {
+        let function = match action {
+          0 => "resume_threads",
+          1 => "pause_threads",
+          2 => "kill_threads",
+          _ => "reset_threads"
+        };
+
+        format!("{}(0x{:02X}, 0x{:02X});", function, first, last)
+      },
+      Instruction::SetPalette { palette } => format!("set_palette(0x{:02X});", palette >> 8),
+      Instruction::SelectPage { page } => format!("select_page(0x{:02X});", page),
+      Instruction::FillPage { page, color } => format!("fill_page(0x{:02X}, 0x{:02X});", page, color),
+      Instruction::CopyPage { src, dst } => format!("copy_page(0x{:02X}, 0x{:02X});", src, dst),
+      Instruction::Blit { page } => format!("blit(0x{:02X});", page),
+      Instruction::DrawString { string_id, x, y, color } => {
+        let call = format!("draw_string(0x{:03X}, {}, {}, 0x{:02X});", string_id, x, y, color);
+
+        match self.game_strings.get(&string_id) {
+          Some(text) => format!("{} // \"{}\"", call, text.replace('\n', "\\n")),
+          None => call
+        }
+      },
+      Instruction::PlaySound { resource_id, freq, volume, channel } => format!("play_sound(0x{:02X}, {}, {}, {});", resource_id, freq, volume, channel),
+      Instruction::PlayMusic { resource_id, delay, position } => format!("play_music(0x{:02X}, {}, {});", resource_id, delay, position),
+      Instruction::LoadResource { resource_id } if resource_id >= BASE_PART_ID => format!("load_part({});", resource_id - BASE_PART_ID),
+      Instruction::LoadResource { resource_id } => format!("load_resource(0x{:02X});", resource_id),
+      Instruction::DrawPoly { buffer, offset, x, y, zoom, .. } => {
+        format!("draw_poly{}(0x{:04X}, {}, {}, {});", buffer, offset, self.format_value(x), self.format_value(y), self.format_value(zoom))
+      },
+      Instruction::DrawPolyShort { offset, x, y } => format!("draw_poly1(0x{:04X}, {}, 199, {});", offset, x as i16 + y as i16 - 199, DEFAULT_ZOOM),
+      Instruction::Jmp { .. } | Instruction::Jnz { .. } | Instruction::CondJmp { .. } => unreachable!()
+    }
+  }
+
+  fn register_name(&self, reg_id: u8) -> String {
+    self.symbols.get_register_name(reg_id)
+  }
+
+  fn format_value(&self, value: Value) -> String {
+    match value {
+      Value::Register(reg_id) => self.register_name(reg_id),
+      Value::Const(value) | Value::Word(value) => value.to_string()
+    }
+  }
+
+  fn format_condition(&self, condition: Condition, reg_id: u8, operand: Value) -> String {
+    let operator = match condition {
+      Condition::Equal => "==",
+      Condition::NotEqual => "!=",
+      Condition::Greater => ">",
+      Condition::GreaterOrEqual => ">=",
+      Condition::Less => "<",
+      Condition::LessOrEqual => "<="
+    };
+
+    format!("{} {} {}", self.register_name(reg_id), operator, self.format_value(operand))
+  }
+
+  fn render(&self) -> String {
+    let mut text = String::new();
+
+    for line in &self.lines {
+      match line {
+        Line::Label(addr) => {
+          if self.gotos.contains(addr) {
+            text.push_str(&format!("{}:\n", self.label(*addr)));
+          }
+        },
+        Line::Code(indent, code) => {
+          text.push_str(&"  ".repeat(*indent));
+          text.push_str(code);
+          text.push('\n');
+        }
+      }
+    }
+
+    text
+  }
+}
+
+fn negate(condition: Condition) -> Condition {
+  match condition {
+    Condition::Equal => Condition::NotEqual,
+    Condition::NotEqual => Condition::Equal,
+    Condition::Greater => Condition::LessOrEqual,
+    Condition::GreaterOrEqual => Condition::Less,
+    Condition::Less => Condition::GreaterOrEqual,
+    Condition::LessOrEqual => Condition::Greater
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::assembler::assemble;
+
+  fn decompile_source(source: &str) -> String {
+    decompile(&assemble(source).unwrap(), &SymbolTable::new(), None)
+  }
+
+  #[test]
+  fn if_else_is_recovered() {
+    let source = "
+        CJZ r[10], 00, other
+        MOV r[11], 0001
+        JMP end
+      other:
+        MOV r[11], 0002
+      end:
+        KILL
+    ";
+
+    assert_eq!(decompile_source(source), "\
+thread thread_0000() { // threads 00
+  if (r[10] != 0) {
+    r[11] = 1;
+  } else {
+    r[11] = 2;
+  }
+  kill();
+}
+
+");
+  }
+
+  #[test]
+  fn backward_jnz_is_a_do_while() {
+    let source = "
+        MOV r[10], 0005
+      loop:
+        YIELD
+        JNZ r[10], loop
+        KILL
+    ";
+
+    assert_eq!(decompile_source(source), "\
+thread thread_0000() { // threads 00
+  r[10] = 5;
+  do {
+    yield();
+  } while (--r[10] != 0);
+  kill();
+}
+
+");
+  }
+
+  #[test]
+  fn jumps_to_themselves_are_loops_without_body() {
+    let source = "
+      wait:
+        CJL r[10], 05, wait
+      count:
+        JNZ r[11], count
+        KILL
+    ";
+
+    assert_eq!(decompile_source(source), "\
+thread thread_0000() { // threads 00
+  while (r[10] < 5);
+  while (--r[11] != 0);
+  kill();
+}
+
+");
+  }
+
+  #[test]
+  fn calls_are_decompiled_as_functions() {
+    let source = "
+        CALL func
+        KILL
+      func:
+        ADD r[10], 0001
+        RET
+    ";
+
+    assert_eq!(decompile_source(source), "\
+thread thread_0000() { // threads 00
+  sub_0004();
+  kill();
+}
+
+function sub_0004() {
+  r[10] += 1;
+  return;
+}
+
+");
+  }
+
+  #[test]
+  fn setvec_and_reset_start_and_stop_threads() {
+    let source = "
+        SETVEC 01, thread
+        RESET 01, 3F, YIELD
+        RESET 02, 02, KILL
+        RESET 03, 04, NONE
+        KILL
+      thread:
+        YIELD
+        KILL
+    ";
+
+    assert_eq!(decompile_source(source), "\
+thread thread_0000() { // threads 00
+  start_thread(0x01, thread_0011);
+  pause_threads(0x01, 0x3F);
+  kill_threads(0x02, 0x02);
+  resume_threads(0x03, 0x04);
+  kill();
+}
+
+thread thread_0011() { // threads 01
+  yield();
+  kill();
+}
+
+");
+  }
+}
@@ -7,6 +7,19 @@ pub const NUM_THREADS: usize = 64;
 pub const NUM_COLORS_PALETTE: u8 = 16;
 pub const BASE_PART_ID: u16 = 0x3e80;
 
+// resources of each game part in the form: [palette, script, polys 1, polys 2]. A part without polys 2 has a 0
+pub const PARTS_FILE_IDS: [[u8; 4]; 9] = [
+  [0x14, 0x15, 0x16, 0x00], // protection screen
+  [0x17, 0x18, 0x19, 0x00], // introduction
+  [0x1a, 0x1b, 0x1c, 0x11],
+  [0x1d, 0x1e, 0x1f, 0x11],
+  [0x20, 0x21, 0x22, 0x11],
+  [0x23, 0x24, 0x25, 0x00],
+  [0x26, 0x27, 0x28, 0x11],
+  [0x29, 0x2a, 0x2b, 0x11], // final
+  [0x7d, 0x7e, 0x7f, 0x00]  // password screen
+];
+
 // these are the values the registers should have when a level begins. Each element in the array is in the form: [register_idx, value]
 pub const LEVEL_00_INITIAL_REGISTERS_VALUES: [[i16; 2]; 6] = [ // the intro
   [0x54, 129], [0xbc, 16], [0xc6, 128], [0xdc, 33], [0xe4, 20], [0xf2, 4000]
@@ -0,0 +1,82 @@
+// Recording of the draw commands executed by Video, for the debugger. The commands of the current frame are recorded,
+// and when the frame is shown (BLIT) they become the last frame. A frame keeps a copy of the pages when it started, so
+// its commands can be replayed one by one (Video::replay_draw_frame). The pages of the commands are the physical ones
+// (0xfe and 0xff are already resolved), and the polys and the strings are drawn in the page that was the backbuffer.
+// Video can also keep, for every pixel of the pages, the command that drew it (PixelOwner).
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DrawCommand {
+  FillPage { color_idx: u8 },
+  CopyPage { src_page_idx: u8, vscroll: i16 },
+  // poly_buffer is 1 or 2, and poly_file_id the file of that buffer in the part that drew the poly (the frame can be
+  // replayed after the part changes). The colors are the ones of the poly
+  Poly { poly_buffer: u8, poly_file_id: u8, offset: u16, x: i16, y: i16, zoom: i16 },
+  String { string_id: u16, x: i16, y: i16, color_idx: u8 },
+  Bitmap { resource_id: u8 } // always drawn in the page 0
+}
+
+impl DrawCommand {
+  // 0 fill, 1 copy, 2 poly, 3 string, 4 bitmap
+  pub fn kind(&self) -> u8 {
+    match self {
+      DrawCommand::FillPage { .. } => 0,
+      DrawCommand::CopyPage { .. } => 1,
+      DrawCommand::Poly { .. } => 2,
+      DrawCommand::String { .. } => 3,
+      DrawCommand::Bitmap { .. } => 4
+    }
+  }
+}
+
+// the instruction at pc of the thread executed the command
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DrawEntry {
+  pub thread_id: u8,
+  pub pc: u16,
+  pub page_idx: u8,
+  pub command: DrawCommand
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct DrawFrame {
+  pub start_pages: Vec<Vec<u8>>,
+  pub entries: Vec<DrawEntry>
+}
+
+// the command that drew a pixel (the pixels copied from other page keep their owner). poly_offset is the offset of the
+// polygon in the poly buffer (in a hierarchy, the polygon that has the pixel), it's 0 for the other commands
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PixelOwner {
+  pub thread_id: u8,
+  pub pc: u16,
+  pub kind: u8, // DrawCommand::kind
+  pub poly_offset: u16
+}
+
+pub struct DrawRecorder {
+  pub current: DrawFrame,
+  pub last: Option<DrawFrame>
+}
+
+impl DrawRecorder {
+  pub fn new(pages: &[Vec<u8>]) -> DrawRecorder {
+    DrawRecorder {
+      current: DrawFrame { start_pages: pages.to_vec(), entries: Vec::new() },
+      last: None
+    }
+  }
+
+  pub fn record(&mut self, entry: DrawEntry) {
+    self.current.entries.push(entry);
+  }
+
+  // pages: the pages when the next frame starts
+  pub fn end_frame(&mut self, pages: &[Vec<u8>]) {
+    let frame = DrawFrame { start_pages: pages.to_vec(), entries: Vec::new() };
+    self.last = Some(std::mem::replace(&mut self.current, frame));
+  }
+
+  pub fn get_frame(&self, last: bool) -> Option<&DrawFrame> {
+    if last { self.last.as_ref() } else { Some(&self.current) }
+  }
+}
@@ -0,0 +1,499 @@
+use crate::utils::{read_u8, read_u16, read_i16};
+
+// operand of the instructions that accept a register or an immediate value (CJxx and DRAWPOLY)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Value {
+  Register(u8),
+  Const(i16), // stored using the most compact encoding that can hold it
+  Word(i16)   // always stored using the long form: a 16 bit word, or an explicit byte for the zoom
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Condition {
+  Equal = 0,
+  NotEqual,
+  Greater,
+  GreaterOrEqual,
+  Less,
+  LessOrEqual
+}
+
+impl Condition {
+  pub fn from_u8(value: u8) -> Option<Condition> {
+    match value {
+      0 => Some(Condition::Equal),
+      1 => Some(Condition::NotEqual),
+      2 => Some(Condition::Greater),
+      3 => Some(Condition::GreaterOrEqual),
+      4 => Some(Condition::Less),
+      5 => Some(Condition::LessOrEqual),
+      _ => None
+    }
+  }
+
+  pub fn mnemonic(self) -> &'static str {
+    ["CJZ", "CJNZ", "CJG", "CJGE", "CJL", "CJLE"][self as usize]
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Instruction {
+  MovConst { dst: u8, value: i16 },
+  Mov { dst: u8, src: u8 },
+  Add { dst: u8, src: u8 },
+  AddConst { dst: u8, value: i16 },
+  Call { addr: u16 },
+  Ret,
+  Yield,
+  Jmp { addr: u16 },
+  SetVec { thread_id: u8, addr: u16 },
+  Jnz { reg: u8, addr: u16 },
+  CondJmp { condition: Condition, reg: u8, operand: Value, addr: u16 },
+  SetPalette { palette: u16 },
+  ResetThreads { first: u8, last: u8, action: u8 },
+  SelectPage { page: u8 },
+  FillPage { page: u8, color: u8 },
+  CopyPage { src: u8, dst: u8 },
+  Blit { page: u8 },
+  Kill,
+  DrawString { string_id: u16, x: u8, y: u8, color: u8 },
+  Sub { dst: u8, src: u8 },
+  And { dst: u8, value: u16 },
+  Or { dst: u8, value: u16 },
+  Shl { dst: u8, value: u16 },
+  Shr { dst: u8, value: u16 },
+  PlaySound { resource_id: u16, freq: u8, volume: u8, channel: u8 },
+  LoadResource { resource_id: u16 },
+  PlayMusic { resource_id: u16, delay: u16, position: u8 },
+  DrawPoly { buffer: u8, offset: u16, x: Value, y: Value, zoom: Value, long_form: bool }, // long_form: never stored in the 4 bytes short form
+  // the short form of DRAWPOLY1 with a y byte above 199, drawn at (x + y - 199, 199) with the default zoom. The bytes
+  // are kept as they are, because other bytes can draw at the same position
+  DrawPolyShort { offset: u16, x: u8, y: u8 }
+}
+
+pub const DEFAULT_ZOOM: i16 = 0x40;
+
+// decodes the instruction at pc. Returns the instruction and its length, or None if the opcode is unknown,
+// the operands are not valid or the instruction runs past the end of the script
+pub fn decode(script: &[u8], pc: u16) -> Option<(Instruction, u16)> {
+  let opcode = *script.get(pc as usize)?;
+
+  if opcode & 0x80 != 0 {
+    return decode_short_draw_poly(script, pc, opcode);
+  }
+
+  if opcode & 0x40 != 0 {
+    return decode_draw_poly(script, pc, opcode);
+  }
+
+  let len: u16 = match opcode {
+    0x05 | 0x06 | 0x11 => 1,
+    0x0d | 0x10 => 2,
+    0x01 | 0x02 | 0x04 | 0x07 | 0x0b | 0x0e | 0x0f | 0x13 | 0x19 => 3,
+    0x00 | 0x03 | 0x08 | 0x09 | 0x0c | 0x14 | 0x15 | 0x16 | 0x17 => 4,
+    0x12 | 0x18 | 0x1a => 6,
+    0x0a => {
+      let param_type = *script.get(pc as usize + 1)?;
+      if param_type & 0x40 != 0 { 7 } else { 6 }
+    },
+    _ => return None
+  };
+
+  if pc as usize + len as usize > script.len() {
+    return None;
+  }
+
+  let p = pc + 1;
+
+  let instruction = match opcode {
+    0x00 => Instruction::MovConst { dst: read_u8(script, p), value: read_i16(script, p + 1) },
+    0x01 => Instruction::Mov { dst: read_u8(script, p), src: read_u8(script, p + 1) },
+    0x02 => Instruction::Add { dst: read_u8(script, p), src: read_u8(script, p + 1) },
+    0x03 => Instruction::AddConst { dst: read_u8(script, p), value: read_i16(script, p + 1) },
+    0x04 => Instruction::Call { addr: read_u16(script, p) },
+    0x05 => Instruction::Ret,
+    0x06 => Instruction::Yield,
+    0x07 => Instruction::Jmp { addr: read_u16(script, p) },
+    0x08 => Instruction::SetVec { thread_id: read_u8(script, p), addr: read_u16(script, p + 1) },
+    0x09 => Instruction::Jnz { reg: read_u8(script, p), addr: read_u16(script, p + 1) },
+    0x0a => {
+      let param_type = read_u8(script, p);
+
+      // a register operand with the word flag set doesn't match the length of the instruction
+      if param_type & 0x38 != 0 || param_type & 0xc0 == 0xc0 {
+        return None;
+      }
+
+      let condition = Condition::from_u8(param_type & 7)?;
+      let reg = read_u8(script, p + 1);
+
+      let (operand, addr_pc) = if param_type & 0x80 != 0 {
+        (Value::Register(read_u8(script, p + 2)), p + 3)
+      } else if param_type & 0x40 != 0 {
+        (Value::Word(read_i16(script, p + 2)), p + 4)
+      } else {
+        (Value::Const(read_u8(script, p + 2) as i16), p + 3)
+      };
+
+      Instruction::CondJmp { condition, reg, operand, addr: read_u16(script, addr_pc) }
+    },
+    0x0b => Instruction::SetPalette { palette: read_u16(script, p) },
+    0x0c => Instruction::ResetThreads { first: read_u8(script, p), last: read_u8(script, p + 1), action: read_u8(script, p + 2) },
+    0x0d => Instruction::SelectPage { page: read_u8(script, p) },
+    0x0e => Instruction::FillPage { page: read_u8(script, p), color: read_u8(script, p + 1) },
+    0x0f => Instruction::CopyPage { src: read_u8(script, p), dst: read_u8(script, p + 1) },
+    0x10 => Instruction::Blit { page: read_u8(script, p) },
+    0x11 => Instruction::Kill,
+    0x12 => Instruction::DrawString { string_id: read_u16(script, p), x: read_u8(script, p + 2), y: read_u8(script, p + 3), color: read_u8(script, p + 4) },
+    0x13 => Instruction::Sub { dst: read_u8(script, p), src: read_u8(script, p + 1) },
+    0x14 => Instruction::And { dst: read_u8(script, p), value: read_u16(script, p + 1) },
+    0x15 => Instruction::Or { dst: read_u8(script, p), value: read_u16(script, p + 1) },
+    0x16 => Instruction::Shl { dst: read_u8(script, p), value: read_u16(script, p + 1) },
+    0x17 => Instruction::Shr { dst: read_u8(script, p), value: read_u16(script, p + 1) },
+    0x18 => Instruction::PlaySound { resource_id: read_u16(script, p), freq: read_u8(script, p + 2), volume: read_u8(script, p + 3), channel: read_u8(script, p + 4) },
+    0x19 => Instruction::LoadResource { resource_id: read_u16(script, p) },
+    _ => Instruction::PlayMusic { resource_id: read_u16(script, p), delay: read_u16(script, p + 2), position: read_u8(script, p + 4) }
+  };
+
+  Some((instruction, len))
+}
+
+fn decode_short_draw_poly(script: &[u8], pc: u16, opcode: u8) -> Option<(Instruction, u16)> {
+  if pc as usize + 4 > script.len() {
+    return None;
+  }
+
+  let offset = ((((opcode as u32) << 8) | read_u8(script, pc + 1) as u32) * 2) as u16;
+  let x = read_u8(script, pc + 2);
+  let y = read_u8(script, pc + 3);
+
+  if y > 199 {
+    return Some((Instruction::DrawPolyShort { offset, x, y }, 4));
+  }
+
+  Some((Instruction::DrawPoly { buffer: 1, offset, x: Value::Const(x as i16), y: Value::Const(y as i16), zoom: Value::Const(DEFAULT_ZOOM), long_form: false }, 4))
+}
+
+fn decode_draw_poly(script: &[u8], pc: u16, opcode: u8) -> Option<(Instruction, u16)> {
+  // the VM reads the y flags 0x0c as a byte, like 0x08, so the encoding couldn't be rebuilt
+  if opcode & 0xc == 0xc {
+    return None;
+  }
+
+  let mut my_pc = pc as usize + 1;
+  let byte = |idx: usize| script.get(idx).copied();
+
+  let offset = (((byte(my_pc)? as u32) << 8 | byte(my_pc + 1)? as u32) * 2) as u16;
+  my_pc += 2;
+
+  let x_byte = byte(my_pc)?;
+  my_pc += 1;
+
+  let x = if opcode & 0x20 == 0 {
+    if opcode & 0x10 == 0 {
+      my_pc += 1;
+      Value::Word(((x_byte as u16) << 8 | byte(my_pc - 1)? as u16) as i16)
+    } else {
+      Value::Register(x_byte)
+    }
+  } else if opcode & 0x10 != 0 {
+    Value::Const(x_byte as i16 + 0x100)
+  } else {
+    Value::Const(x_byte as i16)
+  };
+
+  let y_byte = byte(my_pc)?;
+  my_pc += 1;
+
+  let y = if opcode & 0x8 == 0 {
+    if opcode & 0x4 == 0 {
+      my_pc += 1;
+      Value::Word(((y_byte as u16) << 8 | byte(my_pc - 1)? as u16) as i16)
+    } else {
+      Value::Register(y_byte)
+    }
+  } else {
+    Value::Const(y_byte as i16)
+  };
+
+  let mut buffer = 1;
+
+  let zoom = match opcode & 0x3 {
+    0 => Value::Const(DEFAULT_ZOOM),
+    1 => {
+      my_pc += 1;
+      Value::Register(byte(my_pc - 1)?)
+    },
+    2 => {
+      my_pc += 1;
+      let value = byte(my_pc - 1)? as i16;
+      if value == DEFAULT_ZOOM { Value::Word(value) } else { Value::Const(value) }
+    },
+    _ => {
+      buffer = 2;
+      Value::Const(DEFAULT_ZOOM)
+    }
+  };
+
+  Some((Instruction::DrawPoly { buffer, offset, x, y, zoom, long_form: true }, (my_pc - pc as usize) as u16))
+}
+
+impl Instruction {
+  // encodes the instruction back to bytecode. DRAWPOLY uses the most compact encoding its operands allow
+  pub fn encode(&self) -> Result<Vec<u8>, String> {
+    let mut out = Vec::with_capacity(7);
+
+    match *self {
+      Instruction::MovConst { dst, value } => { out.push(0x00); out.push(dst); push_u16(&mut out, value as u16); },
+      Instruction::Mov { dst, src } => out.extend_from_slice(&[0x01, dst, src]),
+      Instruction::Add { dst, src } => out.extend_from_slice(&[0x02, dst, src]),
+      Instruction::AddConst { dst, value } => { out.push(0x03); out.push(dst); push_u16(&mut out, value as u16); },
+      Instruction::Call { addr } => { out.push(0x04); push_u16(&mut out, addr); },
+      Instruction::Ret => out.push(0x05),
+      Instruction::Yield => out.push(0x06),
+      Instruction::Jmp { addr } => { out.push(0x07); push_u16(&mut out, addr); },
+      Instruction::SetVec { thread_id, addr } => { out.push(0x08); out.push(thread_id); push_u16(&mut out, addr); },
+      Instruction::Jnz { reg, addr } => { out.push(0x09); out.push(reg); push_u16(&mut out, addr); },
+      Instruction::CondJmp { condition, reg, operand, addr } => {
+        out.push(0x0a);
+
+        match operand {
+          Value::Register(src) => out.extend_from_slice(&[0x80 | condition as u8, reg, src]),
+          Value::Const(value) if (0..=0xff).contains(&value) => out.extend_from_slice(&[condition as u8, reg, value as u8]),
+          Value::Const(value) | Value::Word(value) => {
+            out.extend_from_slice(&[0x40 | condition as u8, reg]);
+            push_u16(&mut out, value as u16);
+          }
+        }
+
+        push_u16(&mut out, addr);
+      },
+      Instruction::SetPalette { palette } => { out.push(0x0b); push_u16(&mut out, palette); },
+      Instruction::ResetThreads { first, last, action } => out.extend_from_slice(&[0x0c, first, last, action]),
+      Instruction::SelectPage { page } => out.extend_from_slice(&[0x0d, page]),
+      Instruction::FillPage { page, color } => out.extend_from_slice(&[0x0e, page, color]),
+      Instruction::CopyPage { src, dst } => out.extend_from_slice(&[0x0f, src, dst]),
+      Instruction::Blit { page } => out.extend_from_slice(&[0x10, page]),
+      Instruction::Kill => out.push(0x11),
+      Instruction::DrawString { string_id, x, y, color } => { out.push(0x12); push_u16(&mut out, string_id); out.extend_from_slice(&[x, y, color]); },
+      Instruction::Sub { dst, src } => out.extend_from_slice(&[0x13, dst, src]),
+      Instruction::And { dst, value } => { out.push(0x14); out.push(dst); push_u16(&mut out, value); },
+      Instruction::Or { dst, value } => { out.push(0x15); out.push(dst); push_u16(&mut out, value); },
+      Instruction::Shl { dst, value } => { out.push(0x16); out.push(dst); push_u16(&mut out, value); },
+      Instruction::Shr { dst, value } => { out.push(0x17); out.push(dst); push_u16(&mut out, value); },
+      Instruction::PlaySound { resource_id, freq, volume, channel } => { out.push(0x18); push_u16(&mut out, resource_id); out.extend_from_slice(&[freq, volume, channel]); },
+      Instruction::LoadResource { resource_id } => { out.push(0x19); push_u16(&mut out, resource_id); },
+      Instruction::PlayMusic { resource_id, delay, position } => { out.push(0x1a); push_u16(&mut out, resource_id); push_u16(&mut out, delay); out.push(position); },
+      Instruction::DrawPoly { buffer, offset, x, y, zoom, long_form } => encode_draw_poly(&mut out, buffer, offset, (x, y, zoom), long_form)?,
+      Instruction::DrawPolyShort { offset, x, y } => {
+        if offset & 1 != 0 {
+          return Err(format!("poly offset {:04X} is not even", offset));
+        }
+
+        let value = offset / 2;
+        out.extend_from_slice(&[0x80 | (value >> 8) as u8, value as u8, x, y]);
+      }
+    }
+
+    Ok(out)
+  }
+
+  // poly buffer (1 or 2) and offset of the polys drawn by DRAWPOLY
+  pub fn get_poly(&self) -> Option<(u8, u16)> {
+    match *self {
+      Instruction::DrawPoly { buffer, offset, .. } => Some((buffer, offset)),
+      Instruction::DrawPolyShort { offset, .. } => Some((1, offset)),
+      _ => None
+    }
+  }
+
+  // addresses this instruction can transfer the control to, apart from the next instruction
+  pub fn branch_target(&self) -> Option<u16> {
+    match *self {
+      Instruction::Call { addr } | Instruction::Jmp { addr } | Instruction::Jnz { addr, .. } | Instruction::CondJmp { addr, .. } => Some(addr),
+      _ => None
+    }
+  }
+}
+
+fn push_u16(out: &mut Vec<u8>, value: u16) {
+  out.extend_from_slice(&value.to_be_bytes());
+}
+
+// the x and y bytes of the short form of DRAWPOLY1, if the operands fit in it: default zoom, x in [0, 311] and y in
+// [0, 199]. When y is 199, x can go beyond 255 adding the excess to y
+pub fn get_short_draw_poly_bytes(x: Value, y: Value, zoom: Value) -> Option<(u8, u8)> {
+  if zoom != Value::Const(DEFAULT_ZOOM) {
+    return None;
+  }
+
+  match (x, y) {
+    (Value::Const(vx), Value::Const(vy)) if (0..=0xff).contains(&vx) && (0..=199).contains(&vy) => Some((vx as u8, vy as u8)),
+    (Value::Const(vx), Value::Const(199)) if vx > 0xff && vx <= 0xff + (0xff - 199) => Some((0xff, (199 + vx - 0xff) as u8)),
+    _ => None
+  }
+}
+
+fn encode_draw_poly(out: &mut Vec<u8>, buffer: u8, offset: u16, (x, y, zoom): (Value, Value, Value), long_form: bool) -> Result<(), String> {
+  if offset & 1 != 0 {
+    return Err(format!("poly offset {:04X} is not even", offset));
+  }
+
+  if buffer != 1 && buffer != 2 {
+    return Err(format!("invalid poly buffer {}", buffer));
+  }
+
+  if buffer == 2 && zoom != Value::Const(DEFAULT_ZOOM) {
+    return Err("DRAWPOLY2 only supports the default zoom".to_string());
+  }
+
+  // the shortest form, for the poly buffer 1
+  if buffer == 1 && !long_form {
+    if let Some((bx, by)) = get_short_draw_poly_bytes(x, y, zoom) {
+      let value = offset / 2;
+      out.extend_from_slice(&[0x80 | (value >> 8) as u8, value as u8, bx, by]);
+      return Ok(());
+    }
+  }
+
+  let mut opcode = 0x40;
+  let mut operands = Vec::with_capacity(6);
+
+  push_u16(&mut operands, offset / 2);
+
+  match x {
+    Value::Register(reg) => { opcode |= 0x10; operands.push(reg); },
+    Value::Const(value) if (0..=0xff).contains(&value) => { opcode |= 0x20; operands.push(value as u8); },
+    Value::Const(value) if (0x100..=0x1ff).contains(&value) => { opcode |= 0x30; operands.push((value - 0x100) as u8); },
+    Value::Const(value) | Value::Word(value) => push_u16(&mut operands, value as u16)
+  }
+
+  match y {
+    Value::Register(reg) => { opcode |= 0x4; operands.push(reg); },
+    Value::Const(value) if (0..=0xff).contains(&value) => { opcode |= 0x8; operands.push(value as u8); },
+    Value::Const(value) | Value::Word(value) => push_u16(&mut operands, value as u16)
+  }
+
+  if buffer == 2 {
+    opcode |= 0x3;
+  } else {
+    match zoom {
+      Value::Register(reg) => { opcode |= 0x1; operands.push(reg); },
+      Value::Const(DEFAULT_ZOOM) => {},
+      Value::Const(value) | Value::Word(value) => {
+        if !(0..=0xff).contains(&value) {
+          return Err(format!("zoom {:X} doesn't fit in a byte", value));
+        }
+
+        opcode |= 0x2;
+        operands.push(value as u8);
+      }
+    }
+  }
+
+  out.push(opcode);
+  out.extend_from_slice(&operands);
+
+  Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+  use super::*;
+
+  // an instruction of every opcode and every operand encoding
+  pub const FORMS: &[&[u8]] = &[
+    &[0x00, 0x10, 0xff, 0xf0], // MOV r, const
+    &[0x01, 0x10, 0x11],
+    &[0x02, 0x10, 0x11],
+    &[0x03, 0x10, 0x00, 0x05],
+    &[0x04, 0x12, 0x34],
+    &[0x05],
+    &[0x06],
+    &[0x07, 0x00, 0x20],
+    &[0x08, 0x3f, 0x00, 0x10],
+    &[0x09, 0x10, 0x00, 0x20],
+    &[0x0a, 0x00, 0x10, 0x05, 0x00, 0x30], // CJZ r, byte
+    &[0x0a, 0x01, 0x10, 0xff, 0x00, 0x30],
+    &[0x0a, 0x02, 0x10, 0x00, 0x00, 0x30],
+    &[0x0a, 0x43, 0x10, 0x00, 0x05, 0x00, 0x30], // CJGE r, word with a value that fits in a byte
+    &[0x0a, 0x44, 0x10, 0x80, 0x00, 0x00, 0x30],
+    &[0x0a, 0x85, 0x10, 0x11, 0x00, 0x30], // CJLE r, r
+    &[0x0b, 0x05, 0x00],
+    &[0x0c, 0x01, 0x3f, 0x00],
+    &[0x0c, 0x01, 0x3f, 0x01],
+    &[0x0c, 0x00, 0x00, 0x02],
+    &[0x0d, 0xfe],
+    &[0x0e, 0x00, 0x05],
+    &[0x0f, 0xff, 0x00],
+    &[0x0f, 0x41, 0x02], // copy with vscroll
+    &[0x10, 0xff],
+    &[0x11],
+    &[0x12, 0x01, 0x2c, 0x05, 0x10, 0x0f],
+    &[0x13, 0x10, 0x11],
+    &[0x14, 0x10, 0x00, 0xff],
+    &[0x15, 0x10, 0x80, 0x00],
+    &[0x16, 0x10, 0x00, 0x02],
+    &[0x17, 0x10, 0x00, 0x0f],
+    &[0x18, 0x00, 0x2a, 0x10, 0x3f, 0x01],
+    &[0x19, 0x00, 0x2a],
+    &[0x19, 0x3e, 0x81], // load part
+    &[0x1a, 0x00, 0x07, 0x00, 0x00, 0x00],
+    &[0x80, 0x10, 0x20, 0x30], // short DRAWPOLY1
+    &[0x80, 0x10, 0xff, 0xc7],
+    &[0xff, 0xff, 0x00, 0x00],
+    &[0x80, 0x10, 0x0a, 0xd2], // y above 199, drawn at (0x15, 199)
+    &[0x80, 0x10, 0x15, 0xc7], // the same position with other bytes
+    &[0x80, 0x10, 0xfe, 0xd0], // drawn at (0x107, 199)
+    &[0x80, 0x10, 0xff, 0xcf],
+    &[0x40, 0x01, 0x00, 0x00, 0x20, 0x00, 0x10], // DRAWPOLY1 word, word
+    &[0x40, 0x01, 0x00, 0xff, 0xf0, 0xff, 0xf0],
+    &[0x50, 0x01, 0x00, 0x10, 0x00, 0x20], // x register
+    &[0x60, 0x01, 0x00, 0x10, 0x00, 0x20], // x byte
+    &[0x70, 0x01, 0x00, 0x10, 0x00, 0x20], // x byte + 0x100
+    &[0x64, 0x01, 0x00, 0x10, 0x20], // y register
+    &[0x68, 0x01, 0x00, 0x10, 0x20], // y byte, the operands also fit in the short form
+    &[0x69, 0x01, 0x00, 0x10, 0x20, 0x30], // zoom register
+    &[0x6a, 0x01, 0x00, 0x10, 0x20, 0x80], // zoom byte
+    &[0x6a, 0x01, 0x00, 0x10, 0x20, 0x40], // zoom byte with the default value
+    &[0x78, 0x01, 0x00, 0x07, 0xc7], // long form with operands that fit in the short form
+    &[0x6b, 0x01, 0x00, 0x10, 0x20], // DRAWPOLY2
+    &[0x7f & !0x4, 0x01, 0x00, 0x10, 0x20] // DRAWPOLY2, x byte + 0x100, y byte
+  ];
+
+  #[test]
+  fn decode_encode_round_trip() {
+    for bytes in FORMS {
+      let (instruction, len) = decode(bytes, 0).unwrap_or_else(|| panic!("{:02X?} is not decoded", bytes));
+
+      assert_eq!(len as usize, bytes.len(), "length of {:02X?}", bytes);
+      assert_eq!(instruction.encode().as_deref(), Ok(*bytes), "{:?}", instruction);
+    }
+  }
+
+  #[test]
+  fn short_draw_poly_keeps_the_bytes() {
+    assert_eq!(decode(&[0x80, 0x10, 0x0a, 0xd2], 0), Some((Instruction::DrawPolyShort { offset: 0x20, x: 0x0a, y: 0xd2 }, 4)));
+    assert_eq!(decode(&[0x80, 0x10, 0x15, 0xc7], 0), Some((Instruction::DrawPoly { buffer: 1, offset: 0x20, x: Value::Const(0x15), y: Value::Const(199), zoom: Value::Const(DEFAULT_ZOOM), long_form: false }, 4)));
+  }
+
+  #[test]
+  fn draw_poly_uses_the_most_compact_encoding() {
+    let draw_poly = |x, y, zoom| Instruction::DrawPoly { buffer: 1, offset: 0x20, x, y, zoom, long_form: false }.encode().unwrap();
+
+    assert_eq!(draw_poly(Value::Const(0x20), Value::Const(0x30), Value::Const(DEFAULT_ZOOM)), [0x80, 0x10, 0x20, 0x30]);
+    assert_eq!(draw_poly(Value::Const(0x107), Value::Const(199), Value::Const(DEFAULT_ZOOM)), [0x80, 0x10, 0xff, 0xcf]);
+    assert_eq!(draw_poly(Value::Const(0x20), Value::Const(0xc8), Value::Const(DEFAULT_ZOOM)), [0x68, 0x00, 0x10, 0x20, 0xc8]);
+    assert_eq!(draw_poly(Value::Const(0x120), Value::Register(0x10), Value::Const(0x80)), [0x76, 0x00, 0x10, 0x20, 0x10, 0x80]);
+    assert_eq!(draw_poly(Value::Word(0x20), Value::Word(0x30), Value::Word(DEFAULT_ZOOM)), [0x42, 0x00, 0x10, 0x00, 0x20, 0x00, 0x30, 0x40]);
+  }
+
+  #[test]
+  fn invalid_forms_are_not_decoded() {
+    assert_eq!(decode(&[0x1b], 0), None);
+    assert_eq!(decode(&[0x0a, 0x06, 0x10, 0x05, 0x00, 0x30], 0), None); // unknown condition
+    assert_eq!(decode(&[0x0a, 0xc0, 0x10, 0x05, 0x00, 0x30, 0x00], 0), None);
+    assert_eq!(decode(&[0x6c, 0x01, 0x00, 0x10, 0x20], 0), None); // y flags 0x0c
+    assert_eq!(decode(&[0x00, 0x10, 0xff], 0), None); // past the end of the script
+  }
+}
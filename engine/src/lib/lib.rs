@@ -9,14 +9,51 @@ pub mod defines;
 pub mod font;
 pub mod game_strings;
 pub mod utils;
+pub mod instruction;
+pub mod assembler;
+pub mod control_flow;
+pub mod decompiler;
+pub mod symbols;
+pub mod verifier;
+pub mod patches;
+pub mod profiler;
+pub mod timeline;
+pub mod register_search;
+pub mod observer;
+pub mod checkpoints;
+pub mod scaled_renderer;
+pub mod draw_list;
+pub mod color;
+pub mod svg;
+#[cfg(feature = "capture")]
+pub mod capture;
 
 use crate::defines::{FRAME_BUFFER_WIDTH, FRAME_BUFFER_HEIGHT};
 use crate::resources_manager::{ResourcesManager, ResourceType};
-use crate::virtual_machine::VirtualMachine;
+use crate::virtual_machine::{VirtualMachine, BudgetKind};
 use crate::video::Video;
-use crate::defines::NUM_THREADS;
-use crate::utils::{write_u16};
+use crate::defines::{NUM_THREADS, NUM_REGISTERS, PARTS_FILE_IDS};
+use crate::utils::{write_u16, write_u32};
 use crate::poly::{Poly, draw_poly_to_buffer};
+use crate::control_flow::{ControlFlow, XrefKind};
+use crate::decompiler::decompile;
+use crate::instruction::decode;
+use crate::symbols::SymbolTable;
+use crate::verifier::verify;
+use crate::patches::PatchList;
+use crate::timeline::ThreadChangeKind;
+use crate::draw_list::DrawCommand;
+use crate::color::{ColorTable, PixelFormat, palette_color_to_rgb};
+use crate::svg::{shape_to_svg, frame_to_svg};
+use crate::register_search::{RegisterSearch, SearchCondition};
+use crate::observer::VmObserver;
+use crate::checkpoints::{CheckpointTable, Checkpoint, GAME_PARTS};
+#[cfg(feature = "capture")]
+use crate::capture::{FrameCapture, pause_to_duration};
+#[cfg(feature = "capture")]
+use crate::opcodes::ActionRequest;
+#[cfg(feature = "capture")]
+use crate::virtual_machine::ScriptRegs;
 
 const SHARED_MEMORY_SIZE: usize = 3 * 1024 * 1204; // 3Mb
 
@@ -25,7 +62,17 @@ pub struct AnotherWorldEngine {
   shared_memory: Vec<u8>,
   resources_manager: ResourcesManager,
   virtual_machine: VirtualMachine,
-  video: Video
+  video: Video,
+  symbols: SymbolTable,
+  patches: PatchList,
+  register_search: Option<RegisterSearch>,
+  checkpoints: CheckpointTable,
+  frame_buffer: Vec<u8>, // the frames, pages... written for the host, apart from shared_memory
+  frame_format: PixelFormat,
+  scaled_frame_buffer: Vec<u8>,
+  color_table: ColorTable, // colors of the active palette
+  #[cfg(feature = "capture")]
+  capture: Option<FrameCapture>
 }
 
 #[wasm_bindgen]
@@ -35,7 +82,17 @@ impl AnotherWorldEngine {
       shared_memory: vec![0; SHARED_MEMORY_SIZE],
       resources_manager: ResourcesManager::new(),
       virtual_machine: VirtualMachine::new(),
-      video: Video::new()
+      video: Video::new(),
+      symbols: SymbolTable::new(),
+      patches: PatchList::new(),
+      register_search: None,
+      checkpoints: CheckpointTable::new(),
+      frame_buffer: vec![0; (FRAME_BUFFER_WIDTH * FRAME_BUFFER_HEIGHT) as usize * 4],
+      frame_format: PixelFormat::Rgba8888,
+      scaled_frame_buffer: Vec::new(),
+      color_table: ColorTable::new(),
+      #[cfg(feature = "capture")]
+      capture: None
     }
   }
 
@@ -57,49 +114,370 @@ impl AnotherWorldEngine {
     FRAME_BUFFER_HEIGHT
   }
 
+  // format of get_frame_buffer: 0 rgba, 1 bgra, 2 rgb565, 3 indexed. Returns false if the format isn't valid
+  pub fn set_frame_format(&mut self, format: u8) -> bool {
+    match PixelFormat::from_code(format) {
+      Some(format) => {
+        self.frame_format = format;
+        true
+      },
+      None => false
+    }
+  }
+
   pub fn get_frame_buffer(&mut self) -> *const u8 {
-    let page = self.video.get_screen_page();
-    let palette_id = self.video.get_active_palette_id();
-    let palette = &self.resources_manager.get_file(self.virtual_machine.palette_file_id)[palette_id as usize * 32..]; // * 32 = 16 colors * 2 bytes per color
+    self.write_to_frame_buffer(self.video.get_screen_page_idx(), self.frame_format);
+    self.frame_buffer.as_ptr()
+  }
 
-    for i in 0..FRAME_BUFFER_HEIGHT * FRAME_BUFFER_WIDTH {
-      let color_idx = page[i as usize];
-      let c1 = palette[color_idx as usize * 2];
-      let c2 = palette[color_idx as usize * 2 + 1];
+  pub fn get_frame_buffer_pointer(&self) -> *const u8 {
+    self.frame_buffer.as_ptr()
+  }
 
-      let r = (((c1 & 0x0f) << 2) | ((c1 & 0x0f) >> 2)) << 2;
-      let g = (((c2 & 0xf0) >> 2) | ((c2 & 0xf0) >> 6)) << 2;
-      let b = (((c2 & 0x0f) >> 2) | ((c2 & 0x0f) << 2)) << 2;
+  // the scaled renderer draws the frames at the resolution (0 x 0 disables it)
+  pub fn enable_scaled_renderer(&mut self, width: u16, height: u16) {
+    self.video.enable_scaled_renderer(width as usize, height as usize);
+    self.scaled_frame_buffer = vec![0; width as usize * height as usize * 4];
+  }
 
-      self.shared_memory[i as usize * 4] = r;
-      self.shared_memory[i as usize * 4 + 1] = g;
-      self.shared_memory[i as usize * 4 + 2] = b;
-      self.shared_memory[i as usize * 4 + 3] = 0;
-    }
+  // the edges of the polygons drawn by the scaled renderer are smoothed, the original renderer doesn't change
+  pub fn set_scaled_anti_aliasing(&mut self, enabled: bool) {
+    self.video.set_scaled_anti_aliasing(enabled);
+  }
 
-    self.shared_memory.as_ptr()
+  // any page (0..3, or 0xfe and 0xff for the pages they are mapped to), with the color indexes or in rgba with the
+  // current palette
+  pub fn get_page_buffer(&mut self, page_id: u8, rgba: bool) -> *const u8 {
+    let page_idx = self.video.get_page_idx(page_id);
+
+    self.write_to_frame_buffer(page_idx, if rgba { PixelFormat::Rgba8888 } else { PixelFormat::Indexed });
+    self.frame_buffer.as_ptr()
+  }
+
+  // physical pages of the roles: [front buffer (0xfe) u8][backbuffer u8][background builder (0xff) u8]
+  pub fn build_pages_info(&mut self) {
+    let (frontbuffer_page_idx, backbuffer_page_idx, background_builder_page_idx) = self.video.get_page_roles();
+
+    self.shared_memory[0] = frontbuffer_page_idx as u8;
+    self.shared_memory[1] = backbuffer_page_idx as u8;
+    self.shared_memory[2] = background_builder_page_idx as u8;
+  }
+
+  // the frame drawn by the scaled renderer, in rgba. The buffer is too big for shared_memory, so it has its own
+  pub fn get_scaled_frame_buffer(&mut self) -> *const u8 {
+    self.update_color_table();
+    self.video.write_scaled_screen_rgba(&self.color_table.colors, &mut self.scaled_frame_buffer);
+
+    self.scaled_frame_buffer.as_ptr()
   }
 
   pub fn get_registers(&self) -> *const i16 {
     self.virtual_machine.registers.as_ptr()
   }
 
+  pub fn set_register(&mut self, reg_id: u8, value: i16) {
+    self.virtual_machine.set_register(reg_id, value);
+  }
+
+  pub fn freeze_register(&mut self, reg_id: u8, value: i16) {
+    self.virtual_machine.freeze_register(reg_id, value);
+  }
+
+  pub fn unfreeze_register(&mut self, reg_id: u8) {
+    self.virtual_machine.unfreeze_register(reg_id);
+  }
+
+  // [num frozen registers u16] [register u8][value i16] * num frozen registers
+  pub fn build_frozen_registers_info(&mut self) {
+    write_u16(&mut self.shared_memory, 0, self.virtual_machine.frozen_registers.len() as u16);
+
+    let mut idx = 2;
+
+    for (reg_id, value) in &self.virtual_machine.frozen_registers {
+      self.shared_memory[idx] = *reg_id;
+      write_u16(&mut self.shared_memory, idx + 1, *value as u16);
+      idx += 3;
+    }
+  }
+
+  // all the registers are candidates when the search starts
+  pub fn start_register_search(&mut self) {
+    self.register_search = Some(RegisterSearch::new(&self.virtual_machine.registers));
+  }
+
+  // condition: 0 changed, 1 unchanged, 2 equals value, 3 increased, 4 decreased. Returns the number of candidates left
+  pub fn filter_register_search(&mut self, condition: u8, value: i16) -> u16 {
+    let registers = &self.virtual_machine.registers;
+
+    match (&mut self.register_search, SearchCondition::from_code(condition, value)) {
+      (Some(search), Some(condition)) => search.filter(registers, condition) as u16,
+      (Some(search), None) => search.candidates.len() as u16,
+      (None, _) => 0
+    }
+  }
+
+  // [num candidates u16] [register u8][value i16] * num candidates
+  pub fn build_register_search_info(&mut self) {
+    let candidates = self.register_search.as_ref().map(|search| search.get_candidates()).unwrap_or_default();
+    let mut idx = 2;
+
+    write_u16(&mut self.shared_memory, 0, candidates.len() as u16);
+
+    for (reg_id, value) in candidates {
+      self.shared_memory[idx] = reg_id;
+      write_u16(&mut self.shared_memory, idx + 1, value as u16);
+      idx += 3;
+    }
+  }
+
+  // the symbols file (json) has to be copied to shared_memory before calling this method. Returns 0 if the file has
+  // been loaded, or the length of the error message written to shared_memory
+  pub fn load_symbols(&mut self, len: u32) -> u32 {
+    let result = std::str::from_utf8(&self.shared_memory[..len as usize]).map_err(|e| e.to_string()).and_then(SymbolTable::from_json);
+
+    match result {
+      Ok(symbols) => {
+        self.symbols = symbols;
+        0
+      },
+      Err(message) => self.write_text(&message)
+    }
+  }
+
+  pub fn build_symbols(&mut self) -> u32 {
+    let json = self.symbols.to_json();
+    self.write_text(&json)
+  }
+
+  pub fn build_registers_info(&mut self) {
+    let mut idx = 0;
+
+    for reg_id in 0..NUM_REGISTERS {
+      let (name, comment) = match self.symbols.registers.get(&(reg_id as u8)) {
+        Some(symbol) => (symbol.name.as_bytes(), symbol.comment.as_bytes()),
+        None => (&[][..], &[][..])
+      };
+
+      self.shared_memory[idx] = name.len() as u8;
+      idx += 1;
+
+      self.shared_memory[idx..idx + name.len()].copy_from_slice(name);
+      idx += name.len();
+
+      write_u16(&mut self.shared_memory, idx, comment.len() as u16);
+      idx += 2;
+
+      self.shared_memory[idx..idx + comment.len()].copy_from_slice(comment);
+      idx += comment.len();
+    }
+  }
+
+  // the bytes of the patch have to be copied to shared_memory before calling this method. Like the other patch
+  // methods, returns 0 on success or the length of the error message written to shared_memory
+  pub fn patch_bytes(&mut self, file_id: u8, offset: u16, len: u32) -> u32 {
+    let bytes = self.shared_memory[..len as usize].to_vec();
+    let result = self.patches.patch_bytes(&mut self.resources_manager, file_id, offset, &bytes);
+
+    self.write_result(result.map(|_| ()))
+  }
+
+  // the instruction (text) has to be copied to shared_memory before calling this method
+  pub fn patch_instruction(&mut self, script_id: u8, pc: u16, len: u32) -> u32 {
+    let result = std::str::from_utf8(&self.shared_memory[..len as usize]).map(|text| text.to_string()).map_err(|e| e.to_string())
+      .and_then(|text| self.patches.patch_instruction(&mut self.resources_manager, script_id, pc, &text));
+
+    self.write_result(result.map(|_| ()))
+  }
+
+  pub fn patch_nop(&mut self, script_id: u8, pc: u16) -> u32 {
+    let result = self.patches.patch_nop(&mut self.resources_manager, script_id, pc);
+    self.write_result(result.map(|_| ()))
+  }
+
+  pub fn revert_patch(&mut self, idx: u32) -> u32 {
+    let result = self.patches.revert(&mut self.resources_manager, idx as usize);
+    self.write_result(result)
+  }
+
+  pub fn revert_all_patches(&mut self) {
+    self.patches.revert_all(&mut self.resources_manager);
+  }
+
+  pub fn build_patches(&mut self) -> u32 {
+    let json = self.patches.to_json();
+    self.write_text(&json)
+  }
+
+  // the saved list (json) has to be copied to shared_memory before calling this method
+  pub fn apply_patches(&mut self, len: u32) -> u32 {
+    let result = std::str::from_utf8(&self.shared_memory[..len as usize]).map(|text| text.to_string()).map_err(|e| e.to_string())
+      .and_then(|text| self.patches.apply_json(&mut self.resources_manager, &text));
+
+    self.write_result(result)
+  }
+
   pub fn set_game_data(&mut self, game_data: &[u8]) {
     self.shared_memory[..game_data.len()].clone_from_slice(game_data);
   }
 
   pub fn vm_step(&mut self) -> u32 {
-    self.virtual_machine.step(&mut self.video, &self.resources_manager)
+    let action_requested = self.virtual_machine.step(&mut self.video, &self.resources_manager);
+
+    #[cfg(feature = "capture")]
+    self.capture_frame(action_requested);
+
+    action_requested
   }
 
+  // the game levels are numbered from 0 (the intro), and 0xff is the protection screen
   pub fn vm_restart(&mut self, level: u8) {
-    self.virtual_machine.restart_level(level)
+    self.virtual_machine.restart(self.checkpoints.get_level(level));
+  }
+
+  pub fn vm_restart_checkpoint(&mut self, idx: u16) {
+    if let Some(checkpoint) = self.checkpoints.checkpoints.get(idx as usize) {
+      self.virtual_machine.restart(checkpoint);
+    }
+  }
+
+  // [num parts u8] and then, for each part: [palette u8][script u8][polys 1 u8][polys 2 u8][name len u8][name]
+  pub fn build_parts_info(&mut self) {
+    let mut idx = 1;
+
+    self.shared_memory[0] = GAME_PARTS.len() as u8;
+
+    for part in GAME_PARTS.iter() {
+      let name = part.name().as_bytes();
+
+      self.shared_memory[idx..idx + 4].copy_from_slice(&part.file_ids());
+      self.shared_memory[idx + 4] = name.len() as u8;
+      idx += 5;
+
+      self.shared_memory[idx..idx + name.len()].copy_from_slice(name);
+      idx += name.len();
+    }
+  }
+
+  // [num checkpoints u16] and then, for each checkpoint: [part u8][name len u8][name][code len u8][code]
+  pub fn build_checkpoints_info(&mut self) {
+    let mut idx = 2;
+
+    write_u16(&mut self.shared_memory, 0, self.checkpoints.checkpoints.len() as u16);
+
+    for checkpoint in &self.checkpoints.checkpoints {
+      let name = checkpoint.name.as_bytes();
+      let code = checkpoint.code.as_deref().unwrap_or("").as_bytes();
+
+      self.shared_memory[idx] = checkpoint.part.index();
+      self.shared_memory[idx + 1] = name.len() as u8;
+      idx += 2;
+
+      self.shared_memory[idx..idx + name.len()].copy_from_slice(name);
+      idx += name.len();
+
+      self.shared_memory[idx] = code.len() as u8;
+      idx += 1;
+
+      self.shared_memory[idx..idx + code.len()].copy_from_slice(code);
+      idx += code.len();
+    }
+  }
+
+  // restarts the game in the checkpoint of the code (copied to shared_memory before calling this method). Returns 0
+  // or the length of the error message
+  pub fn enter_level_code(&mut self, len: u32) -> u32 {
+    let result = std::str::from_utf8(&self.shared_memory[..len as usize]).map_err(|e| e.to_string())
+      .and_then(|code| self.checkpoints.find_code(code).ok_or_else(|| format!("invalid code '{}'", code)));
+
+    match result {
+      Ok(idx) => {
+        self.vm_restart_checkpoint(idx as u16);
+        0
+      },
+      Err(message) => self.write_text(&message)
+    }
+  }
+
+  // adds a checkpoint with the current part and registers. The name has to be copied to shared_memory before calling
+  // this method. Like the other checkpoint methods, returns 0 on success or the length of the error message
+  pub fn add_checkpoint(&mut self, len: u32) -> u32 {
+    let registers = self.virtual_machine.registers.iter().enumerate()
+      .filter(|(_, value)| **value != 0)
+      .map(|(reg_id, value)| (reg_id as u8, *value))
+      .collect();
+
+    let result = std::str::from_utf8(&self.shared_memory[..len as usize]).map(|name| name.to_string()).map_err(|e| e.to_string())
+      .and_then(|name| {
+        let part = self.virtual_machine.get_current_part().ok_or("the current part is unknown")?;
+        self.checkpoints.add(Checkpoint { name, part, code: None, registers })
+      });
+
+    self.write_result(result.map(|_| ()))
+  }
+
+  // the checkpoints file (json) has to be copied to shared_memory before calling this method
+  pub fn load_checkpoints(&mut self, len: u32) -> u32 {
+    let result = std::str::from_utf8(&self.shared_memory[..len as usize]).map(|text| text.to_string()).map_err(|e| e.to_string())
+      .and_then(|text| self.checkpoints.load_json(&text));
+
+    self.write_result(result)
+  }
+
+  pub fn build_checkpoints(&mut self) -> u32 {
+    let json = self.checkpoints.to_json();
+    self.write_text(&json)
   }
 
   pub fn vm_get_current_pc(&self) -> u16 {
     self.virtual_machine.get_current_pc()
   }
 
+  // the thread methods change the threads in the next frame, like SETVEC and RESET. They return 0 on success or the
+  // length of the error message written to shared_memory
+  pub fn set_thread_pc(&mut self, thread_id: u8, addr: u16) -> u32 {
+    let result = self.check_script_addr(addr).and_then(|_| self.virtual_machine.set_thread_pc(thread_id, addr));
+    self.write_result(result)
+  }
+
+  pub fn start_thread(&mut self, thread_id: u8, addr: u16) -> u32 {
+    let result = self.check_script_addr(addr).and_then(|_| self.virtual_machine.start_thread(thread_id, addr));
+    self.write_result(result)
+  }
+
+  pub fn freeze_thread(&mut self, thread_id: u8, frozen: bool) -> u32 {
+    let result = self.virtual_machine.freeze_thread(thread_id, frozen);
+    self.write_result(result)
+  }
+
+  pub fn kill_thread(&mut self, thread_id: u8) -> u32 {
+    let result = self.virtual_machine.kill_thread(thread_id);
+    self.write_result(result)
+  }
+
+  pub fn set_instruction_budget(&mut self, slice_budget: u32, frame_budget: u32) {
+    self.virtual_machine.set_instruction_budget(slice_budget, frame_budget);
+  }
+
+  pub fn vm_force_yield(&mut self) {
+    self.virtual_machine.force_yield();
+  }
+
+  // last budget exceeded: [kind u8: 0 none, 1 slice, 2 frame][thread u8][pc u16][instructions u32]
+  pub fn build_budget_exceeded_info(&mut self) {
+    match self.virtual_machine.last_budget_exceeded {
+      Some(exceeded) => {
+        self.shared_memory[0] = match exceeded.kind {
+          BudgetKind::Slice => 1,
+          BudgetKind::Frame => 2
+        };
+        self.shared_memory[1] = exceeded.thread_id;
+        write_u16(&mut self.shared_memory, 2, exceeded.pc);
+        write_u32(&mut self.shared_memory, 4, exceeded.instructions);
+      },
+      None => self.shared_memory[..8].fill(0)
+    }
+  }
+
   pub fn on_key_down(&mut self, key: u8) {
     self.virtual_machine.on_key_down(key);
   }
@@ -108,6 +486,10 @@ impl AnotherWorldEngine {
     self.virtual_machine.on_key_up(key);
   }
 
+  pub fn on_char(&mut self, c: u8) {
+    self.virtual_machine.on_char(c);
+  }
+
   pub fn get_shared_memory_pointer(&self) -> *const u8 {
     self.shared_memory.as_ptr()
   }
@@ -138,15 +520,170 @@ impl AnotherWorldEngine {
     }
   }
 
+  // [active thread u16][stack depth u16] and then, for each thread:
+  // [pc u16][next pc u16][last yield pc u16][flags u8: bit 0 = active, bit 1 = next active]
   pub fn build_threads_info(&mut self) {
     write_u16(&mut self.shared_memory, 0, self.virtual_machine.active_thread as u16);
+    write_u16(&mut self.shared_memory, 2, self.virtual_machine.get_stack_depth() as u16);
 
-    let mut idx = 2;
+    let mut idx = 4;
 
     for i in 0..NUM_THREADS {
-      write_u16(&mut self.shared_memory, idx, self.virtual_machine.threads[i].pc);
-      idx += 2;
+      let thread = &self.virtual_machine.threads[i];
+
+      write_u16(&mut self.shared_memory, idx, thread.pc);
+      write_u16(&mut self.shared_memory, idx + 2, thread.next_pc);
+      write_u16(&mut self.shared_memory, idx + 4, thread.last_yield_pc);
+      self.shared_memory[idx + 6] = thread.active as u8 | (thread.next_active as u8) << 1;
+      idx += 7;
+    }
+  }
+
+  pub fn enable_draw_recorder(&mut self, enabled: bool) {
+    self.video.enable_draw_recorder(enabled);
+  }
+
+  // draw commands of the current frame (or the last one shown): [num commands u16] and then, for each command:
+  // [kind u8: 0 fill, 1 copy, 2 poly, 3 string, 4 bitmap][thread u8][pc u16][page u8][color u8][poly buffer or source page u8]
+  // [offset, string id or resource id u16][x i16][y i16][zoom or vscroll i16]
+  pub fn build_draw_list_info(&mut self, last: bool) {
+    let mut idx = 2;
+    let mut num_written = 0;
+
+    if let Some(frame) = self.video.draw_recorder.as_ref().and_then(|recorder| recorder.get_frame(last)) {
+      for entry in &frame.entries {
+        let (color_idx, buffer, data, x, y, zoom) = match entry.command {
+          DrawCommand::FillPage { color_idx } => (color_idx, 0, 0, 0, 0, 0),
+          DrawCommand::CopyPage { src_page_idx, vscroll } => (0, src_page_idx, 0, 0, 0, vscroll),
+          DrawCommand::Poly { poly_buffer, offset, x, y, zoom, .. } => (0xff, poly_buffer, offset, x, y, zoom),
+          DrawCommand::String { string_id, x, y, color_idx } => (color_idx, 0, string_id, x, y, 0),
+          DrawCommand::Bitmap { resource_id } => (0, 0, resource_id as u16, 0, 0, 0)
+        };
+
+        self.shared_memory[idx] = entry.command.kind();
+        self.shared_memory[idx + 1] = entry.thread_id;
+        write_u16(&mut self.shared_memory, idx + 2, entry.pc);
+        self.shared_memory[idx + 4] = entry.page_idx;
+        self.shared_memory[idx + 5] = color_idx;
+        self.shared_memory[idx + 6] = buffer;
+        write_u16(&mut self.shared_memory, idx + 7, data);
+        write_u16(&mut self.shared_memory, idx + 9, x as u16);
+        write_u16(&mut self.shared_memory, idx + 11, y as u16);
+        write_u16(&mut self.shared_memory, idx + 13, zoom as u16);
+        idx += 15;
+        num_written += 1;
+      }
     }
+
+    write_u16(&mut self.shared_memory, 0, num_written);
+  }
+
+  // draws the first num_commands commands of the frame (see build_draw_list_info) and writes the page of the last one
+  // in the frame buffer (in rgba, with the current palette). Returns the page, or 0xff if there isn't a recorded frame
+  pub fn replay_draw_list(&mut self, last: bool, num_commands: u16) -> u8 {
+    let frame = match self.video.draw_recorder.as_ref().and_then(|recorder| recorder.get_frame(last)) {
+      Some(frame) => frame,
+      None => return 0xff
+    };
+
+    let mut video = Video::new();
+    let page_idx = video.replay_draw_frame(frame, num_commands as usize, &self.resources_manager);
+
+    self.update_color_table();
+    self.color_table.write_pixels(video.get_page(page_idx), PixelFormat::Rgba8888, &mut self.frame_buffer);
+
+    page_idx as u8
+  }
+
+  // svg of the page page_id built from all the commands of the frame, with the current palette. Returns the length of
+  // the svg, or 0 if there isn't a recorded frame or the svg doesn't fit in the shared memory
+  pub fn build_frame_svg(&mut self, last: bool, page_id: u8) -> u32 {
+    self.update_color_table();
+
+    let frame = match self.video.draw_recorder.as_ref().and_then(|recorder| recorder.get_frame(last)) {
+      Some(frame) => frame,
+      None => return 0
+    };
+
+    let svg = frame_to_svg(frame, page_id as usize & 3, &self.resources_manager, &self.color_table.colors);
+    self.write_text(&svg)
+  }
+
+  pub fn enable_pixel_owners(&mut self, enabled: bool) {
+    self.video.enable_pixel_owners(enabled);
+  }
+
+  // the command that drew the pixel of the page: [kind u8 (see build_draw_list_info)][thread u8][pc u16][poly offset u16].
+  // Returns false if the pixel hasn't been drawn since the owners were enabled
+  pub fn who_drew(&mut self, page_id: u8, x: u16, y: u16) -> bool {
+    match self.video.who_drew(page_id, x, y) {
+      Some(owner) => {
+        self.shared_memory[0] = owner.kind;
+        self.shared_memory[1] = owner.thread_id;
+        write_u16(&mut self.shared_memory, 2, owner.pc);
+        write_u16(&mut self.shared_memory, 4, owner.poly_offset);
+        true
+      },
+      None => false
+    }
+  }
+
+  // the page shown by get_frame_buffer
+  pub fn get_screen_page(&self) -> u8 {
+    self.video.get_screen_page_idx() as u8
+  }
+
+  pub fn enable_timeline(&mut self, enabled: bool) {
+    self.virtual_machine.enable_timeline(enabled);
+  }
+
+  // timeline of the last frames (up to num_frames): [num frames u16] and then, for each frame:
+  // [frame u32][num runs u8] [thread u8][start pc u16][end pc u16][instructions u32] * num runs
+  // [num changes u16] [thread u8][by thread u8][pc u16][kind u8: 0 setvec, 1 resume, 2 pause, 3 kill][addr u16] * num changes
+  pub fn build_threads_timeline_info(&mut self, num_frames: u16) {
+    let mut idx = 2;
+    let mut num_written = 0;
+
+    if let Some(timeline) = &self.virtual_machine.timeline {
+      let first = timeline.frames.len().saturating_sub(num_frames as usize);
+
+      for frame in timeline.frames.iter().skip(first) {
+        write_u32(&mut self.shared_memory, idx, frame.frame);
+        self.shared_memory[idx + 4] = frame.runs.len() as u8;
+        idx += 5;
+
+        for run in &frame.runs {
+          self.shared_memory[idx] = run.thread_id;
+          write_u16(&mut self.shared_memory, idx + 1, run.start_pc);
+          write_u16(&mut self.shared_memory, idx + 3, run.end_pc);
+          write_u32(&mut self.shared_memory, idx + 5, run.instructions);
+          idx += 9;
+        }
+
+        write_u16(&mut self.shared_memory, idx, frame.changes.len() as u16);
+        idx += 2;
+
+        for change in &frame.changes {
+          let (kind, addr) = match change.kind {
+            ThreadChangeKind::SetVec(addr) => (0, addr),
+            ThreadChangeKind::Resume => (1, 0),
+            ThreadChangeKind::Pause => (2, 0),
+            ThreadChangeKind::Kill => (3, 0)
+          };
+
+          self.shared_memory[idx] = change.thread_id;
+          self.shared_memory[idx + 1] = change.by_thread_id;
+          write_u16(&mut self.shared_memory, idx + 2, change.pc);
+          self.shared_memory[idx + 4] = kind;
+          write_u16(&mut self.shared_memory, idx + 5, addr);
+          idx += 7;
+        }
+
+        num_written += 1;
+      }
+    }
+
+    write_u16(&mut self.shared_memory, 0, num_written);
   }
 
   pub fn get_active_script_file_id(&self) -> u8 {
@@ -165,6 +702,216 @@ impl AnotherWorldEngine {
     }
   }
 
+  // svg of the poly at offset of the file, with the palette palette_id of the palettes file. Returns the length of the
+  // svg, or 0 if the poly is broken or a file or the palette doesn't exist
+  pub fn build_poly_svg(&mut self, file_id: u8, offset: u16, zoom: i16, palettes_file_id: u8, palette_id: u8) -> u32 {
+    let files = &self.resources_manager.files;
+    let palette_offset = palette_id as usize * 32;
+    let palette = files.get(palettes_file_id as usize).and_then(|file| file.content.get(palette_offset..palette_offset + 32));
+
+    let (poly_buffer, palette) = match (files.get(file_id as usize), palette) {
+      (Some(file), Some(palette)) => (&file.content, palette),
+      _ => return 0
+    };
+
+    let mut color_table = ColorTable::new();
+    color_table.set_palette(palette);
+
+    match shape_to_svg(poly_buffer, offset, zoom, &color_table.colors) {
+      Ok(svg) => self.write_text(&svg),
+      Err(_) => 0
+    }
+  }
+
+  pub fn build_script_listing(&mut self, script_id: u8) -> u32 {
+    let script = self.resources_manager.get_file(script_id);
+    let mut control_flow = ControlFlow::new(script);
+
+    if let Some(part_symbols) = self.symbols.get_script_symbols(script_id) {
+      control_flow.apply_part_symbols(part_symbols);
+    }
+
+    let listing = control_flow.build_listing(script, &self.virtual_machine.opcodes);
+
+    self.write_text(&listing)
+  }
+
+  pub fn build_script_cfg(&mut self, script_id: u8) -> u32 {
+    let script = self.resources_manager.get_file(script_id);
+    let dot = ControlFlow::new(script).build_dot(script, &self.virtual_machine.opcodes);
+
+    self.write_text(&dot)
+  }
+
+  pub fn build_script_pseudocode(&mut self, script_id: u8) -> u32 {
+    let pseudocode = decompile(self.resources_manager.get_file(script_id), &self.symbols, self.symbols.get_script_symbols(script_id));
+
+    self.write_text(&pseudocode)
+  }
+
+  pub fn build_script_labels_info(&mut self, script_id: u8) {
+    let mut control_flow = ControlFlow::new(self.resources_manager.get_file(script_id));
+
+    if let Some(part_symbols) = self.symbols.get_script_symbols(script_id) {
+      control_flow.apply_part_symbols(part_symbols);
+    }
+    let mut idx = 2;
+
+    write_u16(&mut self.shared_memory, 0, control_flow.labels.len() as u16);
+
+    for (addr, label) in &control_flow.labels {
+      write_u16(&mut self.shared_memory, idx, *addr);
+      idx += 2;
+
+      self.shared_memory[idx] = label.len() as u8;
+      idx += 1;
+
+      self.shared_memory[idx..idx + label.len()].copy_from_slice(label.as_bytes());
+      idx += label.len();
+
+      let xrefs = control_flow.xrefs.get(addr).map(|x| x.as_slice()).unwrap_or(&[]);
+
+      write_u16(&mut self.shared_memory, idx, xrefs.len() as u16);
+      idx += 2;
+
+      for xref in xrefs {
+        write_u16(&mut self.shared_memory, idx, xref.from);
+        self.shared_memory[idx + 2] = match xref.kind {
+          XrefKind::Jump => 0,
+          XrefKind::ConditionalJump => 1,
+          XrefKind::Call => 2,
+          XrefKind::SetVec => 3
+        };
+        idx += 3;
+      }
+    }
+  }
+
+  pub fn enable_profiler(&mut self, enabled: bool) {
+    self.virtual_machine.enable_profiler(enabled);
+  }
+
+  pub fn build_coverage_report(&mut self, script_id: u8) -> u32 {
+    let report = match &self.virtual_machine.profiler {
+      Some(profiler) => profiler.build_coverage_report(script_id, self.resources_manager.get_file(script_id), &self.virtual_machine.opcodes, &self.symbols),
+      None => String::new()
+    };
+
+    self.write_text(&report)
+  }
+
+  pub fn build_threads_cost_report(&mut self) -> u32 {
+    let report = match &self.virtual_machine.profiler {
+      Some(profiler) => profiler.build_threads_cost_report(),
+      None => String::new()
+    };
+
+    self.write_text(&report)
+  }
+
+  // executions of every instruction of the script: [num entries u16] and then, for each entry, [pc u16][executions u32]
+  pub fn build_coverage_info(&mut self, script_id: u8) {
+    let mut idx = 2;
+    let mut num_entries = 0;
+
+    if let Some(executions) = self.virtual_machine.profiler.as_ref().and_then(|profiler| profiler.executions.get(&script_id)) {
+      for (pc, count) in executions {
+        write_u16(&mut self.shared_memory, idx, *pc);
+        write_u32(&mut self.shared_memory, idx + 2, *count);
+        idx += 6;
+        num_entries += 1;
+      }
+    }
+
+    write_u16(&mut self.shared_memory, 0, num_entries);
+  }
+
+  // for each thread: [instructions u32][frames u32][max instructions in a frame u32][instructions in the last frame u32]
+  pub fn build_threads_cost_info(&mut self) {
+    let mut idx = 0;
+
+    if let Some(profiler) = &self.virtual_machine.profiler {
+      let last_frame = profiler.frames_history.back().copied().unwrap_or([0; NUM_THREADS]);
+
+      for (thread_id, cost) in profiler.thread_costs.iter().enumerate() {
+        write_u32(&mut self.shared_memory, idx, cost.instructions);
+        write_u32(&mut self.shared_memory, idx + 4, cost.frames);
+        write_u32(&mut self.shared_memory, idx + 8, cost.max_instructions);
+        write_u32(&mut self.shared_memory, idx + 12, last_frame[thread_id]);
+        idx += 16;
+      }
+    } else {
+      self.shared_memory[..NUM_THREADS * 16].fill(0);
+    }
+  }
+
+  // problems found by the verifier in the script. The poly buffers are the ones of the part that runs the script
+  pub fn build_script_errors_info(&mut self, script_id: u8) {
+    let file_ids = PARTS_FILE_IDS.iter().find(|ids| ids[1] == script_id);
+    let poly_buffer_1 = file_ids.map(|ids| self.resources_manager.get_file(ids[2]));
+    let poly_buffer_2 = file_ids.filter(|ids| ids[3] != 0).map(|ids| self.resources_manager.get_file(ids[3]));
+    let errors = verify(self.resources_manager.get_file(script_id), poly_buffer_1, poly_buffer_2, self.resources_manager.files.len());
+    let mut idx = 2;
+
+    write_u16(&mut self.shared_memory, 0, errors.len() as u16);
+
+    for error in &errors {
+      let message = error.message.as_bytes();
+
+      write_u16(&mut self.shared_memory, idx, error.pc);
+      idx += 2;
+
+      self.shared_memory[idx] = message.len() as u8;
+      idx += 1;
+
+      self.shared_memory[idx..idx + message.len()].copy_from_slice(message);
+      idx += message.len();
+    }
+  }
+
+  // there has to be a valid instruction at the address in the active script
+  fn check_script_addr(&self, addr: u16) -> Result<(), String> {
+    let script = self.resources_manager.get_file(self.virtual_machine.script_file_id);
+
+    match decode(script, addr) {
+      Some(_) => Ok(()),
+      None => Err(format!("there is no valid instruction at {:04X}", addr))
+    }
+  }
+
+  fn write_result(&mut self, result: Result<(), String>) -> u32 {
+    match result {
+      Ok(()) => 0,
+      Err(message) => self.write_text(&message)
+    }
+  }
+
+  // returns the length of the text, or 0 if it doesn't fit in the shared memory
+  fn write_text(&mut self, text: &str) -> u32 {
+    let bytes = text.as_bytes();
+
+    if bytes.len() > SHARED_MEMORY_SIZE {
+      return 0;
+    }
+
+    self.shared_memory[..bytes.len()].copy_from_slice(bytes);
+
+    bytes.len() as u32
+  }
+
+  fn write_to_frame_buffer(&mut self, page_idx: usize, format: PixelFormat) {
+    self.update_color_table();
+    self.color_table.write_pixels(self.video.get_page(page_idx), format, &mut self.frame_buffer);
+  }
+
+  // the colors of the active palette
+  fn update_color_table(&mut self) {
+    let palette_id = self.video.get_active_palette_id();
+    let palette = &self.resources_manager.get_file(self.virtual_machine.palette_file_id)[palette_id as usize * 32..]; // * 32 = 16 colors * 2 bytes per color
+
+    self.color_table.set_palette(palette);
+  }
+
   fn build_palettes_info(&mut self, palettes_id: u8, idx: usize) -> usize {
     let palettes = self.resources_manager.get_file(palettes_id);
     let palettes_len = palettes.len();
@@ -177,12 +924,7 @@ impl AnotherWorldEngine {
 
     while i < palettes_len {
       // palettes are 16 colors and each color has 2 bytes
-      let c1 = palettes[i];
-      let c2 = palettes[i + 1];
-
-      self.shared_memory[my_idx] = (((c1 & 0x0f) << 2) | ((c1 & 0x0f) >> 2)) << 2;
-      self.shared_memory[my_idx + 1] = (((c2 & 0xf0) >> 2) | ((c2 & 0xf0) >> 6)) << 2;
-      self.shared_memory[my_idx + 2] = (((c2 & 0x0f) >> 2) | ((c2 & 0x0f) << 2)) << 2;
+      self.shared_memory[my_idx..my_idx + 3].copy_from_slice(&palette_color_to_rgb(palettes[i], palettes[i + 1]));
 
       num_colors += 1;
 
@@ -206,6 +948,7 @@ impl AnotherWorldEngine {
     let mut pc: u16 = 0;
     let mut num_entries: u16 = 0;
     let mut my_idx = idx;
+    let part_symbols = self.symbols.get_script_symbols(script_id);
 
     my_idx += 2; // the first 2 bytes are the num of entries in the array
 
@@ -218,7 +961,7 @@ impl AnotherWorldEngine {
       pc += 1;
 
       let opcode = self.virtual_machine.opcodes.get(opcode_value);
-      let asm_code = (opcode.get_asm_code)(pc, script);
+      let asm_code = self.symbols.format_asm_code(&(opcode.get_asm_code)(pc, script));
       let asm_code_as_bytes = asm_code.as_bytes();
 
       self.shared_memory[my_idx] = asm_code_as_bytes.len() as u8;
@@ -227,6 +970,22 @@ impl AnotherWorldEngine {
       self.shared_memory[my_idx..my_idx + asm_code_as_bytes.len()].copy_from_slice(asm_code_as_bytes);
       my_idx += asm_code_as_bytes.len();
 
+      // label and comment of the symbols file
+      let label = part_symbols.and_then(|symbols| symbols.labels.get(&(pc - 1))).map(|label| label.as_bytes()).unwrap_or(&[]);
+      let comment = part_symbols.and_then(|symbols| symbols.comments.get(&(pc - 1))).map(|comment| comment.as_bytes()).unwrap_or(&[]);
+
+      self.shared_memory[my_idx] = label.len() as u8;
+      my_idx += 1;
+
+      self.shared_memory[my_idx..my_idx + label.len()].copy_from_slice(label);
+      my_idx += label.len();
+
+      write_u16(&mut self.shared_memory, my_idx, comment.len() as u16);
+      my_idx += 2;
+
+      self.shared_memory[my_idx..my_idx + comment.len()].copy_from_slice(comment);
+      my_idx += comment.len();
+
       pc += ((opcode.len)(pc, script) - 1) as u16;
       num_entries += 1;
     }
@@ -235,4 +994,68 @@ impl AnotherWorldEngine {
 
     my_idx - idx
   }
-}
\ No newline at end of file
+}
+
+// methods for the rust hosts, they can't be exported to javascript
+impl AnotherWorldEngine {
+  pub fn add_observer(&mut self, observer: Box<dyn VmObserver>) {
+    self.virtual_machine.add_observer(observer);
+  }
+
+  pub fn remove_observers(&mut self) {
+    self.virtual_machine.remove_observers();
+  }
+
+  pub fn get_checkpoints(&mut self) -> &mut CheckpointTable {
+    &mut self.checkpoints
+  }
+
+  // writes the screen to output, with stride bytes from the start of a row to the next one. Nothing is written if the
+  // output is too small for the rows
+  pub fn write_frame(&mut self, output: &mut [u8], stride: usize, format: PixelFormat) -> Result<(), String> {
+    self.write_page_rows(self.video.get_screen_page_idx(), output, stride, format)
+  }
+
+  // any page (0..3, or 0xfe and 0xff for the pages they are mapped to)
+  pub fn write_page(&mut self, page_id: u8, output: &mut [u8], stride: usize, format: PixelFormat) -> Result<(), String> {
+    self.write_page_rows(self.video.get_page_idx(page_id), output, stride, format)
+  }
+
+  // captures the frames shown from now (numbered from 0), until last_frame if there is one
+  #[cfg(feature = "capture")]
+  pub fn start_capture(&mut self, first_frame: u32, last_frame: Option<u32>) {
+    self.capture = Some(FrameCapture::new(first_frame, last_frame));
+  }
+
+  // the frames captured, that can be saved to files
+  #[cfg(feature = "capture")]
+  pub fn stop_capture(&mut self) -> Option<FrameCapture> {
+    self.capture.take()
+  }
+
+  #[cfg(feature = "capture")]
+  pub fn is_capture_finished(&self) -> bool {
+    self.capture.as_ref().is_some_and(|capture| capture.is_finished())
+  }
+
+  // the frame is shown for the pause of the BLIT
+  #[cfg(feature = "capture")]
+  fn capture_frame(&mut self, action_requested: u32) {
+    if self.capture.is_none() || (action_requested >> 24) as u8 != ActionRequest::Blit as u8 {
+      return;
+    }
+
+    let duration = pause_to_duration(self.virtual_machine.registers[ScriptRegs::PauseSlices as usize]);
+
+    self.update_color_table();
+
+    if let Some(capture) = &mut self.capture {
+      capture.add_frame(self.video.get_screen_page(), &self.color_table.colors, duration);
+    }
+  }
+
+  fn write_page_rows(&mut self, page_idx: usize, output: &mut [u8], stride: usize, format: PixelFormat) -> Result<(), String> {
+    self.update_color_table();
+    self.color_table.write_rows(self.video.get_page(page_idx), FRAME_BUFFER_WIDTH as usize, format, output, stride)
+  }
+}
@@ -0,0 +1,22 @@
+use crate::checkpoints::GamePart;
+
+// Events of the virtual machine for the hosts that embed the engine (tracers, profilers, autosplitters...). The
+// observers are registered with AnotherWorldEngine::add_observer, and all the callbacks do nothing by default, so an
+// observer only implements the ones it needs. They are called while the vm executes the instruction, so they should
+// be fast (on_instruction is called for every instruction).
+
+pub trait VmObserver {
+  fn on_instruction(&mut self, _script_file_id: u8, _thread_id: u8, _pc: u16) {}
+
+  // the threads start and are killed at the beginning of a frame (SETVEC and RESET take effect in the next frame),
+  // but for the KILL instruction, that kills the thread that executes it
+  fn on_thread_started(&mut self, _thread_id: u8, _pc: u16) {}
+  fn on_thread_killed(&mut self, _thread_id: u8) {}
+
+  fn on_blit(&mut self, _page_id: u8) {}
+  fn on_palette_change(&mut self, _palette_id: u8) {}
+  fn on_sound(&mut self, _resource_id: u8, _freq: u8, _volume: u8, _channel: u8) {}
+  fn on_music(&mut self, _resource_id: u16, _delay: u16, _pos: u8) {}
+  fn on_part_load(&mut self, _part: GamePart) {}
+  fn on_bitmap_load(&mut self, _resource_id: u8) {}
+}
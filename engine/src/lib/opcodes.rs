@@ -3,12 +3,17 @@ use crate::virtual_machine::{VirtualMachine, ScriptRegs};
 use crate::video::Video;
 use crate::defines::{INACTIVE_THREAD, NUM_THREADS, BASE_PART_ID};
 use crate::utils::{read_u8, read_u16, read_i16};
+use crate::timeline::ThreadChangeKind;
+use crate::checkpoints::GamePart;
+use crate::instruction::{Instruction, decode, get_short_draw_poly_bytes};
 
 pub enum ActionRequest {
   YieldThread   = 1,
   Blit          = 2,
   LoadPart      = 3,
-  PlaySound     = 4
+  PlaySound     = 4,
+  BudgetExceeded = 5,
+  InvalidPart   = 6
 }
 
 pub struct Opcode {
@@ -21,7 +26,7 @@ pub struct Opcodes {
   opcodes: Vec<Opcode>
 }
 
-fn build_action_request(action: ActionRequest, param: u8) -> u32 {
+pub fn build_action_request(action: ActionRequest, param: u8) -> u32 {
   (action as u32) << 24 | (param as u32) << 16
 }
 
@@ -120,6 +125,7 @@ impl Opcodes {
           let target_thread_id = read_u8(script, pc);
           let addr = read_u16(script, pc + 1);
           vm.threads[target_thread_id as usize].next_pc = addr;
+          vm.add_thread_change(target_thread_id, thread_id, ThreadChangeKind::SetVec(addr));
 
           0
         }
@@ -211,6 +217,7 @@ impl Opcodes {
           let pc = vm.threads[thread_id as usize].pc;
           let palette_id = read_u16(script, pc) >> 8;
           video.set_palette(palette_id as u8);
+          vm.notify(|observer| observer.on_palette_change(palette_id as u8));
 
           0
         }
@@ -230,8 +237,8 @@ impl Opcodes {
 
           format!("RESET {:02X}, {:02X}, {:}", origin_thread_id, target_thread_id, action)
         },
-        exec: |vm: &mut VirtualMachine, _resources_manager: &ResourcesManager, _video: &mut Video, thread_id: u8, script: &[u8], _poly_buffer_1: &[u8], _poly_buffer_2: &[u8]| -> u32 {
-          let pc = vm.threads[thread_id as usize].pc;
+        exec: |vm: &mut VirtualMachine, _resources_manager: &ResourcesManager, _video: &mut Video, by_thread_id: u8, script: &[u8], _poly_buffer_1: &[u8], _poly_buffer_2: &[u8]| -> u32 {
+          let pc = vm.threads[by_thread_id as usize].pc;
           let thread_id = read_u8(script, pc);
           let mut i = read_u8(script, pc + 1);
 
@@ -242,10 +249,12 @@ impl Opcodes {
           if action == 2 {
             for t in thread_id..(thread_id + n) {
               vm.threads[t as usize].next_pc = INACTIVE_THREAD - 1;
+              vm.add_thread_change(t, by_thread_id, ThreadChangeKind::Kill);
             }
           } else if action < 2 {
             for t in thread_id..(thread_id + n) {
               vm.threads[t as usize].next_active = if action == 0 { false } else { true };
+              vm.add_thread_change(t, by_thread_id, if action == 0 { ThreadChangeKind::Resume } else { ThreadChangeKind::Pause });
             }
           }
           0
@@ -287,6 +296,7 @@ impl Opcodes {
 
           vm.registers[0xf7] = 0;
           video.blit(page_id);
+          vm.notify(|observer| observer.on_blit(page_id));
 
           build_action_request(ActionRequest::Blit, (vm.registers[ScriptRegs::PauseSlices as usize] * 20) as u8)
         }
@@ -360,7 +370,10 @@ impl Opcodes {
         get_asm_code: |pc: u16, script: &[u8]| { format!("SND {:04X}, {:02X}, {:02X}, {:02X}", read_u16(script, pc), read_u8(script, pc + 2), read_u8(script, pc + 3), read_u8(script, pc + 4)) },
         exec: |vm: &mut VirtualMachine, _resources_manager: &ResourcesManager, _video: &mut Video, thread_id: u8, script: &[u8], _poly_buffer_1: &[u8], _poly_buffer_2: &[u8]| -> u32 {
           let pc = vm.threads[thread_id as usize].pc;
-          build_play_sound_action_request(read_u16(script, pc) as u8, read_u8(script, pc + 2), read_u8(script, pc + 3), read_u8(script, pc + 4))
+          let (resource_id, freq, volume, channel) = (read_u16(script, pc) as u8, read_u8(script, pc + 2), read_u8(script, pc + 3), read_u8(script, pc + 4));
+
+          vm.notify(|observer| observer.on_sound(resource_id, freq, volume, channel));
+          build_play_sound_action_request(resource_id, freq, volume, channel)
         }
       },
       Opcode {
@@ -372,15 +385,20 @@ impl Opcodes {
           // all the resources are already loaded in memory, so here we check:
           // 1. if it's trying to load a game part
           if resource_id > 0xff {
-            let part = (resource_id - BASE_PART_ID) as u8;
-            vm.set_next_part_to_load(part);
-
-            return build_action_request(ActionRequest::LoadPart, part);
+            // the parts that don't exist are reported to the host with the resource id, and the instruction is ignored
+            return match resource_id.checked_sub(BASE_PART_ID).filter(|part| *part <= 0xff).and_then(|part| GamePart::from_index(part as u8)) {
+              Some(game_part) => {
+                vm.set_next_part_to_load(game_part);
+                build_action_request(ActionRequest::LoadPart, game_part.index())
+              },
+              None => build_action_request(ActionRequest::InvalidPart, thread_id) | resource_id as u32
+            };
           }
 
           // 2. if it's trying to load a bitmap. In this case, the bitmap is copied to page 0
           if resources_manager.get_file_type(resource_id as u8) == 0x2 {
-            video.draw_bitmap(resources_manager.get_file(resource_id as u8));
+            video.draw_bitmap(resources_manager.get_file(resource_id as u8), resource_id as u8);
+            vm.notify(|observer| observer.on_bitmap_load(resource_id as u8));
           }
 
           0
@@ -389,8 +407,12 @@ impl Opcodes {
       Opcode {
         len: |_pc: u16, _script: &[u8]| { 6 },
         get_asm_code: |pc: u16, script: &[u8]| { format!("MUSIC {:04X}, {:04X}, {:02X}", read_u16(script, pc), read_u16(script, pc + 2), read_u8(script, pc + 4)) },
-        exec: |_vm: &mut VirtualMachine, _resources_manager: &ResourcesManager, _video: &mut Video, _thread_id: u8, _script: &[u8], _poly_buffer_1: &[u8], _poly_buffer_2: &[u8]| -> u32 {
+        exec: |vm: &mut VirtualMachine, _resources_manager: &ResourcesManager, _video: &mut Video, thread_id: u8, script: &[u8], _poly_buffer_1: &[u8], _poly_buffer_2: &[u8]| -> u32 {
+          let pc = vm.threads[thread_id as usize].pc;
+          let (resource_id, delay, pos) = (read_u16(script, pc), read_u16(script, pc + 2), read_u8(script, pc + 4));
+
           // TODO: to implement
+          vm.notify(|observer| observer.on_music(resource_id, delay, pos));
           0
         }
       }
@@ -405,13 +427,13 @@ impl Opcodes {
         offset |= read_u8(script, pc) as u16;
         offset = ((offset as u32) * 2) as u16;
 
-        let mut x = read_u8(script, pc + 1) as i16;
-        let mut y = read_u8(script, pc + 2) as i16;
-        let h = y - 199;
+        let x = read_u8(script, pc + 1);
+        let y = read_u8(script, pc + 2);
 
-        if h > 0 {
-          y = 199;
-          x += h;
+        // with y above 199 the poly is drawn at (x + y - 199, 199). Other bytes draw at the same position, so the
+        // bytes are shown as they are
+        if y > 199 {
+          return format!("DRAWPOLYS {:04X}, {:02X}, {:02X}", offset, x, y);
         }
 
         format!("DRAWPOLY1 {:04X}, {:02X}, {:02X}, {:02X}", offset, x, y, 0x40)
@@ -434,7 +456,7 @@ impl Opcodes {
           x += h;
         }
 
-        video.draw_poly(poly_buffer_1, offset, x, y, 0x40);
+        video.draw_poly(poly_buffer_1, 1, offset, x, y, 0x40);
 
         0
       }
@@ -467,58 +489,68 @@ impl Opcodes {
       get_asm_code: |pc: u16, script: &[u8]| {
         let mut my_pc = pc;
         let opcode = read_u8(script, my_pc - 1);
-        let offset = read_u16(script, my_pc) * 2;
+        let offset = read_u16(script, my_pc).wrapping_mul(2);
         my_pc += 2;
 
-        let mut x = read_u8(script, my_pc) as i16;
+        let x_byte = read_u8(script, my_pc);
         my_pc += 1;
 
-        let mut x_unknown = false;
-        let mut y_unknown = false;
-
-        if opcode & 0x20 == 0 {
+        // registers are shown as r[XX] and the values stored as 16 bit words with 4 digits, so the assembler can rebuild the same encoding
+        let final_x = if opcode & 0x20 == 0 {
           if opcode & 0x10 == 0 {
-            x = (x << 8) | read_u8(script, my_pc) as i16;
+            let x = ((x_byte as u16) << 8) | read_u8(script, my_pc) as u16;
             my_pc += 1;
+            format!("{:04X}", x)
           } else {
-            x_unknown = true;
+            format!("r[{:02X}]", x_byte)
           }
         } else if opcode & 0x10 != 0 {
-          x += 0x100;
-        }
+          format!("{:02X}", x_byte as u16 + 0x100)
+        } else {
+          format!("{:02X}", x_byte)
+        };
 
-        let mut y = read_u8(script, my_pc) as i16;
+        let y_byte = read_u8(script, my_pc);
         my_pc += 1;
 
-        if opcode & 0x8 == 0 {
+        let final_y = if opcode & 0x8 == 0 {
           if opcode & 0x4 == 0 {
-            y = (y << 8) | read_u8(script, my_pc) as i16;
+            let y = ((y_byte as u16) << 8) | read_u8(script, my_pc) as u16;
             my_pc += 1;
+            format!("{:04X}", y)
           } else {
-            y_unknown = true;
+            format!("r[{:02X}]", y_byte)
           }
-        }
+        } else {
+          format!("{:02X}", y_byte)
+        };
 
         let mut poly = 1;
-        let mut zoom = read_u8(script, my_pc) as i16;
-        let mut zoom_unknown = false;
+        let final_zoom;
 
+        // the zoom byte is only read when there is one, the instruction can be the last one of the script
         if opcode & 0x2 == 0 {
           if opcode & 0x1 == 0 {
-            zoom = 0x40;
+            final_zoom = format!("{:02X}", 0x40);
           } else {
-            zoom_unknown = true;
+            final_zoom = format!("r[{:02X}]", read_u8(script, my_pc));
           }
         } else if opcode & 0x1 != 0 {
-          zoom = 0x40;
+          final_zoom = format!("{:02X}", 0x40);
           poly = 2;
+        } else if read_u8(script, my_pc) == 0x40 {
+          final_zoom = format!("{:04X}", 0x40); // an explicit zoom with the default value
+        } else {
+          final_zoom = format!("{:02X}", read_u8(script, my_pc));
         }
 
-        let final_x = if x_unknown { "??".to_string() } else { format!("{:02X}", x) };
-        let final_y = if y_unknown { "??".to_string() } else { format!("{:02X}", y) };
-        let final_zoom = if zoom_unknown { "??".to_string() } else { format!("{:02X}", zoom) };
+        // with operands that fit in the short form, DRAWPOLYL keeps the long form when the code is assembled
+        let mnemonic = match decode(script, pc - 1) {
+          Some((Instruction::DrawPoly { buffer: 1, x, y, zoom, .. }, _)) if get_short_draw_poly_bytes(x, y, zoom).is_some() => "DRAWPOLYL".to_string(),
+          _ => format!("DRAWPOLY{:}", poly)
+        };
 
-        format!("DRAWPOLY{:} {:04X}, {:}, {:}, {:}", poly, offset, final_x, final_y, final_zoom)
+        format!("{} {:04X}, {:}, {:}, {:}", mnemonic, offset, final_x, final_y, final_zoom)
       },
       exec: |vm: &mut VirtualMachine, _resources_manager: &ResourcesManager, video: &mut Video, thread_id: u8, script: &[u8], poly_buffer_1: &[u8], poly_buffer_2: &[u8]| -> u32 {
         let mut pc = vm.threads[thread_id as usize].pc;
@@ -553,6 +585,7 @@ impl Opcodes {
         }
 
         let mut poly_buffer = poly_buffer_1;
+        let mut poly_buffer_id = 1;
         let mut zoom = read_u8(script, pc) as i16;
 
         if opcode & 0x2 == 0 {
@@ -564,9 +597,10 @@ impl Opcodes {
         } else if opcode & 0x1 != 0 {
           zoom = 0x40;
           poly_buffer = poly_buffer_2;
+          poly_buffer_id = 2;
         }
 
-        video.draw_poly(poly_buffer, offset, x, y, zoom);
+        video.draw_poly(poly_buffer, poly_buffer_id, offset, x, y, zoom);
 
         0
       }
@@ -0,0 +1,251 @@
+use serde::{Serialize, Deserialize};
+use crate::resources_manager::ResourcesManager;
+use crate::instruction::{Instruction, decode};
+use crate::assembler::assemble_instruction;
+
+// Patches applied to the loaded resources while the game runs. The list keeps the original bytes of every patch, so
+// they can be reverted, and it can be saved (json) to be applied again later, like this:
+// [ { "file_id": 27, "offset": 1234, "bytes": [7, 4, 214], "original": [10, 0, 50] } ]
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Patch {
+  pub file_id: u8,
+  pub offset: u16,
+  pub bytes: Vec<u8>,
+  #[serde(default)]
+  pub original: Vec<u8>
+}
+
+impl Patch {
+  fn overlaps(&self, other: &Patch) -> bool {
+    self.file_id == other.file_id &&
+    (self.offset as usize) < other.offset as usize + other.bytes.len() &&
+    (other.offset as usize) < self.offset as usize + self.bytes.len()
+  }
+}
+
+#[derive(Default)]
+pub struct PatchList {
+  pub patches: Vec<Patch>
+}
+
+impl PatchList {
+  pub fn new() -> PatchList {
+    PatchList {
+      patches: Vec::new()
+    }
+  }
+
+  // returns the index of the patch in the list
+  pub fn patch_bytes(&mut self, resources_manager: &mut ResourcesManager, file_id: u8, offset: u16, bytes: &[u8]) -> Result<usize, String> {
+    let original = resources_manager.patch_file(file_id, offset, bytes)?;
+
+    self.patches.push(Patch { file_id, offset, bytes: bytes.to_vec(), original });
+
+    Ok(self.patches.len() - 1)
+  }
+
+  // replaces the instruction at pc by the one assembled from text (with the disassembler syntax). Both instructions
+  // must have the same length, otherwise the next instructions would be broken
+  pub fn patch_instruction(&mut self, resources_manager: &mut ResourcesManager, script_id: u8, pc: u16, text: &str) -> Result<usize, String> {
+    let len = instruction_len(resources_manager, script_id, pc)?;
+    let bytes = assemble_instruction(text)?;
+
+    if bytes.len() != len as usize {
+      return Err(format!("the new instruction has {} bytes, but the instruction at {:04X} has {}", bytes.len(), pc, len));
+    }
+
+    self.patch_bytes(resources_manager, script_id, pc, &bytes)
+  }
+
+  // makes the instruction at pc do nothing, replacing it by a jump to the next instruction
+  pub fn patch_nop(&mut self, resources_manager: &mut ResourcesManager, script_id: u8, pc: u16) -> Result<usize, String> {
+    let len = instruction_len(resources_manager, script_id, pc)?;
+    let jump = Instruction::Jmp { addr: pc + len }.encode()?;
+
+    if jump.len() > len as usize {
+      return Err(format!("the instruction at {:04X} is too short to be replaced by a jump", pc));
+    }
+
+    self.patch_bytes(resources_manager, script_id, pc, &jump)
+  }
+
+  // a patch can't be reverted while a later patch overlaps it
+  pub fn revert(&mut self, resources_manager: &mut ResourcesManager, idx: usize) -> Result<(), String> {
+    let patch = self.patches.get(idx).ok_or_else(|| format!("the patch {} doesn't exist", idx))?;
+
+    if let Some(later) = self.patches[idx + 1..].iter().position(|p| p.overlaps(patch)) {
+      return Err(format!("the patch {} overlaps the patch {}, revert it first", idx + 1 + later, idx));
+    }
+
+    resources_manager.patch_file(patch.file_id, patch.offset, &patch.original)?;
+    self.patches.remove(idx);
+
+    Ok(())
+  }
+
+  pub fn revert_all(&mut self, resources_manager: &mut ResourcesManager) {
+    while let Some(patch) = self.patches.pop() {
+      resources_manager.patch_file(patch.file_id, patch.offset, &patch.original).unwrap();
+    }
+  }
+
+  pub fn to_json(&self) -> String {
+    serde_json::to_string_pretty(&self.patches).unwrap()
+  }
+
+  // applies the patches of a saved list after the ones already applied. When a patch has the original bytes, they
+  // have to match the current ones (the list may have been saved with other version of the game)
+  pub fn apply_json(&mut self, resources_manager: &mut ResourcesManager, text: &str) -> Result<(), String> {
+    let patches: Vec<Patch> = serde_json::from_str(text).map_err(|e| e.to_string())?;
+    let num_applied = self.patches.len();
+
+    for (idx, patch) in patches.iter().enumerate() {
+      let result = self.patch_bytes(resources_manager, patch.file_id, patch.offset, &patch.bytes).and_then(|applied_idx| {
+        let original = &self.patches[applied_idx].original;
+
+        if !patch.original.is_empty() && *original != patch.original {
+          return Err(format!("the patch {} doesn't match the bytes of the resource {:02X}", idx, patch.file_id));
+        }
+
+        Ok(())
+      });
+
+      if let Err(message) = result {
+        // leaves the resources as they were before the list was applied
+        while self.patches.len() > num_applied {
+          self.revert(resources_manager, self.patches.len() - 1)?;
+        }
+
+        return Err(message);
+      }
+    }
+
+    Ok(())
+  }
+}
+
+fn instruction_len(resources_manager: &ResourcesManager, script_id: u8, pc: u16) -> Result<u16, String> {
+  if script_id as usize >= resources_manager.files.len() {
+    return Err(format!("the resource {:02X} doesn't exist", script_id));
+  }
+
+  match decode(resources_manager.get_file(script_id), pc) {
+    Some((_, len)) => Ok(len),
+    None => Err(format!("there is no valid instruction at {:04X}", pc))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::assembler::assemble;
+  use crate::resources_manager::{FileEntry, ResourceType};
+
+  // the resource 0 is a script and the resource 1 has 16 zeros
+  fn new_resources_manager() -> ResourcesManager {
+    let mut resources_manager = ResourcesManager::new();
+    let script = assemble("MOV r[10], 0005\nYIELD\nJMP 0000").unwrap();
+
+    resources_manager.files.push(FileEntry { ftype: ResourceType::Script as u8, content: script });
+    resources_manager.files.push(FileEntry { ftype: 0, content: vec![0; 16] });
+    resources_manager
+  }
+
+  #[test]
+  fn patches_are_applied_and_reverted() {
+    let mut resources_manager = new_resources_manager();
+    let mut patches = PatchList::new();
+
+    assert_eq!(patches.patch_bytes(&mut resources_manager, 1, 4, &[1, 2, 3]), Ok(0));
+    assert_eq!(resources_manager.get_file(1)[3..8], [0, 1, 2, 3, 0]);
+    assert_eq!(patches.patches[0].original, [0, 0, 0]);
+
+    patches.revert(&mut resources_manager, 0).unwrap();
+
+    assert_eq!(resources_manager.get_file(1), [0; 16]);
+    assert!(patches.patches.is_empty());
+    assert!(patches.revert(&mut resources_manager, 0).is_err());
+  }
+
+  #[test]
+  fn overlapped_patches_are_reverted_in_order() {
+    let mut resources_manager = new_resources_manager();
+    let mut patches = PatchList::new();
+
+    patches.patch_bytes(&mut resources_manager, 1, 4, &[1, 2, 3]).unwrap();
+    patches.patch_bytes(&mut resources_manager, 1, 6, &[9, 9]).unwrap();
+
+    assert_eq!(resources_manager.get_file(1)[4..8], [1, 2, 9, 9]);
+    assert_eq!(patches.patches[1].original, [3, 0]);
+    assert_eq!(patches.revert(&mut resources_manager, 0), Err("the patch 1 overlaps the patch 0, revert it first".to_string()));
+
+    patches.revert(&mut resources_manager, 1).unwrap();
+    assert_eq!(resources_manager.get_file(1)[4..8], [1, 2, 3, 0]);
+
+    patches.revert(&mut resources_manager, 0).unwrap();
+    assert_eq!(resources_manager.get_file(1), [0; 16]);
+
+    patches.patch_bytes(&mut resources_manager, 1, 4, &[1, 2, 3]).unwrap();
+    patches.patch_bytes(&mut resources_manager, 1, 6, &[9, 9]).unwrap();
+    patches.revert_all(&mut resources_manager);
+
+    assert_eq!(resources_manager.get_file(1), [0; 16]);
+  }
+
+  #[test]
+  fn instructions_are_replaced_by_instructions_of_the_same_length() {
+    let mut resources_manager = new_resources_manager();
+    let mut patches = PatchList::new();
+
+    patches.patch_instruction(&mut resources_manager, 0, 0, "MOV r[10], 0007").unwrap();
+    assert_eq!(resources_manager.get_file(0)[..4], [0x00, 0x10, 0x00, 0x07]);
+
+    assert!(patches.patch_instruction(&mut resources_manager, 0, 4, "KILL").is_ok());
+    assert!(patches.patch_instruction(&mut resources_manager, 0, 4, "MOV r[10], 0007").is_err());
+    assert!(patches.patch_instruction(&mut resources_manager, 0, 5, "JMP 0001").is_ok());
+    assert!(patches.patch_instruction(&mut resources_manager, 0, 1, "KILL").is_err());
+
+    patches.revert_all(&mut resources_manager);
+    patches.patch_nop(&mut resources_manager, 0, 0).unwrap();
+
+    assert_eq!(resources_manager.get_file(0)[..4], [0x07, 0x00, 0x04, 0x05]);
+    assert!(patches.patch_nop(&mut resources_manager, 0, 4).is_err());
+  }
+
+  #[test]
+  fn saved_patches_are_applied_again() {
+    let mut resources_manager = new_resources_manager();
+    let mut patches = PatchList::new();
+
+    patches.patch_bytes(&mut resources_manager, 1, 4, &[1, 2, 3]).unwrap();
+    patches.patch_bytes(&mut resources_manager, 1, 6, &[9, 9]).unwrap();
+    patches.patch_instruction(&mut resources_manager, 0, 0, "MOV r[10], 0007").unwrap();
+
+    let json = patches.to_json();
+    let patched: Vec<Vec<u8>> = resources_manager.files.iter().map(|file| file.content.clone()).collect();
+    let saved_patches = patches.patches.clone();
+
+    patches.revert_all(&mut resources_manager);
+    patches.apply_json(&mut resources_manager, &json).unwrap();
+
+    assert_eq!(patches.patches, saved_patches);
+    assert!(resources_manager.files.iter().map(|file| &file.content).eq(patched.iter()));
+  }
+
+  #[test]
+  fn saved_patches_have_to_match_the_resources() {
+    let mut resources_manager = new_resources_manager();
+    let mut patches = PatchList::new();
+    let json = r#"[
+      { "file_id": 1, "offset": 0, "bytes": [1, 2] },
+      { "file_id": 1, "offset": 8, "bytes": [3], "original": [7] }
+    ]"#;
+
+    patches.patch_bytes(&mut resources_manager, 1, 12, &[5]).unwrap();
+
+    assert_eq!(patches.apply_json(&mut resources_manager, json), Err("the patch 1 doesn't match the bytes of the resource 01".to_string()));
+    assert_eq!(patches.patches.len(), 1);
+    assert_eq!(resources_manager.get_file(1)[..13], [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 5]);
+  }
+}
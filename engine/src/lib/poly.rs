@@ -1,69 +1,117 @@
 use std::cmp;
-use crate::utils::{read_u16};
 use crate::defines::{FRAME_BUFFER_WIDTH, FRAME_BUFFER_HEIGHT};
 
 const MAX_NUM_VERTICES: usize = 50;
 
+// the polys are parsed before drawing them, so a broken poly isn't drawn (see parse_shape for the broken children of
+// a group). The color 0xff means the colors of the polygons
 pub fn draw_poly_to_buffer(poly: &mut Poly, poly_buffer: &[u8], offset: u16, x: i16, y: i16, zoom: i16, color_idx: u8, output_pages: &mut [Vec<u8>; 4], backbuffer_page_idx: usize) {
-  let mut data_index = offset as usize;
-  let mut info = poly_buffer[data_index];
-  data_index += 1;
-
-  if info >= 0xc0 {
-    let mut final_color_idx = color_idx;
+  if let Ok(shape) = parse_shape(poly_buffer, offset) {
+    draw_shape_to_buffer(poly, &shape, (x, y), zoom, color_idx, output_pages, backbuffer_page_idx);
+  }
+}
 
-    if (final_color_idx & 0x80) != 0 {
-      final_color_idx = info & 0x3f;
-    }
+// the zoom is applied to every value with the rounding of the original renderer
+fn draw_shape_to_buffer(poly: &mut Poly, shape: &Shape, (x, y): (i16, i16), zoom: i16, color_idx: u8, output_pages: &mut [Vec<u8>; 4], backbuffer_page_idx: usize) {
+  let zoomed = |value: u8| value as i32 * zoom as i32 / 64;
 
-    // read vertices
-    poly.bounding_box_width = (poly_buffer[data_index] as i32 * zoom as i32 / 64) as i16;
-    poly.bounding_box_height = (poly_buffer[data_index + 1] as i32 * zoom as i32 / 64) as i16;
-    poly.num_vertices = poly_buffer[data_index + 2];
+  match shape {
+    Shape::Polygon { offset, bounding_box, vertices, color_idx: polygon_color_idx } => {
+      let final_color_idx = if (color_idx & 0x80) != 0 { *polygon_color_idx } else { color_idx };
 
-    data_index += 3;
+      poly.bounding_box_width = zoomed(bounding_box.0) as i16;
+      poly.bounding_box_height = zoomed(bounding_box.1) as i16;
+      poly.num_vertices = vertices.len() as u8;
 
-    for i in 0..poly.num_vertices {
-      poly.vertices[i as usize] = [(poly_buffer[data_index] as i32 * zoom as i32 / 64) as i16, (poly_buffer[data_index + 1] as i32 * zoom as i32 / 64) as i16];
-      data_index += 2;
-    }
+      for (i, (vx, vy)) in vertices.iter().enumerate() {
+        poly.vertices[i] = [zoomed(*vx) as i16, zoomed(*vy) as i16];
+      }
 
-    poly.draw(x, y, final_color_idx, output_pages, backbuffer_page_idx);
-  } else {
-    info = info & 0x3f;
+      poly.offset = *offset;
+      poly.draw(x, y, final_color_idx, output_pages, backbuffer_page_idx);
+    },
+    Shape::Group { origin, children, .. } => {
+      let nx = (x as i32 - zoomed(origin.0)) as i16;
+      let ny = (y as i32 - zoomed(origin.1)) as i16;
 
-    if info == 2 {
-      draw_hierarchy_poly_to_buffer(poly, &poly_buffer, data_index as u16, x, y, zoom, output_pages, backbuffer_page_idx);
+      for child in children {
+        let position = ((nx as i32 + zoomed(child.x)) as i16, (ny as i32 + zoomed(child.y)) as i16);
+        draw_shape_to_buffer(poly, &child.shape, position, zoom, child.color_idx.unwrap_or(0xff), output_pages, backbuffer_page_idx);
+      }
     }
   }
 }
 
-fn draw_hierarchy_poly_to_buffer(poly: &mut Poly, poly_buffer: &[u8], offset: u16, x: i16, y: i16, zoom: i16, output_pages: &mut [Vec<u8>; 4], backbuffer_page_idx: usize) {
-  let mut data_index = offset as usize;
-  let nx = (x as i32 - poly_buffer[data_index] as i32 * zoom as i32 / 64) as i16;
-  let ny = (y as i32 - poly_buffer[data_index + 1] as i32 * zoom as i32 / 64) as i16;
-  let num_children = poly_buffer[data_index + 2] + 1;
+// Parsed polys: a polygon or a group (hierarchy) of shapes, without drawing them. The coordinates are the ones of the
+// poly buffer (before the zoom), and the offsets are the byte offsets in the poly buffer.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Shape {
+  Polygon { offset: u16, bounding_box: (u8, u8), vertices: Vec<(u8, u8)>, color_idx: u8 }, // vertices from the top left of the bounding box
+  Group { offset: u16, origin: (u8, u8), children: Vec<ShapeChild> } // the children are at (x, y) - origin
+}
 
-  data_index += 3;
+#[derive(Clone, Debug, PartialEq)]
+pub struct ShapeChild {
+  pub x: u8,
+  pub y: u8,
+  pub color_idx: Option<u8>, // the color that replaces the colors of the polygons of the child
+  pub shape: Shape
+}
 
-  for _ in 0..num_children {
-    let mut off = read_u16(poly_buffer, data_index as u16);
-    let cx = (nx as i32 + poly_buffer[data_index + 2] as i32 * zoom as i32 / 64) as i16;
-    let cy = (ny as i32 + poly_buffer[data_index + 3] as i32 * zoom as i32 / 64) as i16;
+// groups can't be nested deeper, to stop on the loops of a broken poly buffer
+const MAX_GROUP_DEPTH: usize = 16;
 
-    data_index += 4;
+pub fn parse_shape(poly_buffer: &[u8], offset: u16) -> Result<Shape, String> {
+  parse_shape_at_depth(poly_buffer, offset, 0)
+}
+
+fn parse_shape_at_depth(poly_buffer: &[u8], offset: u16, depth: usize) -> Result<Shape, String> {
+  let read = |idx: usize, len: usize| poly_buffer.get(idx..idx + len).ok_or(format!("shape at {:04x}: out of the poly buffer", offset));
+  let info = read(offset as usize, 1)?[0];
+  let data_index = offset as usize + 1;
 
-    let mut final_color_idx = 0xff;
-    let bp = off;
+  if info >= 0xc0 {
+    let header = read(data_index, 3)?;
+    let num_vertices = header[2] as usize;
+
+    if num_vertices == 0 || num_vertices & 1 != 0 || num_vertices > MAX_NUM_VERTICES {
+      return Err(format!("polygon at {:04x}: invalid number of vertices {}", offset, num_vertices));
+    }
 
-    off &= 0x7fff;
+    let vertices = read(data_index + 3, num_vertices * 2)?.chunks_exact(2).map(|vertex| (vertex[0], vertex[1])).collect();
 
-    if bp & 0x8000 != 0 {
-      final_color_idx = poly_buffer[data_index] & 0x7f;
-      data_index += 2;
+    Ok(Shape::Polygon { offset, bounding_box: (header[0], header[1]), vertices, color_idx: info & 0x3f })
+  } else if info & 0x3f == 2 {
+    if depth >= MAX_GROUP_DEPTH {
+      return Err(format!("group at {:04x}: too many nested groups", offset));
     }
 
-    draw_poly_to_buffer(poly, poly_buffer, off * 2, cx, cy, zoom, final_color_idx, output_pages, backbuffer_page_idx);
+    let header = read(data_index, 3)?;
+    let mut children = Vec::new();
+    let mut child_index = data_index + 3;
+
+    for _ in 0..header[2] as usize + 1 {
+      let child = read(child_index, 4)?;
+      let child_offset = u16::from_be_bytes([child[0], child[1]]);
+      let mut color_idx = None;
+
+      child_index += 4;
+
+      if child_offset & 0x8000 != 0 {
+        color_idx = Some(read(child_index, 2)?[0] & 0x7f);
+        child_index += 2;
+      }
+
+      // a child that can't be parsed is skipped, like the original renderer does with the unknown types, and the
+      // other children are kept
+      if let Ok(shape) = parse_shape_at_depth(poly_buffer, (child_offset & 0x7fff) * 2, depth + 1) {
+        children.push(ShapeChild { x: child[2], y: child[3], color_idx, shape });
+      }
+    }
+
+    Ok(Shape::Group { offset, origin: (header[0], header[1]), children })
+  } else {
+    Err(format!("shape at {:04x}: unknown type {:02x}", offset, info))
   }
 }
 
@@ -71,7 +119,9 @@ pub struct Poly {
   pub bounding_box_width: i16,
   pub bounding_box_height: i16,
   pub num_vertices: u8,
-  pub vertices: Vec<[i16; 2]>
+  pub vertices: Vec<[i16; 2]>,
+  pub offset: u16, // offset of the polygon in the poly buffer
+  pub drawn_spans: Option<Vec<(u16, usize, usize)>> // if it's enabled, the drawn pixels: (offset, first index, last index + 1)
 }
 
 impl Poly {
@@ -80,11 +130,13 @@ impl Poly {
       bounding_box_width: 0,
       bounding_box_height: 0,
       num_vertices: 0,
-      vertices: vec![[0, 0]; MAX_NUM_VERTICES]
+      vertices: vec![[0, 0]; MAX_NUM_VERTICES],
+      offset: 0,
+      drawn_spans: None
     }
   }
 
-  pub fn draw(&self, x: i16, y: i16, color: u8, output_pages: &mut [Vec<u8>; 4], backbuffer_page_idx: usize) {
+  pub fn draw(&mut self, x: i16, y: i16, color: u8, output_pages: &mut [Vec<u8>; 4], backbuffer_page_idx: usize) {
     if self.bounding_box_width == 0 && self.bounding_box_height == 1 && self.num_vertices == 4 { // it's a point
       if x < 0 || x >= FRAME_BUFFER_WIDTH as i16 || y < 0 || y >= FRAME_BUFFER_HEIGHT as i16 {
         return;
@@ -99,6 +151,10 @@ impl Poly {
       } else {
         output_pages[backbuffer_page_idx][offset] = color
       }
+
+      if let Some(spans) = &mut self.drawn_spans {
+        spans.push((self.offset, offset, offset + 1));
+      }
     }
 
     // if some point of the bounding box is outside the screen, the poly is discarded
@@ -153,8 +209,8 @@ impl Poly {
       cpt2 = (cpt2 & 0xFFFF0000) | 0x8000;
 
       if h == 0 {
-        cpt1 += step1 as u32;
-        cpt2 += step2 as u32;
+        cpt1 = cpt1.wrapping_add(step1 as u32);
+        cpt2 = cpt2.wrapping_add(step2 as u32);
       } else {
         while h != 0 {
           if hliney >= 0 {
@@ -182,7 +238,7 @@ impl Poly {
     }
   }
 
-  fn draw_hor_line(&self, x1: i16, x2: i16, y: i16, color: u8, output_pages: &mut [Vec<u8>; 4], backbuffer_page_idx: usize) {
+  fn draw_hor_line(&mut self, x1: i16, x2: i16, y: i16, color: u8, output_pages: &mut [Vec<u8>; 4], backbuffer_page_idx: usize) {
     let xmin = cmp::min(x1, x2);
     let xmax = cmp::max(x1, x2);
 
@@ -199,5 +255,164 @@ impl Poly {
         output_pages[backbuffer_page_idx][idx] = (output_pages[backbuffer_page_idx][idx] & 0x7) + 0x8;
       }
     }
+
+    if let Some(spans) = &mut self.drawn_spans {
+      spans.push((self.offset, start_idx, start_idx + (xmax - (xmin - 1)) as usize));
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::utils::read_u16;
+
+  const POLYGON: [u8; 12] = [0xc3, 30, 20, 4, 30, 0, 30, 20, 0, 20, 0, 0];
+
+  // a group at 0 with the polygon at 0x20 and a nested group at 0x30, that draws the polygon twice, once with the color
+  // of the child. The color of the nested group (5) isn't used by its children
+  fn build_poly_buffer() -> Vec<u8> {
+    let mut poly_buffer = vec![0; 0x40];
+
+    poly_buffer[..14].copy_from_slice(&[0x02, 10, 10, 1, 0x00, 0x10, 20, 20, 0x80, 0x18, 60, 40, 5, 0]);
+    poly_buffer[0x20..0x2c].copy_from_slice(&POLYGON);
+    poly_buffer[0x30..0x3e].copy_from_slice(&[0x02, 5, 5, 1, 0x00, 0x10, 0, 0, 0x80, 0x10, 30, 10, 9, 0]);
+    poly_buffer
+  }
+
+  fn new_pages() -> [Vec<u8>; 4] {
+    [vec![0; 320 * 200], vec![0; 320 * 200], vec![0; 320 * 200], vec![0; 320 * 200]]
+  }
+
+  #[test]
+  fn polygons_are_parsed() {
+    let shape = parse_shape(&POLYGON, 0).unwrap();
+
+    assert_eq!(shape, Shape::Polygon { offset: 0, bounding_box: (30, 20), vertices: vec![(30, 0), (30, 20), (0, 20), (0, 0)], color_idx: 3 });
+  }
+
+  #[test]
+  fn groups_are_parsed() {
+    let poly_buffer = build_poly_buffer();
+    let polygon = parse_shape(&poly_buffer, 0x20).unwrap();
+
+    let nested_group = Shape::Group { offset: 0x30, origin: (5, 5), children: vec![
+      ShapeChild { x: 0, y: 0, color_idx: None, shape: polygon.clone() },
+      ShapeChild { x: 30, y: 10, color_idx: Some(9), shape: polygon.clone() }
+    ]};
+
+    assert_eq!(parse_shape(&poly_buffer, 0).unwrap(), Shape::Group { offset: 0, origin: (10, 10), children: vec![
+      ShapeChild { x: 20, y: 20, color_idx: None, shape: polygon },
+      ShapeChild { x: 60, y: 40, color_idx: Some(5), shape: nested_group }
+    ]});
+  }
+
+  #[test]
+  fn invalid_numbers_of_vertices_are_rejected() {
+    for num_vertices in [0, 3, 52] {
+      let mut poly_buffer = vec![0xc3, 30, 20, num_vertices];
+      poly_buffer.resize(4 + num_vertices as usize * 2, 0);
+
+      assert!(parse_shape(&poly_buffer, 0).is_err(), "{} vertices", num_vertices);
+    }
+
+    // truncated vertices
+    assert!(parse_shape(&POLYGON[..10], 0).is_err());
+  }
+
+  #[test]
+  fn broken_children_are_skipped() {
+    let mut poly_buffer = build_poly_buffer();
+    poly_buffer[0x30] = 0x05; // unknown type
+
+    match parse_shape(&poly_buffer, 0).unwrap() {
+      Shape::Group { children, .. } => {
+        assert_eq!(children.len(), 1);
+        assert_eq!(children[0].shape, parse_shape(&poly_buffer, 0x20).unwrap());
+      },
+      shape => panic!("{:?}", shape)
+    }
+
+    assert!(parse_shape(&poly_buffer, 0x30).is_err());
+  }
+
+  #[test]
+  fn nested_groups_are_limited() {
+    // a group with itself as its only child
+    let poly_buffer = [0x02, 0, 0, 0, 0x00, 0x00, 0, 0];
+    let mut shape = Some(parse_shape(&poly_buffer, 0).unwrap());
+    let mut num_groups = 0;
+
+    while let Some(Shape::Group { mut children, .. }) = shape {
+      shape = children.pop().map(|child| child.shape);
+      num_groups += 1;
+    }
+
+    assert_eq!(num_groups, MAX_GROUP_DEPTH);
+  }
+
+  // the renderer before parse_shape, to check that the drawn pixels didn't change
+  fn draw_poly_to_buffer_baseline(poly: &mut Poly, poly_buffer: &[u8], offset: u16, (x, y): (i16, i16), zoom: i16, color_idx: u8, output_pages: &mut [Vec<u8>; 4]) {
+    let mut data_index = offset as usize;
+    let info = poly_buffer[data_index];
+    data_index += 1;
+
+    if info >= 0xc0 {
+      let final_color_idx = if (color_idx & 0x80) != 0 { info & 0x3f } else { color_idx };
+
+      poly.bounding_box_width = (poly_buffer[data_index] as i32 * zoom as i32 / 64) as i16;
+      poly.bounding_box_height = (poly_buffer[data_index + 1] as i32 * zoom as i32 / 64) as i16;
+      poly.num_vertices = poly_buffer[data_index + 2];
+
+      data_index += 3;
+
+      for i in 0..poly.num_vertices {
+        poly.vertices[i as usize] = [(poly_buffer[data_index] as i32 * zoom as i32 / 64) as i16, (poly_buffer[data_index + 1] as i32 * zoom as i32 / 64) as i16];
+        data_index += 2;
+      }
+
+      poly.draw(x, y, final_color_idx, output_pages, 1);
+    } else if info & 0x3f == 2 {
+      let nx = (x as i32 - poly_buffer[data_index] as i32 * zoom as i32 / 64) as i16;
+      let ny = (y as i32 - poly_buffer[data_index + 1] as i32 * zoom as i32 / 64) as i16;
+      let num_children = poly_buffer[data_index + 2] + 1;
+
+      data_index += 3;
+
+      for _ in 0..num_children {
+        let off = read_u16(poly_buffer, data_index as u16);
+        let cx = (nx as i32 + poly_buffer[data_index + 2] as i32 * zoom as i32 / 64) as i16;
+        let cy = (ny as i32 + poly_buffer[data_index + 3] as i32 * zoom as i32 / 64) as i16;
+        let mut final_color_idx = 0xff;
+
+        data_index += 4;
+
+        if off & 0x8000 != 0 {
+          final_color_idx = poly_buffer[data_index] & 0x7f;
+          data_index += 2;
+        }
+
+        draw_poly_to_buffer_baseline(poly, poly_buffer, (off & 0x7fff) * 2, (cx, cy), zoom, final_color_idx, output_pages);
+      }
+    }
+  }
+
+  #[test]
+  fn nested_groups_are_drawn_like_the_baseline() {
+    let poly_buffer = build_poly_buffer();
+
+    for zoom in [0x40, 0x20, 0x64] {
+      let mut pages = new_pages();
+      let mut baseline_pages = new_pages();
+
+      draw_poly_to_buffer(&mut Poly::new(), &poly_buffer, 0, 100, 80, zoom, 0xff, &mut pages, 1);
+      draw_poly_to_buffer_baseline(&mut Poly::new(), &poly_buffer, 0, (100, 80), zoom, 0xff, &mut baseline_pages);
+
+      for color in [3, 9] {
+        assert!(pages[1].contains(&color), "zoom {:02x}: color {} isn't drawn", zoom, color);
+      }
+
+      assert!(pages == baseline_pages, "zoom {:02x}", zoom);
+    }
   }
 }
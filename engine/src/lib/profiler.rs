@@ -0,0 +1,149 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, VecDeque};
+use crate::control_flow::ControlFlow;
+use crate::opcodes::Opcodes;
+use crate::symbols::SymbolTable;
+use crate::defines::NUM_THREADS;
+
+const MAX_PROFILED_FRAMES: usize = 600; // frames kept in the history
+
+#[derive(Clone, Copy, Default)]
+pub struct ThreadCost {
+  pub instructions: u32,
+  pub frames: u32, // frames in which the thread has run
+  pub max_instructions: u32 // max instructions in a frame
+}
+
+// counts the instructions executed per script and pc, and per thread and frame
+pub struct Profiler {
+  pub frame: u32,
+  pub executions: BTreeMap<u8, BTreeMap<u16, u32>>, // script file id -> pc -> executions
+  pub thread_costs: [ThreadCost; NUM_THREADS],
+  pub frames_history: VecDeque<[u32; NUM_THREADS]>, // instructions per thread in the last frames
+  current_frame: [u32; NUM_THREADS]
+}
+
+impl Default for Profiler {
+  fn default() -> Profiler {
+    Profiler::new()
+  }
+}
+
+impl Profiler {
+  pub fn new() -> Profiler {
+    Profiler {
+      frame: 0,
+      executions: BTreeMap::new(),
+      thread_costs: [ThreadCost::default(); NUM_THREADS],
+      frames_history: VecDeque::with_capacity(MAX_PROFILED_FRAMES),
+      current_frame: [0; NUM_THREADS]
+    }
+  }
+
+  pub fn count(&mut self, script_file_id: u8, thread_id: u8, pc: u16) {
+    let executions = self.executions.entry(script_file_id).or_default().entry(pc).or_insert(0);
+    *executions = executions.saturating_add(1);
+
+    self.current_frame[thread_id as usize] += 1;
+  }
+
+  pub fn end_frame(&mut self) {
+    for (cost, instructions) in self.thread_costs.iter_mut().zip(self.current_frame.iter()) {
+      if *instructions > 0 {
+        cost.instructions = cost.instructions.saturating_add(*instructions);
+        cost.frames += 1;
+        cost.max_instructions = cost.max_instructions.max(*instructions);
+      }
+    }
+
+    if self.frames_history.len() == MAX_PROFILED_FRAMES {
+      self.frames_history.pop_front();
+    }
+
+    self.frames_history.push_back(self.current_frame);
+    self.current_frame = [0; NUM_THREADS];
+    self.frame += 1;
+  }
+
+  pub fn get_executions(&self, script_file_id: u8, pc: u16) -> u32 {
+    self.executions.get(&script_file_id).and_then(|executions| executions.get(&pc)).copied().unwrap_or(0)
+  }
+
+  // disassembly of the script with the times each instruction has been executed. The blocks that have never been
+  // executed are marked, and listed at the beginning of the report. The registers, labels and comments of the symbols
+  // are shown like in the disassembler
+  pub fn build_coverage_report(&self, script_file_id: u8, script: &[u8], opcodes: &Opcodes, symbols: &SymbolTable) -> String {
+    let part_symbols = symbols.get_script_symbols(script_file_id);
+    let control_flow = ControlFlow::new(script);
+    let num_executed = self.executions.get(&script_file_id).map(|executions| executions.len()).unwrap_or(0);
+    let never_executed: Vec<(u16, u16)> = control_flow.blocks.values()
+      .filter(|block| self.get_executions(script_file_id, block.start) == 0)
+      .map(|block| (block.start, block.end))
+      .collect();
+
+    let mut report = format!("; coverage of the script {:02X} after {} frames\n", script_file_id, self.frame);
+    report.push_str(&format!("; {} of {} reachable instructions executed\n", num_executed, control_flow.instructions.len()));
+    report.push_str(&format!("; {} of {} blocks never executed:", never_executed.len(), control_flow.blocks.len()));
+
+    for (start, end) in &never_executed {
+      report.push_str(&format!(" {:04X}-{:04X}", start, end));
+    }
+
+    report.push_str("\n\n");
+
+    let mut pc: usize = 0;
+
+    while pc < script.len() {
+      let opcode = opcodes.get(script[pc]);
+      let asm_code = symbols.format_asm_code(&(opcode.get_asm_code)(pc as u16 + 1, script));
+      let executions = self.get_executions(script_file_id, pc as u16);
+      let block_start = never_executed.iter().any(|(start, _)| *start as usize == pc);
+      let label = part_symbols.and_then(|symbols| symbols.labels.get(&(pc as u16)));
+      let comment = part_symbols.and_then(|symbols| symbols.comments.get(&(pc as u16)));
+
+      if let Some(label) = label {
+        report.push_str(&format!("{:>10}  {:04X}  {}:\n", "", pc, label));
+      }
+
+      let comments: Vec<&str> = block_start.then_some("never executed").into_iter().chain(comment.map(|comment| comment.as_str())).collect();
+      let executions = if executions == 0 { "-".to_string() } else { executions.to_string() };
+
+      if comments.is_empty() {
+        report.push_str(&format!("{:>10}  {:04X}  {}\n", executions, pc, asm_code));
+      } else {
+        report.push_str(&format!("{:>10}  {:04X}  {:<40}; {}\n", executions, pc, asm_code, comments.join(", ")));
+      }
+
+      pc += (opcode.len)(pc as u16 + 1, script) as usize;
+    }
+
+    report
+  }
+
+  // instructions executed by every thread that has run, sorted by cost
+  pub fn build_threads_cost_report(&self) -> String {
+    let total: u64 = self.thread_costs.iter().map(|cost| cost.instructions as u64).sum();
+    let mut threads: Vec<(usize, &ThreadCost)> = self.thread_costs.iter().enumerate().filter(|(_, cost)| cost.frames > 0).collect();
+
+    threads.sort_by_key(|(_, cost)| Reverse(cost.instructions));
+
+    let mut report = format!("; instructions per thread after {} frames\n", self.frame);
+    report.push_str("thread  instructions  frames  avg/frame  max/frame  last frame      %\n");
+
+    for (thread_id, cost) in threads {
+      let last_frame = self.frames_history.back().map(|frame| frame[thread_id]).unwrap_or(0);
+
+      report.push_str(&format!("    {:02X}  {:>12}  {:>6}  {:>9}  {:>9}  {:>10}  {:>5.1}\n",
+        thread_id,
+        cost.instructions,
+        cost.frames,
+        cost.instructions / cost.frames,
+        cost.max_instructions,
+        last_frame,
+        cost.instructions as f64 * 100.0 / total as f64
+      ));
+    }
+
+    report
+  }
+}
@@ -0,0 +1,67 @@
+use crate::defines::NUM_REGISTERS;
+
+// Search of the registers that hold a value of the game (the lives, the position of an enemy...), like a cheat
+// finder: the search starts with all the registers as candidates, and every filter keeps only the registers whose
+// value has changed as expected since the previous filter (or since the search started)
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SearchCondition {
+  Changed,
+  Unchanged,
+  Equals(i16),
+  Increased,
+  Decreased
+}
+
+impl SearchCondition {
+  // the condition codes used by the host: 0 changed, 1 unchanged, 2 equals, 3 increased, 4 decreased
+  pub fn from_code(code: u8, value: i16) -> Option<SearchCondition> {
+    match code {
+      0 => Some(SearchCondition::Changed),
+      1 => Some(SearchCondition::Unchanged),
+      2 => Some(SearchCondition::Equals(value)),
+      3 => Some(SearchCondition::Increased),
+      4 => Some(SearchCondition::Decreased),
+      _ => None
+    }
+  }
+
+  fn matches(self, previous: i16, current: i16) -> bool {
+    match self {
+      SearchCondition::Changed => current != previous,
+      SearchCondition::Unchanged => current == previous,
+      SearchCondition::Equals(value) => current == value,
+      SearchCondition::Increased => current > previous,
+      SearchCondition::Decreased => current < previous
+    }
+  }
+}
+
+pub struct RegisterSearch {
+  pub candidates: Vec<u8>,
+  previous: Vec<i16> // values of the registers in the last filter
+}
+
+impl RegisterSearch {
+  pub fn new(registers: &[i16]) -> RegisterSearch {
+    RegisterSearch {
+      candidates: (0..NUM_REGISTERS).map(|reg_id| reg_id as u8).collect(),
+      previous: registers.to_vec()
+    }
+  }
+
+  // returns the number of candidates left
+  pub fn filter(&mut self, registers: &[i16], condition: SearchCondition) -> usize {
+    let previous = &self.previous;
+
+    self.candidates.retain(|reg_id| condition.matches(previous[*reg_id as usize], registers[*reg_id as usize]));
+    self.previous = registers.to_vec();
+
+    self.candidates.len()
+  }
+
+  // candidate registers with their value in the last filter
+  pub fn get_candidates(&self) -> Vec<(u8, i16)> {
+    self.candidates.iter().map(|reg_id| (*reg_id, self.previous[*reg_id as usize])).collect()
+  }
+}
@@ -133,6 +133,22 @@ impl ResourcesManager {
     self.files[file_id as usize].ftype
   }
 
+  // overwrites some bytes of a loaded file and returns the bytes that were there. The bitmaps are patched after being
+  // converted to 4bpp
+  pub fn patch_file(&mut self, file_id: u8, offset: u16, bytes: &[u8]) -> Result<Vec<u8>, String> {
+    let file = self.files.get_mut(file_id as usize).ok_or_else(|| format!("the resource {:02X} doesn't exist", file_id))?;
+    let end = offset as usize + bytes.len();
+
+    if end > file.content.len() {
+      return Err(format!("the patch {:04X}-{:04X} is outside the resource {:02X}", offset, end, file_id));
+    }
+
+    let original = file.content[offset as usize..end].to_vec();
+    file.content[offset as usize..end].copy_from_slice(bytes);
+
+    Ok(original)
+  }
+
   fn create_bitmap(&self, content: &Vec<u8>) -> Vec<u8> {
     let mut bitmap = Vec::new();
     let mut src_idx = 0;
@@ -288,4 +304,20 @@ impl ResourcesManager {
 
     rcf
   }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn patched_bytes_have_to_be_in_the_file() {
+    let mut resources_manager = ResourcesManager::new();
+    resources_manager.files.push(FileEntry { ftype: 0, content: vec![1, 2, 3, 4] });
+
+    assert_eq!(resources_manager.patch_file(0, 2, &[8, 9]), Ok(vec![3, 4]));
+    assert_eq!(resources_manager.get_file(0), [1, 2, 8, 9]);
+    assert_eq!(resources_manager.patch_file(0, 3, &[5, 6]), Err("the patch 0003-0005 is outside the resource 00".to_string()));
+    assert_eq!(resources_manager.patch_file(1, 0, &[5]), Err("the resource 01 doesn't exist".to_string()));
+    assert_eq!(resources_manager.get_file(0), [1, 2, 8, 9]);
+  }
+}
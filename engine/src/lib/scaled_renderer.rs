@@ -0,0 +1,490 @@
+use crate::defines::{FRAME_BUFFER_WIDTH, FRAME_BUFFER_HEIGHT};
+use crate::poly::{Shape, parse_shape};
+use crate::font::FONT;
+
+// Second rendering backend, that draws the same operations as Video in pages of any resolution. The polygons are
+// rasterized from their vertices (without the rounding of the zoom and of the edges of the original renderer), so the
+// shapes are crisp at high resolutions. The coordinates of the operations are the ones of the game (320x200), and the
+// page indexes are the ones resolved by Video (0xfe and 0xff are already mapped to a page).
+// With anti-aliasing, the edges of the polygons are drawn with the coverage of the pixels (sampled in several rows per
+// pixel). As the pages have color indexes, every page keeps, for each pixel, the coverage of its color and the color
+// that was below it, and both colors are blended in the rgba output.
+
+const NUM_PAGES: usize = 4;
+const NUM_SUBSAMPLES: usize = 4; // rows sampled per pixel with anti-aliasing
+const FULL_COVERAGE: u8 = 0xff;
+
+pub struct ScaledRenderer {
+  pub width: usize,
+  pub height: usize,
+  pages: [Vec<u8>; NUM_PAGES],
+  coverages: [Vec<u8>; NUM_PAGES], // only with anti-aliasing
+  colors_below: [Vec<u8>; NUM_PAGES], // only with anti-aliasing
+  anti_aliasing: bool,
+  scale_x: f32,
+  scale_y: f32
+}
+
+impl ScaledRenderer {
+  pub fn new(width: usize, height: usize) -> ScaledRenderer {
+    let size = width * height;
+
+    ScaledRenderer {
+      width,
+      height,
+      pages: [vec![0; size], vec![0; size], vec![0; size], vec![0; size]],
+      coverages: [Vec::new(), Vec::new(), Vec::new(), Vec::new()],
+      colors_below: [Vec::new(), Vec::new(), Vec::new(), Vec::new()],
+      anti_aliasing: false,
+      scale_x: width as f32 / FRAME_BUFFER_WIDTH as f32,
+      scale_y: height as f32 / FRAME_BUFFER_HEIGHT as f32
+    }
+  }
+
+  // the pixels already drawn keep their color, without coverage
+  pub fn set_anti_aliasing(&mut self, enabled: bool) {
+    let size = self.width * self.height;
+
+    self.anti_aliasing = enabled;
+
+    for page_idx in 0..NUM_PAGES {
+      self.coverages[page_idx] = if enabled { vec![FULL_COVERAGE; size] } else { Vec::new() };
+      self.colors_below[page_idx] = if enabled { self.pages[page_idx].clone() } else { Vec::new() };
+    }
+  }
+
+  pub fn get_page(&self, page_idx: usize) -> &[u8] {
+    &self.pages[page_idx]
+  }
+
+  // colors: rgb of the 16 colors of the palette. The pixels are opaque (alpha 0xff), like the frame buffer
+  pub fn write_rgba(&self, page_idx: usize, colors: &[[u8; 3]; 16], output: &mut [u8]) {
+    for (i, color_idx) in self.pages[page_idx].iter().enumerate() {
+      let color = colors[*color_idx as usize & 0xf];
+
+      if !self.anti_aliasing || self.coverages[page_idx][i] == FULL_COVERAGE {
+        output[i * 4..i * 4 + 3].copy_from_slice(&color);
+      } else {
+        let coverage = self.coverages[page_idx][i] as u16;
+        let color_below = colors[self.colors_below[page_idx][i] as usize & 0xf];
+
+        for c in 0..3 {
+          output[i * 4 + c] = ((color[c] as u16 * coverage + color_below[c] as u16 * (0xff - coverage)) / 0xff) as u8;
+        }
+      }
+
+      output[i * 4 + 3] = 0xff;
+    }
+  }
+
+  pub fn fill_page(&mut self, page_idx: usize, color_idx: u8) {
+    for i in &mut self.pages[page_idx] {
+      *i = color_idx;
+    }
+
+    if self.anti_aliasing {
+      self.coverages[page_idx].iter_mut().for_each(|coverage| *coverage = FULL_COVERAGE);
+    }
+  }
+
+  // the rows of the source page are copied vscroll rows (of the game) below in the destination page
+  pub fn copy_page(&mut self, src_page_idx: usize, dst_page_idx: usize, vscroll: i16) {
+    if src_page_idx == dst_page_idx || vscroll.abs() >= FRAME_BUFFER_HEIGHT as i16 {
+      return;
+    }
+
+    let rows = (vscroll.abs() as f32 * self.scale_y).round() as usize;
+    let len = (self.height - rows) * self.width;
+    let (src_start, dst_start) = if vscroll < 0 { (rows * self.width, 0) } else { (0, rows * self.width) };
+    let src = self.pages[src_page_idx][src_start..src_start + len].to_vec();
+
+    self.pages[dst_page_idx][dst_start..dst_start + len].copy_from_slice(&src);
+
+    if self.anti_aliasing {
+      let src = self.coverages[src_page_idx][src_start..src_start + len].to_vec();
+      self.coverages[dst_page_idx][dst_start..dst_start + len].copy_from_slice(&src);
+
+      let src = self.colors_below[src_page_idx][src_start..src_start + len].to_vec();
+      self.colors_below[dst_page_idx][dst_start..dst_start + len].copy_from_slice(&src);
+    }
+  }
+
+  // the bitmaps are always copied to the page 0
+  pub fn draw_bitmap(&mut self, bitmap: &[u8]) {
+    self.draw_page(0, bitmap);
+  }
+
+  // scales a page of the game (320x200) to the page
+  pub fn draw_page(&mut self, page_idx: usize, pixels: &[u8]) {
+    for y in 0..self.height {
+      let py = (y as f32 / self.scale_y) as usize * FRAME_BUFFER_WIDTH as usize;
+
+      for x in 0..self.width {
+        self.pages[page_idx][y * self.width + x] = pixels[py + (x as f32 / self.scale_x) as usize];
+      }
+    }
+
+    if self.anti_aliasing {
+      self.coverages[page_idx].iter_mut().for_each(|coverage| *coverage = FULL_COVERAGE);
+    }
+  }
+
+  pub fn draw_char(&mut self, c: char, x: i16, y: i16, color_idx: u8, page_idx: usize) {
+    let char_idx = ((c as u8 - b' ') as usize) * 8;
+    let char_info = &FONT[char_idx .. char_idx + 8];
+
+    for (row, bits) in char_info.iter().enumerate() {
+      for col in 0..8 {
+        if (bits & (1 << (7 - col))) != 0 {
+          self.fill_rect((x + col) as f32, (y + row as i16) as f32, 1.0, 1.0, color_idx, page_idx);
+        }
+      }
+    }
+  }
+
+  // the polys are parsed before drawing them, so a broken poly buffer isn't drawn
+  pub fn draw_poly(&mut self, poly_buffer: &[u8], offset: u16, (x, y): (f32, f32), zoom: i16, color_idx: u8, page_idx: usize) {
+    if let Ok(shape) = parse_shape(poly_buffer, offset) {
+      self.draw_shape(&shape, (x, y), zoom, color_idx, page_idx);
+    }
+  }
+
+  // like draw_poly_to_buffer, the color 0xff means the colors of the polygons
+  fn draw_shape(&mut self, shape: &Shape, (x, y): (f32, f32), zoom: i16, color_idx: u8, page_idx: usize) {
+    let scale = zoom as f32 / 64.0;
+
+    match shape {
+      Shape::Polygon { bounding_box, vertices, color_idx: polygon_color_idx, .. } => {
+        let color_idx = if (color_idx & 0x80) != 0 { *polygon_color_idx } else { color_idx };
+
+        // it's a point (the check is done with the zoom of the original renderer)
+        if bounding_box.0 == 0 && bounding_box.1 as i32 * zoom as i32 / 64 == 1 && vertices.len() == 4 {
+          self.fill_rect(x.floor(), y.floor(), 1.0, 1.0, color_idx, page_idx);
+          return;
+        }
+
+        let vertices: Vec<(f32, f32)> = vertices.iter().map(|(vx, vy)| (*vx as f32 * scale, *vy as f32 * scale)).collect();
+
+        self.fill_polygon((x, y), (bounding_box.0 as f32 * scale, bounding_box.1 as f32 * scale), &vertices, color_idx, page_idx);
+      },
+      Shape::Group { origin, children, .. } => {
+        let nx = x - origin.0 as f32 * scale;
+        let ny = y - origin.1 as f32 * scale;
+
+        for child in children {
+          let position = (nx + child.x as f32 * scale, ny + child.y as f32 * scale);
+          self.draw_shape(&child.shape, position, zoom, child.color_idx.unwrap_or(0xff), page_idx);
+        }
+      }
+    }
+  }
+
+  // the vertices are in pairs with the same y: the first half is the right edge from the top, and the second half is the
+  // left edge from the bottom. Like in the original renderer, the pixels of the right edge are drawn
+  fn fill_polygon(&mut self, (x, y): (f32, f32), (bounding_box_width, bounding_box_height): (f32, f32), vertices: &[(f32, f32)], color_idx: u8, page_idx: usize) {
+    let x1 = x - (bounding_box_width / 2.0).trunc();
+    let y1 = y - (bounding_box_height / 2.0).trunc();
+
+    // the poly is discarded if it's outside the screen
+    if x + bounding_box_width / 2.0 < 0.0 || x1 >= FRAME_BUFFER_WIDTH as f32 || y + bounding_box_height / 2.0 < 0.0 || y1 >= FRAME_BUFFER_HEIGHT as f32 {
+      return;
+    }
+
+    let n = vertices.len();
+    let mut segments = Vec::with_capacity(n / 2);
+
+    for k in 1..n / 2 {
+      let (right0, right1) = (vertices[k - 1], vertices[k]);
+      let (left0, left1) = (vertices[n - k], vertices[n - 1 - k]);
+
+      if right1.1 > right0.1 {
+        segments.push(Segment {
+          top: y1 + right0.1,
+          bottom: y1 + right1.1,
+          left: x1 + left0.0,
+          left_slope: if left1.1 != left0.1 { (left1.0 - left0.0) / (left1.1 - left0.1) } else { 0.0 },
+          right: x1 + right0.0,
+          right_slope: (right1.0 - right0.0) / (right1.1 - right0.1)
+        });
+      }
+    }
+
+    if segments.is_empty() {
+      return;
+    }
+
+    if self.anti_aliasing {
+      self.fill_segments_anti_aliased(&segments, color_idx, page_idx);
+      return;
+    }
+
+    for segment in &segments {
+      // the rows with the center inside the segment
+      let first_row = ((segment.top * self.scale_y - 0.5).ceil().max(0.0)) as usize;
+      let last_row = ((segment.bottom * self.scale_y - 0.5).ceil().min(self.height as f32)) as usize;
+
+      for row in first_row..last_row {
+        let (left, right) = segment.span((row as f32 + 0.5) / self.scale_y);
+        self.draw_hor_line(left, right, row, color_idx, page_idx);
+      }
+    }
+  }
+
+  // the coverage of every pixel is the average of the parts of its subsample rows inside the polygon
+  fn fill_segments_anti_aliased(&mut self, segments: &[Segment], color_idx: u8, page_idx: usize) {
+    let top = segments[0].top;
+    let bottom = segments[segments.len() - 1].bottom;
+    let first_row = ((top * self.scale_y).floor().max(0.0)) as usize;
+    let last_row = ((bottom * self.scale_y).ceil().min(self.height as f32)) as usize;
+    let mut coverages = vec![0.0f32; self.width];
+
+    for row in first_row..last_row {
+      let mut first_col = self.width;
+      let mut last_col = 0;
+
+      for subsample in 0..NUM_SUBSAMPLES {
+        let y = (row as f32 + (subsample as f32 + 0.5) / NUM_SUBSAMPLES as f32) / self.scale_y;
+        let segment = match segments.iter().find(|segment| y >= segment.top && y < segment.bottom) {
+          Some(segment) => segment,
+          None => continue
+        };
+
+        let (left, right) = segment.span(y);
+        let left = (left * self.scale_x).max(0.0);
+        let right = (right * self.scale_x).min(self.width as f32);
+
+        if right <= left {
+          continue;
+        }
+
+        // the part of every pixel between left and right
+        for (col, coverage) in coverages.iter_mut().enumerate().take(right.ceil() as usize).skip(left.floor() as usize) {
+          let inside = (col as f32 + 1.0).min(right) - (col as f32).max(left);
+          *coverage += inside / NUM_SUBSAMPLES as f32;
+        }
+
+        first_col = first_col.min(left.floor() as usize);
+        last_col = last_col.max(right.ceil() as usize);
+      }
+
+      for (col, coverage) in coverages.iter_mut().enumerate().take(last_col).skip(first_col) {
+        let pixel_coverage = (coverage.min(1.0) * FULL_COVERAGE as f32).round() as u8;
+
+        if pixel_coverage > 0 {
+          self.draw_pixel(row * self.width + col, color_idx, pixel_coverage, page_idx);
+        }
+
+        *coverage = 0.0;
+      }
+    }
+  }
+
+  fn fill_rect(&mut self, x: f32, y: f32, width: f32, height: f32, color_idx: u8, page_idx: usize) {
+    let first_row = ((y * self.scale_y).round().max(0.0)) as usize;
+    let last_row = (((y + height) * self.scale_y).round().min(self.height as f32)) as usize;
+
+    for row in first_row..last_row {
+      self.draw_hor_line(x, x + width, row, color_idx, page_idx);
+    }
+  }
+
+  // the line goes from x1 to x2 (not included), in coordinates of the game
+  fn draw_hor_line(&mut self, x1: f32, x2: f32, row: usize, color_idx: u8, page_idx: usize) {
+    let first_col = ((x1 * self.scale_x).round().max(0.0)) as usize;
+    let last_col = ((x2 * self.scale_x).round().min(self.width as f32)) as usize;
+    let row_idx = row * self.width;
+
+    for idx in row_idx + first_col..row_idx + last_col.max(first_col) {
+      self.draw_pixel(idx, color_idx, FULL_COVERAGE, page_idx);
+    }
+  }
+
+  // 0x10 makes the color brighter (it's used for the shadows and lights), and 0x11 copies the pixel of the page 0
+  fn draw_pixel(&mut self, idx: usize, color_idx: u8, coverage: u8, page_idx: usize) {
+    let color_below = self.pages[page_idx][idx];
+
+    self.pages[page_idx][idx] = if color_idx < 0x10 {
+      color_idx
+    } else if color_idx > 0x10 {
+      self.pages[0][idx]
+    } else {
+      (color_below & 0x7) + 0x8
+    };
+
+    if self.anti_aliasing {
+      self.coverages[page_idx][idx] = coverage;
+      self.colors_below[page_idx][idx] = color_below;
+    }
+  }
+}
+
+// part of a polygon between two rows of vertices
+struct Segment {
+  top: f32,
+  bottom: f32,
+  left: f32, // x of the left edge in the top
+  left_slope: f32,
+  right: f32, // x of the right edge in the top
+  right_slope: f32
+}
+
+impl Segment {
+  // from the left edge to the right one, with the pixels of the right edge
+  fn span(&self, y: f32) -> (f32, f32) {
+    let left = self.left + (y - self.top) * self.left_slope;
+    let right = self.right + (y - self.top) * self.right_slope;
+
+    (left.min(right), left.max(right) + 1.0)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::poly::{Poly, draw_poly_to_buffer};
+
+  const WIDTH: usize = FRAME_BUFFER_WIDTH as usize;
+
+  // a square from (90.5, 40) to (101.5, 50) in a renderer with the resolution of the game: the pixels of the columns
+  // 90 and 101 are half inside it
+  fn fill_square(renderer: &mut ScaledRenderer, color_idx: u8, page_idx: usize) {
+    let vertices = [(10.5, 0.0), (10.5, 10.0), (0.5, 10.0), (0.5, 0.0)];
+    renderer.fill_polygon((100.0, 50.0), (20.0, 20.0), &vertices, color_idx, page_idx);
+  }
+
+  // a polygon of 30x20 at (0, 0) of the bounding box, in the poly buffer at the offset 0
+  const SQUARE_POLY: [u8; 12] = [0xc3, 30, 20, 4, 30, 0, 30, 20, 0, 20, 0, 0];
+
+  fn coverage(renderer: &ScaledRenderer, x: usize, y: usize) -> u8 {
+    renderer.coverages[1][y * WIDTH + x]
+  }
+
+  #[test]
+  fn edges_have_partial_coverage() {
+    let mut renderer = ScaledRenderer::new(WIDTH, FRAME_BUFFER_HEIGHT as usize);
+    renderer.set_anti_aliasing(true);
+    fill_square(&mut renderer, 0xf, 1);
+
+    assert_eq!(coverage(&renderer, 90, 45), 0x80);
+    assert_eq!(coverage(&renderer, 101, 45), 0x80);
+    assert_eq!(renderer.get_page(1)[45 * WIDTH + 90], 0xf);
+
+    for x in 91..101 {
+      assert_eq!(coverage(&renderer, x, 40), FULL_COVERAGE);
+      assert_eq!(coverage(&renderer, x, 49), FULL_COVERAGE);
+    }
+
+    // the pixels around keep their color
+    assert_eq!(renderer.get_page(1)[45 * WIDTH + 89], 0);
+    assert_eq!(renderer.get_page(1)[45 * WIDTH + 102], 0);
+    assert_eq!(renderer.get_page(1)[50 * WIDTH + 95], 0);
+  }
+
+  #[test]
+  fn brighter_color_uses_the_color_below() {
+    let mut renderer = ScaledRenderer::new(WIDTH, FRAME_BUFFER_HEIGHT as usize);
+    renderer.fill_page(1, 0x5);
+    fill_square(&mut renderer, 0x10, 1);
+
+    assert_eq!(renderer.get_page(1)[45 * WIDTH + 95], 0xd);
+    assert_eq!(renderer.get_page(1)[45 * WIDTH + 80], 0x5);
+  }
+
+  #[test]
+  fn color_0x11_copies_the_page_0() {
+    let mut renderer = ScaledRenderer::new(WIDTH, FRAME_BUFFER_HEIGHT as usize);
+    renderer.fill_page(0, 0x7);
+    renderer.fill_page(1, 0x2);
+    fill_square(&mut renderer, 0x11, 1);
+
+    assert_eq!(renderer.get_page(1)[45 * WIDTH + 95], 0x7);
+    assert_eq!(renderer.get_page(1)[45 * WIDTH + 80], 0x2);
+  }
+
+  #[test]
+  fn anti_aliased_brighter_color_blends_the_color_below() {
+    let mut colors = [[0; 3]; 16];
+    let mut output = vec![0; WIDTH * FRAME_BUFFER_HEIGHT as usize * 4];
+    let mut renderer = ScaledRenderer::new(WIDTH, FRAME_BUFFER_HEIGHT as usize);
+
+    colors[0x5] = [0, 0, 0xff];
+    colors[0xd] = [0xff, 0xff, 0xff];
+
+    renderer.fill_page(1, 0x5);
+    renderer.set_anti_aliasing(true);
+    fill_square(&mut renderer, 0x10, 1);
+    renderer.write_rgba(1, &colors, &mut output);
+
+    let idx = 45 * WIDTH + 90;
+
+    assert_eq!(renderer.get_page(1)[idx], 0xd);
+    assert_eq!(coverage(&renderer, 90, 45), 0x80);
+    assert_eq!(renderer.colors_below[1][idx], 0x5);
+    assert_eq!(output[idx * 4..idx * 4 + 3], [0x80, 0x80, 0xff]);
+    assert_eq!(renderer.get_page(1)[45 * WIDTH + 95], 0xd);
+    assert_eq!(coverage(&renderer, 95, 45), FULL_COVERAGE);
+  }
+
+  #[test]
+  fn anti_aliased_color_0x11_blends_the_page_0() {
+    let mut colors = [[0; 3]; 16];
+    let mut output = vec![0; WIDTH * FRAME_BUFFER_HEIGHT as usize * 4];
+    let mut renderer = ScaledRenderer::new(WIDTH, FRAME_BUFFER_HEIGHT as usize);
+
+    colors[0x2] = [0, 0, 0xff];
+    colors[0x7] = [0xff, 0, 0];
+
+    renderer.fill_page(0, 0x7);
+    renderer.fill_page(1, 0x2);
+    renderer.set_anti_aliasing(true);
+    fill_square(&mut renderer, 0x11, 1);
+    renderer.write_rgba(1, &colors, &mut output);
+
+    let idx = 45 * WIDTH + 101;
+
+    assert_eq!(renderer.get_page(1)[idx], 0x7);
+    assert_eq!(coverage(&renderer, 101, 45), 0x80);
+    assert_eq!(renderer.colors_below[1][idx], 0x2);
+    assert_eq!(output[idx * 4..idx * 4 + 3], [0x80, 0, 0x7f]);
+    assert_eq!(renderer.get_page(1)[45 * WIDTH + 95], 0x7);
+    assert_eq!(renderer.get_page(1)[45 * WIDTH + 102], 0x2);
+  }
+
+  #[test]
+  fn rgba_blends_the_color_below_by_coverage() {
+    let mut colors = [[0; 3]; 16];
+    let mut output = vec![0; WIDTH * FRAME_BUFFER_HEIGHT as usize * 4];
+    let mut renderer = ScaledRenderer::new(WIDTH, FRAME_BUFFER_HEIGHT as usize);
+
+    colors[0x2] = [0, 0, 0xff];
+    colors[0xf] = [0xff, 0xff, 0xff];
+
+    renderer.fill_page(1, 0x2);
+    renderer.set_anti_aliasing(true);
+    fill_square(&mut renderer, 0xf, 1);
+    renderer.write_rgba(1, &colors, &mut output);
+
+    let pixel = |x: usize, y: usize| &output[(y * WIDTH + x) * 4..(y * WIDTH + x) * 4 + 3];
+
+    assert_eq!(pixel(90, 45), [0x80, 0x80, 0xff]);
+    assert_eq!(pixel(95, 45), [0xff, 0xff, 0xff]);
+    assert_eq!(pixel(80, 45), [0, 0, 0xff]);
+  }
+
+  #[test]
+  fn without_anti_aliasing_the_output_is_the_one_of_the_game() {
+    let mut pages = [(); NUM_PAGES].map(|_| vec![0; WIDTH * FRAME_BUFFER_HEIGHT as usize]);
+    let mut renderer = ScaledRenderer::new(WIDTH, FRAME_BUFFER_HEIGHT as usize);
+
+    // the anti-aliasing doesn't leave anything when it's disabled
+    renderer.set_anti_aliasing(true);
+    renderer.set_anti_aliasing(false);
+
+    for (x, y, zoom) in [(160, 100, 64), (10, 5, 64), (315, 195, 64), (100, 80, 128)] {
+      draw_poly_to_buffer(&mut Poly::new(), &SQUARE_POLY, 0, x, y, zoom, 0xff, &mut pages, 1);
+      renderer.draw_poly(&SQUARE_POLY, 0, (x as f32, y as f32), zoom, 0xff, 1);
+
+      assert!(renderer.get_page(1) == pages[1].as_slice(), "poly at ({}, {}) with the zoom {}", x, y, zoom);
+    }
+  }
+}
@@ -0,0 +1,243 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use crate::defines::{FRAME_BUFFER_WIDTH, FRAME_BUFFER_HEIGHT};
+use crate::color::NUM_PALETTE_COLORS;
+use crate::poly::{Shape, parse_shape};
+use crate::draw_list::{DrawCommand, DrawFrame};
+use crate::font::FONT;
+use crate::game_strings::init_game_strings;
+use crate::resources_manager::ResourcesManager;
+
+// Export of the polys to svg, with the colors of a palette. The polys are vector data, so they are exported as svg
+// polygons at any zoom. A frame is built from its recorded draw commands: every page is a list of elements, the pixels
+// of the pages when the frame started and of the bitmaps are rects (a rect for every run of pixels of the same color
+// in a row), and the chars of the strings are rects of their pixels.
+// The special colors are exported with the elements below them: 0x10 (brighter color) draws again the elements of
+// the page inside the polygon with the colors changed, and 0x11 draws the elements of the page 0 inside the polygon.
+// In the export of a shape the pages start empty.
+
+enum Element {
+  Polygon { points: Vec<(f32, f32)>, color_idx: u8 },
+  Rect { x: f32, y: f32, width: f32, height: f32, color_idx: u8 },
+  Clipped { clip: Vec<(f32, f32)>, elements: Vec<Element> },
+  Translated { dy: f32, elements: Vec<Element> }
+}
+
+impl Clone for Element {
+  fn clone(&self) -> Element {
+    self.map_colors(&|color_idx| color_idx)
+  }
+}
+
+impl Element {
+  fn map_colors(&self, map: &dyn Fn(u8) -> u8) -> Element {
+    match self {
+      Element::Polygon { points, color_idx } => Element::Polygon { points: points.clone(), color_idx: map(*color_idx) },
+      Element::Rect { x, y, width, height, color_idx } => Element::Rect { x: *x, y: *y, width: *width, height: *height, color_idx: map(*color_idx) },
+      Element::Clipped { clip, elements } => Element::Clipped { clip: clip.clone(), elements: elements.iter().map(|e| e.map_colors(map)).collect() },
+      Element::Translated { dy, elements } => Element::Translated { dy: *dy, elements: elements.iter().map(|e| e.map_colors(map)).collect() }
+    }
+  }
+
+  fn write(&self, out: &mut String, colors: &[[u8; 3]; NUM_PALETTE_COLORS], num_clips: &mut usize) {
+    match self {
+      Element::Polygon { points, color_idx } => {
+        writeln!(out, "<polygon points=\"{}\" fill=\"{}\"/>", format_points(points), format_color(colors, *color_idx)).unwrap();
+      },
+      Element::Rect { x, y, width, height, color_idx } => {
+        writeln!(out, "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"{}\"/>", x, y, width, height, format_color(colors, *color_idx)).unwrap();
+      },
+      Element::Clipped { clip, elements } => {
+        let clip_id = *num_clips;
+        *num_clips += 1;
+
+        writeln!(out, "<clipPath id=\"clip{}\"><polygon points=\"{}\"/></clipPath>", clip_id, format_points(clip)).unwrap();
+        writeln!(out, "<g clip-path=\"url(#clip{})\">", clip_id).unwrap();
+        elements.iter().for_each(|element| element.write(out, colors, num_clips));
+        writeln!(out, "</g>").unwrap();
+      },
+      Element::Translated { dy, elements } => {
+        writeln!(out, "<g transform=\"translate(0 {})\">", dy).unwrap();
+        elements.iter().for_each(|element| element.write(out, colors, num_clips));
+        writeln!(out, "</g>").unwrap();
+      }
+    }
+  }
+
+  // (min x, min y, max x, max y)
+  fn get_bounds(&self, bounds: &mut (f32, f32, f32, f32)) {
+    let mut add_point = |(x, y): (f32, f32)| *bounds = (bounds.0.min(x), bounds.1.min(y), bounds.2.max(x), bounds.3.max(y));
+
+    match self {
+      Element::Polygon { points, .. } => points.iter().for_each(|point| add_point(*point)),
+      Element::Rect { x, y, width, height, .. } => {
+        add_point((*x, *y));
+        add_point((x + width, y + height));
+      },
+      Element::Clipped { clip, .. } => clip.iter().for_each(|point| add_point(*point)),
+      Element::Translated { elements, .. } => elements.iter().for_each(|element| element.get_bounds(bounds))
+    }
+  }
+}
+
+// the shape is drawn at (0, 0), the svg has the size of the shape
+pub fn shape_to_svg(poly_buffer: &[u8], offset: u16, zoom: i16, colors: &[[u8; 3]; NUM_PALETTE_COLORS]) -> Result<String, String> {
+  let shape = parse_shape(poly_buffer, offset)?;
+  let mut pages: Vec<Vec<Element>> = vec![Vec::new(); 4];
+  let mut bounds = (f32::MAX, f32::MAX, f32::MIN, f32::MIN);
+
+  add_shape(&mut pages, 1, &shape, (0.0, 0.0), zoom as f32 / 64.0, 0xff);
+
+  pages[1].iter().for_each(|element| element.get_bounds(&mut bounds));
+
+  if pages[1].is_empty() {
+    bounds = (0.0, 0.0, 0.0, 0.0);
+  }
+
+  Ok(build_svg(&pages[1], (bounds.0, bounds.1, bounds.2 - bounds.0, bounds.3 - bounds.1), colors))
+}
+
+// the frame is built from all its commands, and the svg has the page page_idx
+pub fn frame_to_svg(frame: &DrawFrame, page_idx: usize, resources_manager: &ResourcesManager, colors: &[[u8; 3]; NUM_PALETTE_COLORS]) -> String {
+  let mut pages: Vec<Vec<Element>> = frame.start_pages.iter().map(|page| page_to_rects(page)).collect();
+  let mut game_strings = HashMap::new();
+
+  init_game_strings(&mut game_strings);
+
+  for entry in &frame.entries {
+    let page_idx = entry.page_idx as usize;
+
+    match entry.command {
+      DrawCommand::FillPage { color_idx } => {
+        pages[page_idx] = vec![Element::Rect { x: 0.0, y: 0.0, width: FRAME_BUFFER_WIDTH as f32, height: FRAME_BUFFER_HEIGHT as f32, color_idx }];
+      },
+      DrawCommand::CopyPage { src_page_idx, vscroll } => {
+        let src = pages[src_page_idx as usize].clone();
+
+        if vscroll == 0 {
+          pages[page_idx] = src;
+        } else {
+          pages[page_idx].push(Element::Translated { dy: vscroll as f32, elements: src });
+        }
+      },
+      DrawCommand::Poly { poly_file_id, offset, x, y, zoom, .. } => {
+        // the broken polys are skipped
+        if let Ok(shape) = parse_shape(resources_manager.get_file(poly_file_id), offset) {
+          add_shape(&mut pages, page_idx, &shape, (x as f32, y as f32), zoom as f32 / 64.0, 0xff);
+        }
+      },
+      DrawCommand::String { string_id, x, y, color_idx } => {
+        if let Some(string) = game_strings.get(&string_id) {
+          add_string(&mut pages[page_idx], string, x, y, color_idx);
+        }
+      },
+      DrawCommand::Bitmap { resource_id } => {
+        pages[0] = page_to_rects(resources_manager.get_file(resource_id));
+      }
+    }
+  }
+
+  build_svg(&pages[page_idx], (0.0, 0.0, FRAME_BUFFER_WIDTH as f32, FRAME_BUFFER_HEIGHT as f32), colors)
+}
+
+fn build_svg(elements: &[Element], (x, y, width, height): (f32, f32, f32, f32), colors: &[[u8; 3]; NUM_PALETTE_COLORS]) -> String {
+  let mut out = String::new();
+  let mut num_clips = 0;
+
+  writeln!(out, "<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"{} {} {} {}\" width=\"{}\" height=\"{}\">", x, y, width, height, width, height).unwrap();
+  elements.iter().for_each(|element| element.write(&mut out, colors, &mut num_clips));
+  writeln!(out, "</svg>").unwrap();
+
+  out
+}
+
+// like draw_poly_to_buffer, the color 0xff means the colors of the polygons
+fn add_shape(pages: &mut Vec<Vec<Element>>, page_idx: usize, shape: &Shape, (x, y): (f32, f32), scale: f32, color_idx: u8) {
+  match shape {
+    Shape::Polygon { bounding_box, vertices, color_idx: polygon_color_idx, .. } => {
+      let color_idx = if (color_idx & 0x80) != 0 { *polygon_color_idx } else { color_idx };
+      let bounding_box_width = bounding_box.0 as f32 * scale;
+      let bounding_box_height = bounding_box.1 as f32 * scale;
+      let x1 = x - bounding_box_width / 2.0;
+      let y1 = y - bounding_box_height / 2.0;
+
+      // a point
+      let points: Vec<(f32, f32)> = if bounding_box_width.trunc() == 0.0 && bounding_box_height.trunc() == 1.0 && vertices.len() == 4 {
+        vec![(x, y), (x + 1.0, y), (x + 1.0, y + 1.0), (x, y + 1.0)]
+      } else {
+        vertices.iter().map(|(vx, vy)| (x1 + *vx as f32 * scale, y1 + *vy as f32 * scale)).collect()
+      };
+
+      let element = if color_idx < 0x10 {
+        Element::Polygon { points, color_idx }
+      } else if color_idx > 0x10 {
+        Element::Clipped { clip: points, elements: pages[0].clone() }
+      } else {
+        Element::Clipped { clip: points, elements: pages[page_idx].iter().map(|element| element.map_colors(&|c| (c & 0x7) + 0x8)).collect() }
+      };
+
+      pages[page_idx].push(element);
+    },
+    Shape::Group { origin, children, .. } => {
+      let nx = x - origin.0 as f32 * scale;
+      let ny = y - origin.1 as f32 * scale;
+
+      for child in children {
+        let position = (nx + child.x as f32 * scale, ny + child.y as f32 * scale);
+        add_shape(pages, page_idx, &child.shape, position, scale, child.color_idx.unwrap_or(0xff));
+      }
+    }
+  }
+}
+
+fn add_string(elements: &mut Vec<Element>, string: &str, x: i16, y: i16, color_idx: u8) {
+  let mut wx = x * 8;
+  let mut wy = y;
+
+  for c in string.chars() {
+    if c == '\n' {
+      wx = x * 8;
+      wy += 8;
+      continue;
+    }
+
+    let char_idx = ((c as u8 - b' ') as usize) * 8;
+
+    for (row, bits) in FONT[char_idx..char_idx + 8].iter().enumerate() {
+      for col in 0..8 {
+        if bits & (1 << (7 - col)) != 0 {
+          elements.push(Element::Rect { x: (wx + col) as f32, y: (wy + row as i16) as f32, width: 1.0, height: 1.0, color_idx });
+        }
+      }
+    }
+
+    wx += 8;
+  }
+}
+
+// a rect for every run of pixels of the same color in a row
+fn page_to_rects(page: &[u8]) -> Vec<Element> {
+  let mut elements = Vec::new();
+
+  for (y, row) in page.chunks_exact(FRAME_BUFFER_WIDTH as usize).enumerate() {
+    let mut start = 0;
+
+    for x in 1..=row.len() {
+      if x == row.len() || row[x] != row[start] {
+        elements.push(Element::Rect { x: start as f32, y: y as f32, width: (x - start) as f32, height: 1.0, color_idx: row[start] });
+        start = x;
+      }
+    }
+  }
+
+  elements
+}
+
+fn format_points(points: &[(f32, f32)]) -> String {
+  points.iter().map(|(x, y)| format!("{},{}", x, y)).collect::<Vec<String>>().join(" ")
+}
+
+fn format_color(colors: &[[u8; 3]; NUM_PALETTE_COLORS], color_idx: u8) -> String {
+  let [r, g, b] = colors[color_idx as usize & 0xf];
+  format!("#{:02x}{:02x}{:02x}", r, g, b)
+}
@@ -0,0 +1,236 @@
+use std::collections::BTreeMap;
+use std::fs;
+use serde::{Serialize, Deserialize};
+use crate::assembler::is_valid_label;
+use crate::defines::PARTS_FILE_IDS;
+
+// Names and comments for the registers, and label names and comments per game part. They are loaded from a json
+// file like this one (the numbers are in hex, but the part numbers):
+// {
+//   "registers": { "3C": { "name": "RandomSeed", "comment": "seed of the random numbers" } },
+//   "parts": { "1": { "labels": { "0010": "intro_loop" }, "comments": { "0010": "waits for the music" } } }
+// }
+
+const DEFAULT_SYMBOLS: &str = include_str!("../../data/symbols.json");
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RegisterSymbol {
+  pub name: String,
+  #[serde(default, skip_serializing_if = "String::is_empty")]
+  pub comment: String
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PartSymbols {
+  pub labels: BTreeMap<u16, String>,
+  pub comments: BTreeMap<u16, String>
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SymbolTable {
+  pub registers: BTreeMap<u8, RegisterSymbol>,
+  pub parts: BTreeMap<u8, PartSymbols>
+}
+
+// layout of the json file
+#[derive(Default, Serialize, Deserialize)]
+struct SymbolFile {
+  #[serde(default)]
+  registers: BTreeMap<String, RegisterSymbol>,
+  #[serde(default)]
+  parts: BTreeMap<String, PartFile>
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct PartFile {
+  #[serde(default)]
+  labels: BTreeMap<String, String>,
+  #[serde(default)]
+  comments: BTreeMap<String, String>
+}
+
+impl SymbolTable {
+  // the symbols bundled with the engine: only the registers with a known use in the original engine, without labels or
+  // comments for the parts. The rest is loaded from a file
+  pub fn new() -> SymbolTable {
+    SymbolTable::from_json(DEFAULT_SYMBOLS).expect("invalid default symbols")
+  }
+
+  pub fn from_file(path: &str) -> Result<SymbolTable, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("can't read {}: {}", path, e))?;
+    SymbolTable::from_json(&text)
+  }
+
+  pub fn from_json(text: &str) -> Result<SymbolTable, String> {
+    let file: SymbolFile = serde_json::from_str(text).map_err(|e| e.to_string())?;
+    let mut table = SymbolTable::default();
+
+    for (key, symbol) in file.registers {
+      let reg_id = u8::from_str_radix(&key, 16).map_err(|_| format!("invalid register '{}'", key))?;
+
+      if !is_valid_name(&symbol.name) {
+        return Err(format!("invalid name '{}' for the register {}", symbol.name, key));
+      }
+
+      table.registers.insert(reg_id, symbol);
+    }
+
+    for (key, part_file) in file.parts {
+      let part = key.parse::<u8>().ok().filter(|part| (*part as usize) < PARTS_FILE_IDS.len()).ok_or_else(|| format!("invalid part '{}'", key))?;
+      let mut symbols = PartSymbols::default();
+
+      for (addr, label) in part_file.labels {
+        let addr = parse_addr(&addr)?;
+
+        if !is_valid_name(&label) {
+          return Err(format!("invalid label '{}' in the part {}", label, part));
+        }
+
+        if symbols.labels.values().any(|l| *l == label) {
+          return Err(format!("label '{}' is defined twice in the part {}", label, part));
+        }
+
+        symbols.labels.insert(addr, label);
+      }
+
+      for (addr, comment) in part_file.comments {
+        // the comments are shown at the end of a line
+        symbols.comments.insert(parse_addr(&addr)?, comment.replace('\n', " "));
+      }
+
+      table.parts.insert(part, symbols);
+    }
+
+    Ok(table)
+  }
+
+  pub fn to_json(&self) -> String {
+    let mut file = SymbolFile::default();
+
+    for (reg_id, symbol) in &self.registers {
+      file.registers.insert(format!("{:02X}", reg_id), symbol.clone());
+    }
+
+    for (part, symbols) in &self.parts {
+      let part_file = PartFile {
+        labels: symbols.labels.iter().map(|(addr, label)| (format!("{:04X}", addr), label.clone())).collect(),
+        comments: symbols.comments.iter().map(|(addr, comment)| (format!("{:04X}", addr), comment.clone())).collect()
+      };
+
+      file.parts.insert(part.to_string(), part_file);
+    }
+
+    serde_json::to_string_pretty(&file).unwrap()
+  }
+
+  pub fn get_register_name(&self, reg_id: u8) -> String {
+    match self.registers.get(&reg_id) {
+      Some(symbol) => symbol.name.clone(),
+      None => format!("r[{:02X}]", reg_id)
+    }
+  }
+
+  // symbols of the part that runs the script
+  pub fn get_script_symbols(&self, script_file_id: u8) -> Option<&PartSymbols> {
+    let part = PARTS_FILE_IDS.iter().position(|ids| ids[1] == script_file_id)?;
+    self.parts.get(&(part as u8))
+  }
+
+  // replaces the registers of a line of the disassembler (r[XX]) by their names
+  pub fn format_asm_code(&self, asm_code: &str) -> String {
+    let mut text = String::with_capacity(asm_code.len());
+    let mut rest = asm_code;
+
+    while let Some(idx) = rest.find("r[") {
+      let reg_id = rest.get(idx + 2..idx + 5).filter(|reg| reg.ends_with(']')).and_then(|reg| u8::from_str_radix(&reg[..2], 16).ok());
+
+      match reg_id {
+        Some(reg_id) => {
+          text.push_str(&rest[..idx]);
+          text.push_str(&self.get_register_name(reg_id));
+          rest = &rest[idx + 5..];
+        },
+        None => {
+          text.push_str(&rest[..idx + 2]);
+          rest = &rest[idx + 2..];
+        }
+      }
+    }
+
+    text.push_str(rest);
+    text
+  }
+}
+
+// the names are sent to javascript with a byte for their length
+fn is_valid_name(name: &str) -> bool {
+  name.len() <= 0xff && is_valid_label(name)
+}
+
+fn parse_addr(text: &str) -> Result<u16, String> {
+  u16::from_str_radix(text, 16).map_err(|_| format!("invalid address '{}'", text))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::virtual_machine::ScriptRegs;
+
+  const SYMBOLS: &str = r#"{
+    "registers": { "10": { "name": "counter", "comment": "frames left" }, "2a": { "name": "door" } },
+    "parts": { "2": { "labels": { "0010": "main_loop", "00A0": "open_door" }, "comments": { "0010": "waits for\nthe door" } } }
+  }"#;
+
+  #[test]
+  fn symbols_are_loaded_from_json() {
+    let table = SymbolTable::from_json(SYMBOLS).unwrap();
+
+    assert_eq!(table.registers[&0x10], RegisterSymbol { name: "counter".to_string(), comment: "frames left".to_string() });
+    assert_eq!(table.get_register_name(0x2a), "door");
+    assert_eq!(table.get_register_name(0x11), "r[11]");
+
+    let part_symbols = table.get_script_symbols(PARTS_FILE_IDS[2][1]).unwrap();
+
+    assert_eq!(part_symbols.labels[&0x10], "main_loop");
+    assert_eq!(part_symbols.labels[&0xa0], "open_door");
+    assert_eq!(part_symbols.comments[&0x10], "waits for the door");
+    assert!(table.get_script_symbols(PARTS_FILE_IDS[3][1]).is_none());
+
+    assert_eq!(SymbolTable::from_json(&table.to_json()).unwrap(), table);
+  }
+
+  #[test]
+  fn invalid_symbols_are_rejected() {
+    let invalid = [
+      r#"{ "registers": { "100": { "name": "counter" } } }"#,
+      r#"{ "registers": { "10": { "name": "not a name" } } }"#,
+      r#"{ "parts": { "9": { "labels": { "0010": "main_loop" } } } }"#,
+      r#"{ "parts": { "2": { "labels": { "0010": "main_loop", "0020": "main_loop" } } } }"#,
+      r#"{ "parts": { "2": { "labels": { "0010": "1234" } } } }"#,
+      r#"{ "parts": { "2": { "comments": { "zz": "comment" } } } }"#
+    ];
+
+    for json in invalid {
+      assert!(SymbolTable::from_json(json).is_err(), "{}", json);
+    }
+  }
+
+  #[test]
+  fn default_symbols_name_the_registers_of_the_engine() {
+    let table = SymbolTable::new();
+
+    assert_eq!(table.get_register_name(ScriptRegs::PauseSlices as u8), "PauseSlices");
+    assert_eq!(table.get_register_name(ScriptRegs::ScrollY as u8), "ScrollY");
+    assert!(table.parts.is_empty());
+  }
+
+  #[test]
+  fn registers_of_asm_code_are_replaced() {
+    let table = SymbolTable::from_json(SYMBOLS).unwrap();
+
+    assert_eq!(table.format_asm_code("ADD r[10], r[2A]"), "ADD counter, door");
+    assert_eq!(table.format_asm_code("CJLE r[10], r[11], 0030"), "CJLE counter, r[11], 0030");
+    assert_eq!(table.format_asm_code("MOV r[1, r[10]"), "MOV r[1, counter");
+    assert_eq!(table.format_asm_code("JMP 0010"), "JMP 0010");
+  }
+}
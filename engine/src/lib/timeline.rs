@@ -0,0 +1,83 @@
+use std::collections::VecDeque;
+
+const MAX_TIMELINE_FRAMES: usize = 300; // frames kept in the timeline
+pub const HOST_THREAD_ID: u8 = 0xff; // by_thread_id of the changes requested by the host (pc is 0xffff)
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ThreadChangeKind {
+  SetVec(u16), // the thread will start at the address in the next frame
+  Resume,
+  Pause,
+  Kill
+}
+
+// a change of a thread requested by a SETVEC or RESET instruction. It takes effect in the next frame
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ThreadChange {
+  pub thread_id: u8,
+  pub by_thread_id: u8,
+  pub pc: u16, // address of the instruction that requested the change
+  pub kind: ThreadChangeKind
+}
+
+// the instructions a thread has executed in a frame, from start_pc until it yielded (or was killed) at end_pc
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ThreadRun {
+  pub thread_id: u8,
+  pub start_pc: u16,
+  pub end_pc: u16,
+  pub instructions: u32
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FrameTimeline {
+  pub frame: u32,
+  pub runs: Vec<ThreadRun>,
+  pub changes: Vec<ThreadChange>
+}
+
+// threads that have run in the last frames, in the order they have been scheduled
+pub struct Timeline {
+  pub frames: VecDeque<FrameTimeline>,
+  pub current: FrameTimeline
+}
+
+impl Default for Timeline {
+  fn default() -> Timeline {
+    Timeline::new()
+  }
+}
+
+impl Timeline {
+  pub fn new() -> Timeline {
+    Timeline {
+      frames: VecDeque::with_capacity(MAX_TIMELINE_FRAMES),
+      current: FrameTimeline::default()
+    }
+  }
+
+  pub fn count(&mut self, thread_id: u8, pc: u16) {
+    match self.current.runs.last_mut() {
+      Some(run) if run.thread_id == thread_id => {
+        run.end_pc = pc;
+        run.instructions += 1;
+      },
+      _ => self.current.runs.push(ThreadRun { thread_id, start_pc: pc, end_pc: pc, instructions: 1 })
+    }
+  }
+
+  pub fn add_change(&mut self, change: ThreadChange) {
+    self.current.changes.push(change);
+  }
+
+  pub fn end_frame(&mut self) {
+    if self.frames.len() == MAX_TIMELINE_FRAMES {
+      self.frames.pop_front();
+    }
+
+    let frame = self.current.frame;
+
+    self.frames.push_back(std::mem::take(&mut self.current));
+    self.current.frame = frame + 1;
+  }
+}
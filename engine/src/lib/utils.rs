@@ -2,6 +2,10 @@ pub fn write_u16(mem: &mut [u8], addr: usize, value: u16) {
   mem[addr..addr + 2].copy_from_slice(&value.to_le_bytes());
 }
 
+pub fn write_u32(mem: &mut [u8], addr: usize, value: u32) {
+  mem[addr..addr + 4].copy_from_slice(&value.to_le_bytes());
+}
+
 pub fn read_u16(mem: &[u8], addr: u16) -> u16 {
   let mut buffer: [u8; 2] = [0; 2];
   buffer.copy_from_slice(&mem[addr as usize..(addr + 2) as usize]);
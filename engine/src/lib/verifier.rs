@@ -0,0 +1,197 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use crate::instruction::{Instruction, decode};
+use crate::game_strings::init_game_strings;
+use crate::defines::{BASE_PART_ID, NUM_THREADS, PARTS_FILE_IDS};
+
+// Static checks of a script, to find the problems of a modified script before running it:
+// - JMP, JNZ, CJxx, CALL and SETVEC targets outside the script or in the middle of an instruction
+// - instructions that don't fit in the script, and execution running past the end of the script
+// - DRAWPOLY offsets beyond the poly buffer
+// - DRAWSTR strings that don't exist, LDRES resources and parts that don't exist, and invalid thread ids
+
+#[derive(Debug, PartialEq)]
+pub struct VerifierError {
+  pub pc: u16,
+  pub message: String
+}
+
+impl fmt::Display for VerifierError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{:04X}: {}", self.pc, self.message)
+  }
+}
+
+// the poly buffers are the ones of the part that runs the script (None if the part doesn't have them)
+pub fn verify(script: &[u8], poly_buffer_1: Option<&[u8]>, poly_buffer_2: Option<&[u8]>, num_resources: usize) -> Vec<VerifierError> {
+  let mut game_strings = HashMap::new();
+  let mut errors = Vec::new();
+  let mut instructions = Vec::new();
+  let mut boundaries = BTreeSet::new();
+  let mut pc: usize = 0;
+
+  init_game_strings(&mut game_strings);
+
+  // the script is decoded with some padding to tell the truncated instructions from the invalid ones
+  let mut padded_script = script.to_vec();
+  padded_script.extend_from_slice(&[0; 8]);
+
+  while pc < script.len() {
+    boundaries.insert(pc as u16);
+
+    match decode(&padded_script, pc as u16) {
+      Some((_, len)) if pc + len as usize > script.len() => {
+        errors.push(VerifierError { pc: pc as u16, message: "the instruction runs past the end of the script".to_string() });
+        break;
+      },
+      Some((instruction, len)) => {
+        instructions.push((pc as u16, instruction, len));
+        pc += len as usize;
+      },
+      None => {
+        errors.push(VerifierError { pc: pc as u16, message: format!("invalid instruction (opcode {:02X})", script[pc]) });
+        pc += 1;
+      }
+    }
+  }
+
+  if let Some((pc, instruction, len)) = instructions.last() {
+    if *pc as usize + *len as usize == script.len() && !matches!(instruction, Instruction::Jmp { .. } | Instruction::Ret | Instruction::Kill) {
+      errors.push(VerifierError { pc: *pc, message: "the execution continues past the end of the script".to_string() });
+    }
+  }
+
+  for (pc, instruction, _) in &instructions {
+    let mut error = |message: String| errors.push(VerifierError { pc: *pc, message });
+
+    let target = match instruction {
+      Instruction::SetVec { addr, .. } => Some(*addr),
+      _ => instruction.branch_target()
+    };
+
+    if let Some(target) = target {
+      if target as usize >= script.len() {
+        error(format!("the target {:04X} is outside the script", target));
+      } else if !boundaries.contains(&target) {
+        error(format!("the target {:04X} is in the middle of an instruction", target));
+      }
+    }
+
+    if let Some((buffer, offset)) = instruction.get_poly() {
+      let poly_buffer = if buffer == 1 { poly_buffer_1 } else { poly_buffer_2 };
+
+      match poly_buffer {
+        Some(poly_buffer) if offset as usize >= poly_buffer.len() => {
+          error(format!("the offset {:04X} is beyond the poly buffer {} ({:04X} bytes)", offset, buffer, poly_buffer.len()));
+        },
+        Some(_) => {},
+        None => error(format!("the part has no poly buffer {}", buffer))
+      }
+    }
+
+    match *instruction {
+      Instruction::SetVec { thread_id, .. } if thread_id as usize >= NUM_THREADS => {
+        error(format!("the thread {:02X} doesn't exist", thread_id));
+      },
+      Instruction::ResetThreads { first, last, .. } if last < first || last as usize >= NUM_THREADS => {
+        error(format!("invalid range of threads {:02X}-{:02X}", first, last));
+      },
+      Instruction::DrawString { string_id, .. } if !game_strings.contains_key(&string_id) => {
+        error(format!("the string {:03X} doesn't exist", string_id));
+      },
+      Instruction::LoadResource { resource_id } => {
+        if resource_id > 0xff {
+          if resource_id < BASE_PART_ID || (resource_id - BASE_PART_ID) as usize >= PARTS_FILE_IDS.len() {
+            error(format!("the part {:04X} doesn't exist", resource_id));
+          }
+        } else if resource_id as usize >= num_resources {
+          error(format!("the resource {:02X} doesn't exist", resource_id));
+        }
+      },
+      _ => {}
+    }
+  }
+
+  errors.sort_by_key(|error| error.pc);
+  errors
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::assembler::assemble;
+
+  // the part has a poly buffer 1 of 0x100 bytes, no poly buffer 2, and there are 0x20 resources
+  fn check_script(script: &[u8]) -> Vec<String> {
+    verify(script, Some(&[0; 0x100]), None, 0x20).iter().map(|error| error.to_string()).collect()
+  }
+
+  fn check(source: &str) -> Vec<String> {
+    check_script(&assemble(source).unwrap())
+  }
+
+  #[test]
+  fn valid_script_has_no_errors() {
+    let source = "
+        SETVEC 3F, thread
+        RESET 00, 3F, KILL
+        DRAWSTR 0001, 00, 00, 0F
+        LDRES 001F
+        LDRES 3E88
+        DRAWPOLY1 00FE, 10, 20, 40
+        CJZ r[10], 00, thread
+        CALL func
+        KILL
+      func:
+        RET
+      thread:
+        JMP thread
+    ";
+
+    assert!(check(source).is_empty(), "{:?}", check(source));
+  }
+
+  #[test]
+  fn targets_have_to_be_instructions_of_the_script() {
+    assert_eq!(check("JMP 0100"), ["0000: the target 0100 is outside the script"]);
+    assert_eq!(check("CALL 0001\nKILL"), ["0000: the target 0001 is in the middle of an instruction"]);
+    assert_eq!(check("SETVEC 01, 0005\nKILL"), ["0000: the target 0005 is outside the script"]);
+  }
+
+  #[test]
+  fn instructions_have_to_fit_in_the_script() {
+    assert_eq!(check_script(&[0x11, 0x07, 0x00]), ["0001: the instruction runs past the end of the script"]);
+    assert_eq!(check("YIELD"), ["0000: the execution continues past the end of the script"]);
+    assert!(check("YIELD\nRET").is_empty());
+  }
+
+  #[test]
+  fn invalid_opcodes_are_found() {
+    assert_eq!(check_script(&[0x1b, 0x11]), ["0000: invalid instruction (opcode 1B)"]);
+  }
+
+  #[test]
+  fn poly_offsets_have_to_be_in_the_poly_buffer() {
+    assert_eq!(check("DRAWPOLY1 0100, 10, 20, 40\nKILL"), ["0000: the offset 0100 is beyond the poly buffer 1 (0100 bytes)"]);
+    assert_eq!(check("DRAWPOLY2 0000, 10, 20, 40\nKILL"), ["0000: the part has no poly buffer 2"]);
+  }
+
+  #[test]
+  fn threads_have_to_exist() {
+    assert_eq!(check("SETVEC 40, 0000\nKILL"), ["0000: the thread 40 doesn't exist"]);
+    assert_eq!(check("RESET 10, 05, KILL\nKILL"), ["0000: invalid range of threads 10-05"]);
+    assert_eq!(check("RESET 00, 40, KILL\nKILL"), ["0000: invalid range of threads 00-40"]);
+  }
+
+  #[test]
+  fn strings_have_to_exist() {
+    assert_eq!(check("DRAWSTR 0FFF, 00, 00, 0F\nKILL"), ["0000: the string FFF doesn't exist"]);
+  }
+
+  #[test]
+  fn resources_and_parts_have_to_exist() {
+    assert_eq!(check("LDRES 0020\nKILL"), ["0000: the resource 20 doesn't exist"]);
+    assert_eq!(check("LDRES 0100\nKILL"), ["0000: the part 0100 doesn't exist"]);
+    assert_eq!(check("LDRES 3E89\nKILL"), ["0000: the part 3E89 doesn't exist"]);
+  }
+}
@@ -3,6 +3,9 @@ use crate::defines::{FRAME_BUFFER_WIDTH, FRAME_BUFFER_HEIGHT, INVALID_PALETTE};
 use crate::game_strings::init_game_strings;
 use crate::font::FONT;
 use crate::poly::{Poly, draw_poly_to_buffer};
+use crate::scaled_renderer::ScaledRenderer;
+use crate::draw_list::{DrawRecorder, DrawCommand, DrawEntry, DrawFrame, PixelOwner};
+use crate::resources_manager::ResourcesManager;
 
 const NUM_PAGES: usize = 4;
 
@@ -13,7 +16,13 @@ pub struct Video {
   background_builder_page_idx: usize,
   palette_id: u8,
   next_palette_id: u8,
-  game_strings: HashMap<u16, &'static str>
+  game_strings: HashMap<u16, &'static str>,
+  pub scaled_renderer: Option<ScaledRenderer>, // draws the same operations at other resolution
+  pub draw_recorder: Option<DrawRecorder>,
+  pixel_owners: Option<[Vec<Option<PixelOwner>>; NUM_PAGES]>, // the command that drew every pixel of the pages
+  draw_thread_id: u8, // instruction that is being executed
+  draw_pc: u16,
+  poly_file_ids: (u8, u8) // file ids of the poly buffers 1 and 2 of the part
 }
 
 impl Video {
@@ -30,7 +39,126 @@ impl Video {
       background_builder_page_idx: 1,
       palette_id: 0,
       next_palette_id: INVALID_PALETTE,
-      game_strings: game_strings
+      game_strings: game_strings,
+      scaled_renderer: None,
+      draw_recorder: None,
+      pixel_owners: None,
+      draw_thread_id: 0,
+      draw_pc: 0,
+      poly_file_ids: (0, 0)
+    }
+  }
+
+  // the recording starts with the current frame
+  pub fn enable_draw_recorder(&mut self, enabled: bool) {
+    self.draw_recorder = if enabled { Some(DrawRecorder::new(&self.pages)) } else { None };
+  }
+
+  // the pixels drawn before are not owned by any command
+  pub fn enable_pixel_owners(&mut self, enabled: bool) {
+    let size = (FRAME_BUFFER_WIDTH * FRAME_BUFFER_HEIGHT) as usize;
+    self.pixel_owners = if enabled { Some([vec![None; size], vec![None; size], vec![None; size], vec![None; size]]) } else { None };
+  }
+
+  // the command that drew the pixel of the page (0xfe and 0xff are the front buffer and the background builder)
+  pub fn who_drew(&self, page_id: u8, x: u16, y: u16) -> Option<PixelOwner> {
+    if x >= FRAME_BUFFER_WIDTH || y >= FRAME_BUFFER_HEIGHT {
+      return None;
+    }
+
+    let page_idx = self.get_page_idx(page_id);
+    self.pixel_owners.as_ref().and_then(|owners| owners[page_idx][(y * FRAME_BUFFER_WIDTH + x) as usize])
+  }
+
+  // called by the vm before executing an instruction
+  pub fn set_draw_source(&mut self, thread_id: u8, pc: u16) {
+    self.draw_thread_id = thread_id;
+    self.draw_pc = pc;
+  }
+
+  pub fn set_poly_file_ids(&mut self, poly_file_ids: (u8, u8)) {
+    self.poly_file_ids = poly_file_ids;
+  }
+
+  // draws the first num_entries commands of the frame in the pages it had when it started. Returns the page of the
+  // last command drawn (the backbuffer if there isn't any)
+  pub fn replay_draw_frame(&mut self, frame: &DrawFrame, num_entries: usize, resources_manager: &ResourcesManager) -> usize {
+    let mut page_idx = self.backbuffer_page_idx;
+
+    for (idx, page) in frame.start_pages.iter().enumerate() {
+      self.pages[idx].clone_from_slice(page);
+    }
+
+    for entry in frame.entries.iter().take(num_entries) {
+      let page_id = entry.page_idx;
+      page_idx = page_id as usize;
+
+      match entry.command {
+        DrawCommand::FillPage { color_idx } => self.fill_page(page_id, color_idx),
+        DrawCommand::CopyPage { src_page_idx, vscroll } => self.copy_page(0x80 | src_page_idx, page_id, vscroll),
+        DrawCommand::Poly { poly_buffer, poly_file_id, offset, x, y, zoom } => {
+          self.set_backbuffer_page(page_id);
+          self.draw_poly(resources_manager.get_file(poly_file_id), poly_buffer, offset, x, y, zoom);
+        },
+        DrawCommand::String { string_id, x, y, color_idx } => {
+          self.set_backbuffer_page(page_id);
+          self.draw_string(string_id, x, y, color_idx);
+        },
+        DrawCommand::Bitmap { resource_id } => self.draw_bitmap(resources_manager.get_file(resource_id), resource_id)
+      }
+    }
+
+    page_idx
+  }
+
+  pub fn get_page(&self, page_idx: usize) -> &[u8] {
+    &self.pages[page_idx]
+  }
+
+  fn record(&mut self, page_idx: usize, command: DrawCommand) {
+    if let Some(recorder) = &mut self.draw_recorder {
+      recorder.record(DrawEntry { thread_id: self.draw_thread_id, pc: self.draw_pc, page_idx: page_idx as u8, command });
+    }
+  }
+
+  fn new_pixel_owner(&self, kind: u8, poly_offset: u16) -> Option<PixelOwner> {
+    Some(PixelOwner { thread_id: self.draw_thread_id, pc: self.draw_pc, kind, poly_offset })
+  }
+
+  // the scaled renderer starts with the current pages scaled, until they are redrawn at its resolution
+  pub fn enable_scaled_renderer(&mut self, width: usize, height: usize) {
+    if width == 0 || height == 0 {
+      self.scaled_renderer = None;
+      return;
+    }
+
+    let mut renderer = ScaledRenderer::new(width, height);
+
+    for (page_idx, page) in self.pages.iter().enumerate() {
+      renderer.draw_page(page_idx, page);
+    }
+
+    self.scaled_renderer = Some(renderer);
+  }
+
+  pub fn get_scaled_screen_page(&self) -> Option<&[u8]> {
+    self.scaled_renderer.as_ref().map(|renderer| renderer.get_page(self.backbuffer_page_idx))
+  }
+
+  pub fn set_scaled_anti_aliasing(&mut self, enabled: bool) {
+    if let Some(renderer) = &mut self.scaled_renderer {
+      renderer.set_anti_aliasing(enabled);
+    }
+  }
+
+  // returns false if there isn't a scaled renderer
+  pub fn write_scaled_screen_rgba(&self, colors: &[[u8; 3]; 16], output: &mut [u8]) -> bool {
+    match &self.scaled_renderer {
+      Some(renderer) => {
+        renderer.write_rgba(self.backbuffer_page_idx, colors, output);
+        true
+      },
+      None => false
     }
   }
 
@@ -42,6 +170,15 @@ impl Video {
     &self.pages[self.backbuffer_page_idx]
   }
 
+  pub fn get_screen_page_idx(&self) -> usize {
+    self.backbuffer_page_idx
+  }
+
+  // physical pages of (front buffer, backbuffer, background builder)
+  pub fn get_page_roles(&self) -> (usize, usize, usize) {
+    (self.frontbuffer_page_idx, self.backbuffer_page_idx, self.background_builder_page_idx)
+  }
+
   pub fn blit(&mut self, page_id: u8) {
     if page_id == 0xff {
       let tmp = self.frontbuffer_page_idx;
@@ -55,6 +192,10 @@ impl Video {
       self.palette_id = self.next_palette_id;
       self.next_palette_id = INVALID_PALETTE;
     }
+
+    if let Some(recorder) = &mut self.draw_recorder {
+      recorder.end_frame(&self.pages);
+    }
   }
 
   pub fn fill_page(&mut self, page_id: u8, color_idx: u8) {
@@ -63,6 +204,18 @@ impl Video {
     for i in &mut self.pages[page_idx] {
       *i = color_idx;
     }
+
+    self.record(page_idx, DrawCommand::FillPage { color_idx });
+
+    let owner = self.new_pixel_owner(DrawCommand::FillPage { color_idx }.kind(), 0);
+
+    if let Some(owners) = &mut self.pixel_owners {
+      owners[page_idx].iter_mut().for_each(|pixel_owner| *pixel_owner = owner);
+    }
+
+    if let Some(renderer) = &mut self.scaled_renderer {
+      renderer.fill_page(page_idx, color_idx);
+    }
   }
 
   pub fn copy_page(&mut self, src_page_id: u8, dst_page_id: u8, vscroll: i16) {
@@ -83,23 +236,45 @@ impl Video {
       for i in 0..(FRAME_BUFFER_WIDTH * FRAME_BUFFER_HEIGHT) as usize {
         self.pages[d][i] = self.pages[s][i];
       }
+
+      self.record(d, DrawCommand::CopyPage { src_page_idx: s as u8, vscroll: 0 });
+
+      if let Some(owners) = &mut self.pixel_owners {
+        owners[d] = owners[s].clone();
+      }
+
+      if let Some(renderer) = &mut self.scaled_renderer {
+        renderer.copy_page(s, d, 0);
+      }
     } else {
       let s = self.get_page_idx(spage & 3);
       let d = self.get_page_idx(dst_page_id);
 
-      if vscroll.abs() < FRAME_BUFFER_HEIGHT as i16 {
-        let source_y = vscroll.abs();
+      // the rows of the source page are copied vscroll rows below (or above, if it's negative)
+      if s != d && vscroll.abs() < FRAME_BUFFER_HEIGHT as i16 {
+        let source_y = if vscroll < 0 { -vscroll } else { 0 };
         let dest_y = if vscroll < 0 { 0 } else { vscroll };
-        let height = FRAME_BUFFER_HEIGHT as i16 - source_y;
+        let height = FRAME_BUFFER_HEIGHT as i16 - vscroll.abs();
 
         for y in 0..height {
-          let ix = (source_y + y) as usize * FRAME_BUFFER_WIDTH as usize;
-          let iy = (dest_y + y) as usize * FRAME_BUFFER_WIDTH as usize;
+          let is = (source_y + y) as usize * FRAME_BUFFER_WIDTH as usize;
+          let id = (dest_y + y) as usize * FRAME_BUFFER_WIDTH as usize;
 
           for x in 0..FRAME_BUFFER_WIDTH {
-            self.pages[d][ix + x as usize] = self.pages[s][iy + x as usize];
+            self.pages[d][id + x as usize] = self.pages[s][is + x as usize];
           }
         }
+
+        self.record(d, DrawCommand::CopyPage { src_page_idx: s as u8, vscroll });
+
+        if let Some(owners) = &mut self.pixel_owners {
+          let src = owners[s][source_y as usize * FRAME_BUFFER_WIDTH as usize..(source_y + height) as usize * FRAME_BUFFER_WIDTH as usize].to_vec();
+          owners[d][dest_y as usize * FRAME_BUFFER_WIDTH as usize..(dest_y + height) as usize * FRAME_BUFFER_WIDTH as usize].copy_from_slice(&src);
+        }
+
+        if let Some(renderer) = &mut self.scaled_renderer {
+          renderer.copy_page(s, d, vscroll);
+        }
       }
     }
   }
@@ -112,13 +287,49 @@ impl Video {
     self.backbuffer_page_idx = self.get_page_idx(page_id);
   }
 
-  pub fn draw_poly(&mut self, poly_buffer: &[u8], offset: u16, x: i16, y: i16, zoom: i16) {
+  // poly_buffer_id: 1 or 2, the buffer of the part with the polys
+  pub fn draw_poly(&mut self, poly_buffer: &[u8], poly_buffer_id: u8, offset: u16, x: i16, y: i16, zoom: i16) {
     let mut poly = Poly::new();
+    let poly_file_id = if poly_buffer_id == 1 { self.poly_file_ids.0 } else { self.poly_file_ids.1 };
+    let command = DrawCommand::Poly { poly_buffer: poly_buffer_id, poly_file_id, offset, x, y, zoom };
+
+    if self.pixel_owners.is_some() {
+      poly.drawn_spans = Some(Vec::new());
+    }
+
     draw_poly_to_buffer(&mut poly, poly_buffer, offset, x, y, zoom, 0xff, &mut self.pages, self.backbuffer_page_idx);
+
+    self.record(self.backbuffer_page_idx, command);
+
+    if let Some(spans) = poly.drawn_spans {
+      for (poly_offset, start, end) in spans {
+        let owner = self.new_pixel_owner(command.kind(), poly_offset);
+
+        if let Some(owners) = &mut self.pixel_owners {
+          owners[self.backbuffer_page_idx][start..end].iter_mut().for_each(|pixel_owner| *pixel_owner = owner);
+        }
+      }
+    }
+
+    if let Some(renderer) = &mut self.scaled_renderer {
+      renderer.draw_poly(poly_buffer, offset, (x as f32, y as f32), zoom, 0xff, self.backbuffer_page_idx);
+    }
   }
 
-  pub fn draw_bitmap(&mut self, bitmap: &[u8]) {
+  pub fn draw_bitmap(&mut self, bitmap: &[u8], resource_id: u8) {
     self.pages[0].clone_from_slice(bitmap);
+
+    self.record(0, DrawCommand::Bitmap { resource_id });
+
+    let owner = self.new_pixel_owner(DrawCommand::Bitmap { resource_id }.kind(), 0);
+
+    if let Some(owners) = &mut self.pixel_owners {
+      owners[0].iter_mut().for_each(|pixel_owner| *pixel_owner = owner);
+    }
+
+    if let Some(renderer) = &mut self.scaled_renderer {
+      renderer.draw_bitmap(bitmap);
+    }
   }
 
   pub fn draw_string(&mut self, string_id: u16, x: i16, y: i16, color_idx: u8) {
@@ -126,31 +337,44 @@ impl Video {
     let mut wx = x * 8;
     let mut wy = y;
 
+    self.record(self.backbuffer_page_idx, DrawCommand::String { string_id, x, y, color_idx });
+
     for c in string.chars() {
       if c == '\n' {
         wx = x * 8;
         wy += 8;
       } else {
-        self.draw_char(c, wx, wy, color_idx);
+        self.draw_char(c, wx, wy, color_idx, string_id);
         wx += 8;
       }
     }
   }
 
-  fn draw_char(&mut self, c: char, x: i16, y: i16, color_idx: u8) {
+  fn draw_char(&mut self, c: char, x: i16, y: i16, color_idx: u8, string_id: u16) {
     let char_idx = ((c as u8 - ' ' as u8) as usize) * 8;
     let char_info = &FONT[char_idx .. char_idx + 8];
+    let owner = self.new_pixel_owner(DrawCommand::String { string_id, x, y, color_idx }.kind(), 0);
 
     for row in 0..8 {
       for col in 0..8 {
         if (char_info[row] & (1 << (7 - col))) != 0 {
-          self.pages[self.backbuffer_page_idx][((y + row as i16) as u16 * FRAME_BUFFER_WIDTH + x as u16 + col) as usize] = color_idx;
+          let idx = ((y + row as i16) as u16 * FRAME_BUFFER_WIDTH + x as u16 + col) as usize;
+          self.pages[self.backbuffer_page_idx][idx] = color_idx;
+
+          if let Some(owners) = &mut self.pixel_owners {
+            owners[self.backbuffer_page_idx][idx] = owner;
+          }
         }
       }
     }
+
+    if let Some(renderer) = &mut self.scaled_renderer {
+      renderer.draw_char(c, x, y, color_idx, self.backbuffer_page_idx);
+    }
   }
 
-  fn get_page_idx(&self, page_id: u8) -> usize {
+  // the physical page (0..3) of a page id: 0xfe is the front buffer, 0xff is the background builder
+  pub fn get_page_idx(&self, page_id: u8) -> usize {
     if page_id <= 3 {
       return page_id as usize
     }
@@ -164,3 +388,79 @@ impl Video {
     0
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const WIDTH: usize = FRAME_BUFFER_WIDTH as usize;
+  const HEIGHT: usize = FRAME_BUFFER_HEIGHT as usize;
+
+  // the page 1 has the row number in every pixel, and the page 2 is filled with 0xff
+  fn new_video() -> Video {
+    let mut video = Video::new();
+
+    for (idx, pixel) in video.pages[1].iter_mut().enumerate() {
+      *pixel = (idx / WIDTH) as u8;
+    }
+
+    video.pages[2].fill(0xff);
+    video
+  }
+
+  fn row(video: &Video, page_idx: usize, y: usize) -> &[u8] {
+    &video.pages[page_idx][y * WIDTH..(y + 1) * WIDTH]
+  }
+
+  #[test]
+  fn positive_vscroll_moves_the_rows_down() {
+    let mut video = new_video();
+    video.copy_page(0x81, 2, 10);
+
+    for y in 0..10 {
+      assert!(row(&video, 2, y).iter().all(|pixel| *pixel == 0xff), "row {}", y);
+    }
+
+    for y in 10..HEIGHT {
+      assert!(row(&video, 2, y).iter().all(|pixel| *pixel as usize == y - 10), "row {}", y);
+    }
+  }
+
+  #[test]
+  fn negative_vscroll_moves_the_rows_up() {
+    let mut video = new_video();
+    video.copy_page(0x81, 2, -10);
+
+    for y in 0..HEIGHT - 10 {
+      assert!(row(&video, 2, y).iter().all(|pixel| *pixel as usize == y + 10), "row {}", y);
+    }
+
+    for y in HEIGHT - 10..HEIGHT {
+      assert!(row(&video, 2, y).iter().all(|pixel| *pixel == 0xff), "row {}", y);
+    }
+  }
+
+  #[test]
+  fn scaled_renderer_starts_with_the_current_pages() {
+    let mut video = new_video();
+    video.enable_scaled_renderer(WIDTH * 2, HEIGHT * 2);
+
+    let renderer = video.scaled_renderer.as_ref().unwrap();
+
+    for y in 0..HEIGHT * 2 {
+      assert!(renderer.get_page(1)[y * WIDTH * 2..(y + 1) * WIDTH * 2].iter().all(|pixel| *pixel as usize == y / 2), "row {}", y);
+    }
+
+    assert!(renderer.get_page(2).iter().all(|pixel| *pixel == 0xff));
+    assert!(renderer.get_page(0).iter().all(|pixel| *pixel == 0));
+  }
+
+  #[test]
+  fn vscroll_out_of_the_page_copies_nothing() {
+    let mut video = new_video();
+    video.copy_page(0x81, 2, FRAME_BUFFER_HEIGHT as i16);
+    video.copy_page(0x81, 2, -(FRAME_BUFFER_HEIGHT as i16));
+
+    assert!(video.pages[2].iter().all(|pixel| *pixel == 0xff));
+  }
+}
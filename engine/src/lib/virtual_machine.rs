@@ -1,6 +1,11 @@
+use std::collections::BTreeMap;
 use crate::resources_manager::ResourcesManager;
-use crate::opcodes::{Opcodes, ActionRequest};
+use crate::opcodes::{Opcodes, ActionRequest, build_action_request};
 use crate::video::Video;
+use crate::profiler::Profiler;
+use crate::observer::VmObserver;
+use crate::checkpoints::{Checkpoint, GamePart};
+use crate::timeline::{Timeline, ThreadChange, ThreadChangeKind, HOST_THREAD_ID};
 use crate::defines::*;
 
 enum Keys {
@@ -25,11 +30,26 @@ pub enum ScriptRegs {
   PauseSlices       = 0xff
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BudgetKind {
+  Slice, // instructions run by a thread without yielding
+  Frame // instructions run by all the threads in a frame
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BudgetExceeded {
+  pub kind: BudgetKind,
+  pub thread_id: u8,
+  pub pc: u16,
+  pub instructions: u32
+}
+
 pub struct Thread {
   pub pc: u16,
   pub next_pc: u16,
   pub active: bool,
-  pub next_active: bool
+  pub next_active: bool,
+  pub last_yield_pc: u16
 }
 
 impl Thread {
@@ -38,7 +58,8 @@ impl Thread {
       pc: 0,
       next_pc: 0,
       active: false,
-      next_active: false
+      next_active: false,
+      last_yield_pc: INACTIVE_THREAD
     }
   }
 }
@@ -50,12 +71,23 @@ pub struct VirtualMachine {
   pub active_thread: u8,
   pub script_file_id: u8,
   pub palette_file_id: u8,
-  pub next_part_id: u8,
+  pub next_part: Option<GamePart>,
+  pub profiler: Option<Profiler>,
+  pub timeline: Option<Timeline>,
+  pub slice_budget: u32, // 0 = no limit
+  pub frame_budget: u32, // 0 = no limit
+  pub last_budget_exceeded: Option<BudgetExceeded>,
+  pub frozen_registers: BTreeMap<u8, i16>, // values set again in every frame
+  observers: Vec<Box<dyn VmObserver>>,
+  slice_instructions: u32,
+  frame_instructions: u32,
   stack: Vec<u16>,
   polys1_file_id: u8,
   polys2_file_id: u8,
   direction_keys_enabled: u8,
-  action_key_enabled: bool
+  action_key_enabled: bool,
+  last_char: u8, // char typed and not seen yet by the scripts
+  last_char_seen: bool
 }
 
 impl VirtualMachine {
@@ -76,9 +108,20 @@ impl VirtualMachine {
       polys1_file_id: 0,
       polys2_file_id: 0,
       palette_file_id: 0,
-      next_part_id: 0,
+      next_part: None,
+      profiler: None,
+      timeline: None,
+      slice_budget: 0,
+      frame_budget: 0,
+      last_budget_exceeded: None,
+      frozen_registers: BTreeMap::new(),
+      observers: Vec::new(),
+      slice_instructions: 0,
+      frame_instructions: 0,
       direction_keys_enabled: 0,
-      action_key_enabled: false
+      action_key_enabled: false,
+      last_char: 0,
+      last_char_seen: false
     }
   }
 
@@ -86,118 +129,131 @@ impl VirtualMachine {
     self.registers[ScriptRegs::RandomSeed as usize] = 0; // not very a random number
   }
 
-  pub fn restart_level(&mut self, level: u8) {
+  pub fn restart(&mut self, checkpoint: &Checkpoint) {
     for i in 0..64 {
       self.registers[i] = 0;
     }
 
-    let mut part = 0;
+    for (reg_id, value) in &checkpoint.registers {
+      self.registers[*reg_id as usize] = *value;
+    }
 
-    match level {
-      0 => { // the intro
-        for i in 0..LEVEL_00_INITIAL_REGISTERS_VALUES.len() {
-          self.registers[LEVEL_00_INITIAL_REGISTERS_VALUES[i][0] as usize] = LEVEL_00_INITIAL_REGISTERS_VALUES[i][1];
-        }
-        part = 1;
-      },
-      1 => {
-        for i in 0..LEVEL_01_INITIAL_REGISTERS_VALUES.len() {
-          self.registers[LEVEL_01_INITIAL_REGISTERS_VALUES[i][0] as usize] = LEVEL_01_INITIAL_REGISTERS_VALUES[i][1];
-        }
-        part = 2;
-      },
-      2 => {
-        for i in 0..LEVEL_02_INITIAL_REGISTERS_VALUES.len() {
-          self.registers[LEVEL_02_INITIAL_REGISTERS_VALUES[i][0] as usize] = LEVEL_02_INITIAL_REGISTERS_VALUES[i][1];
-        }
-        part = 3;
-      },
-      3 => {
-        for i in 0..LEVEL_03_INITIAL_REGISTERS_VALUES.len() {
-          self.registers[LEVEL_03_INITIAL_REGISTERS_VALUES[i][0] as usize] = LEVEL_03_INITIAL_REGISTERS_VALUES[i][1];
-        }
-        part = 4;
-      },
-      4 => {
-        for i in 0..LEVEL_04_INITIAL_REGISTERS_VALUES.len() {
-          self.registers[LEVEL_04_INITIAL_REGISTERS_VALUES[i][0] as usize] = LEVEL_04_INITIAL_REGISTERS_VALUES[i][1];
-        }
-        part = 4;
-      },
-      5 => {
-        for i in 0..LEVEL_05_INITIAL_REGISTERS_VALUES.len() {
-          self.registers[LEVEL_05_INITIAL_REGISTERS_VALUES[i][0] as usize] = LEVEL_05_INITIAL_REGISTERS_VALUES[i][1];
-        }
-        part = 4;
-      },
-      6 => {
-        for i in 0..LEVEL_06_INITIAL_REGISTERS_VALUES.len() {
-          self.registers[LEVEL_06_INITIAL_REGISTERS_VALUES[i][0] as usize] = LEVEL_06_INITIAL_REGISTERS_VALUES[i][1];
-        }
-        part = 4;
-      },
-      7 => {
-        for i in 0..LEVEL_07_INITIAL_REGISTERS_VALUES.len() {
-          self.registers[LEVEL_07_INITIAL_REGISTERS_VALUES[i][0] as usize] = LEVEL_07_INITIAL_REGISTERS_VALUES[i][1];
-        }
-        part = 4;
-      },
-      8 => {
-        for i in 0..LEVEL_08_INITIAL_REGISTERS_VALUES.len() {
-          self.registers[LEVEL_08_INITIAL_REGISTERS_VALUES[i][0] as usize] = LEVEL_08_INITIAL_REGISTERS_VALUES[i][1];
-        }
-        part = 4;
-      },
-      9 => {
-        for i in 0..LEVEL_09_INITIAL_REGISTERS_VALUES.len() {
-          self.registers[LEVEL_09_INITIAL_REGISTERS_VALUES[i][0] as usize] = LEVEL_09_INITIAL_REGISTERS_VALUES[i][1];
-        }
-        part = 4;
-      },
-      10 => {
-        for i in 0..LEVEL_10_INITIAL_REGISTERS_VALUES.len() {
-          self.registers[LEVEL_10_INITIAL_REGISTERS_VALUES[i][0] as usize] = LEVEL_10_INITIAL_REGISTERS_VALUES[i][1];
-        }
-        part = 4;
-      },
-      11 => {
-        for i in 0..LEVEL_11_INITIAL_REGISTERS_VALUES.len() {
-          self.registers[LEVEL_11_INITIAL_REGISTERS_VALUES[i][0] as usize] = LEVEL_11_INITIAL_REGISTERS_VALUES[i][1];
-        }
-        part = 4;
-      },
-      12 => {
-        for i in 0..LEVEL_12_INITIAL_REGISTERS_VALUES.len() {
-          self.registers[LEVEL_12_INITIAL_REGISTERS_VALUES[i][0] as usize] = LEVEL_12_INITIAL_REGISTERS_VALUES[i][1];
-        }
-        part = 6;
-      },
-      13 => {
-        for i in 0..LEVEL_13_INITIAL_REGISTERS_VALUES.len() {
-          self.registers[LEVEL_13_INITIAL_REGISTERS_VALUES[i][0] as usize] = LEVEL_13_INITIAL_REGISTERS_VALUES[i][1];
-        }
-        part = 6;
-      },
-      14 => {
-        for i in 0..LEVEL_14_INITIAL_REGISTERS_VALUES.len() {
-          self.registers[LEVEL_14_INITIAL_REGISTERS_VALUES[i][0] as usize] = LEVEL_14_INITIAL_REGISTERS_VALUES[i][1];
-        }
-        part = 5;
-      },
-      15 => {
-        for i in 0..LEVEL_15_INITIAL_REGISTERS_VALUES.len() {
-          self.registers[LEVEL_15_INITIAL_REGISTERS_VALUES[i][0] as usize] = LEVEL_15_INITIAL_REGISTERS_VALUES[i][1];
-        }
-        part = 6;
-      },
-      _ => {}
+    self.load_part(checkpoint.part);
+  }
+
+  pub fn get_current_part(&self) -> Option<GamePart> {
+    GamePart::from_script_file_id(self.script_file_id)
+  }
+
+  // the profiler counts the instructions executed until it's disabled
+  pub fn enable_profiler(&mut self, enabled: bool) {
+    self.profiler = if enabled { Some(Profiler::new()) } else { None };
+  }
+
+  pub fn enable_timeline(&mut self, enabled: bool) {
+    self.timeline = if enabled { Some(Timeline::new()) } else { None };
+  }
+
+  // called by SETVEC and RESET
+  pub fn add_thread_change(&mut self, thread_id: u8, by_thread_id: u8, kind: ThreadChangeKind) {
+    if let Some(timeline) = &mut self.timeline {
+      let pc = self.threads[by_thread_id as usize].pc - 1;
+      timeline.add_change(ThreadChange { thread_id, by_thread_id, pc, kind });
+    }
+  }
+
+  pub fn add_observer(&mut self, observer: Box<dyn VmObserver>) {
+    self.observers.push(observer);
+  }
+
+  pub fn remove_observers(&mut self) {
+    self.observers.clear();
+  }
+
+  pub fn notify<F: FnMut(&mut dyn VmObserver)>(&mut self, mut callback: F) {
+    for observer in self.observers.iter_mut() {
+      callback(observer.as_mut());
     }
+  }
+
+  pub fn set_register(&mut self, reg_id: u8, value: i16) {
+    self.registers[reg_id as usize] = value;
+  }
+
+  // the register is set to the value now and at the beginning of every frame, until it's unfrozen
+  pub fn freeze_register(&mut self, reg_id: u8, value: i16) {
+    self.registers[reg_id as usize] = value;
+    self.frozen_registers.insert(reg_id, value);
+  }
 
-    self.load_part(part);
+  pub fn unfreeze_register(&mut self, reg_id: u8) {
+    self.frozen_registers.remove(&reg_id);
   }
 
-  pub fn set_next_part_to_load(&mut self, part: u8) {
-    self.next_part_id = part;
+  // the host can change the threads like SETVEC and RESET do, the changes take effect in the next frame
+  pub fn set_thread_pc(&mut self, thread_id: u8, addr: u16) -> Result<(), String> {
+    check_thread_id(thread_id)?;
+
+    if addr >= INACTIVE_THREAD - 1 {
+      return Err(format!("invalid address {:04X}", addr));
+    }
+
+    self.threads[thread_id as usize].next_pc = addr;
+    self.add_host_thread_change(thread_id, ThreadChangeKind::SetVec(addr));
+
+    Ok(())
+  }
+
+  // starts the thread at the address, even if it was frozen
+  pub fn start_thread(&mut self, thread_id: u8, addr: u16) -> Result<(), String> {
+    self.set_thread_pc(thread_id, addr)?;
+    self.freeze_thread(thread_id, false)
+  }
+
+  pub fn freeze_thread(&mut self, thread_id: u8, frozen: bool) -> Result<(), String> {
+    check_thread_id(thread_id)?;
+
+    self.threads[thread_id as usize].next_active = frozen;
+    self.add_host_thread_change(thread_id, if frozen { ThreadChangeKind::Pause } else { ThreadChangeKind::Resume });
+
+    Ok(())
+  }
+
+  pub fn kill_thread(&mut self, thread_id: u8) -> Result<(), String> {
+    check_thread_id(thread_id)?;
+
+    self.threads[thread_id as usize].next_pc = INACTIVE_THREAD - 1;
+    self.add_host_thread_change(thread_id, ThreadChangeKind::Kill);
+
+    Ok(())
+  }
+
+  fn add_host_thread_change(&mut self, thread_id: u8, kind: ThreadChangeKind) {
+    if let Some(timeline) = &mut self.timeline {
+      timeline.add_change(ThreadChange { thread_id, by_thread_id: HOST_THREAD_ID, pc: INACTIVE_THREAD, kind });
+    }
+  }
+
+  // a budget of 0 instructions means no limit
+  pub fn set_instruction_budget(&mut self, slice_budget: u32, frame_budget: u32) {
+    self.slice_budget = slice_budget;
+    self.frame_budget = frame_budget;
+  }
+
+  // the active thread stops as if it had executed a YIELD, and it will continue from its pc in the next frame
+  pub fn force_yield(&mut self) {
+    let tidx = self.active_thread as usize;
+
+    self.threads[tidx].last_yield_pc = self.threads[tidx].pc;
+    self.schedule_next_thread();
+  }
+
+  pub fn get_stack_depth(&self) -> usize {
+    self.stack.len()
+  }
+
+  pub fn set_next_part_to_load(&mut self, part: GamePart) {
+    self.next_part = Some(part);
   }
 
   pub fn on_key_down(&mut self, key: u8) {
@@ -216,13 +272,24 @@ impl VirtualMachine {
     }
   }
 
+  // text input: letters, backspace (8) and enter (0x0d). Like in the original game, the letters are sent to the
+  // scripts in uppercase
+  pub fn on_char(&mut self, c: u8) {
+    if c == 8 || c == 0x0d || c.is_ascii_alphabetic() {
+      self.last_char = c & !0x20;
+    }
+  }
+
   pub fn step(&mut self, video: &mut Video, resources_manager: &ResourcesManager) -> u32 {
-    if self.next_part_id != 0 {
-      self.load_part(self.next_part_id);
-      self.next_part_id = 0;
+    if let Some(part) = self.next_part.take() {
+      self.load_part(part);
     }
 
     let tidx = self.active_thread as usize;
+    let pc = self.threads[tidx].pc;
+
+    video.set_draw_source(self.active_thread, pc);
+    video.set_poly_file_ids((self.polys1_file_id, self.polys2_file_id));
 
     let action_requested = self.thread_step(
       resources_manager,
@@ -235,43 +302,93 @@ impl VirtualMachine {
 
     let action = (action_requested >> 24) as u8;
 
+    if action == ActionRequest::YieldThread as u8 {
+      self.threads[tidx].last_yield_pc = pc;
+    }
+
+    self.slice_instructions += 1;
+    self.frame_instructions += 1;
+
     if action == ActionRequest::YieldThread as u8 || self.threads[tidx].pc == INACTIVE_THREAD {
-      let mut idx = ((self.active_thread + 1) as usize) % NUM_THREADS;
+      self.schedule_next_thread();
+    }
 
-      loop {
-        // after loop over all the threads, the pc is set based on the next_pc variable
-        if idx == 0 {
-          self.process_input();
+    // only return the action requested that should be managed by the host system
+    if action >= ActionRequest::Blit as u8 {
+      return action_requested;
+    }
 
-          for i in 0..NUM_THREADS {
-            self.threads[i].active = self.threads[i].next_active;
+    self.check_instruction_budget(tidx as u8)
+  }
+
+  // the budget is checked after the instructions that don't request anything to the host. The host can pause the
+  // game, force a yield or just continue (the budget will be exceeded again after the same number of instructions)
+  fn check_instruction_budget(&mut self, thread_id: u8) -> u32 {
+    let (kind, instructions) = if self.slice_budget > 0 && self.slice_instructions >= self.slice_budget && thread_id == self.active_thread {
+      (BudgetKind::Slice, std::mem::replace(&mut self.slice_instructions, 0))
+    } else if self.frame_budget > 0 && self.frame_instructions >= self.frame_budget {
+      (BudgetKind::Frame, std::mem::replace(&mut self.frame_instructions, 0))
+    } else {
+      return 0;
+    };
 
-            if self.threads[i].next_pc != INACTIVE_THREAD {
-              if self.threads[i].next_pc == INACTIVE_THREAD - 1 {
-                self.threads[i].pc = INACTIVE_THREAD;
-              } else {
-                self.threads[i].pc = self.threads[i].next_pc;
+    let pc = self.threads[thread_id as usize].pc;
+    self.last_budget_exceeded = Some(BudgetExceeded { kind, thread_id, pc, instructions });
+
+    build_action_request(ActionRequest::BudgetExceeded, thread_id) | pc as u32
+  }
+
+  fn schedule_next_thread(&mut self) {
+    let mut idx = ((self.active_thread + 1) as usize) % NUM_THREADS;
+
+    self.slice_instructions = 0;
+
+    loop {
+      // after loop over all the threads, the pc is set based on the next_pc variable
+      if idx == 0 {
+        self.process_input();
+        self.frame_instructions = 0;
+
+        for (reg_id, value) in &self.frozen_registers {
+          self.registers[*reg_id as usize] = *value;
+        }
+
+        if let Some(profiler) = &mut self.profiler {
+          profiler.end_frame();
+        }
+
+        if let Some(timeline) = &mut self.timeline {
+          timeline.end_frame();
+        }
+
+        for i in 0..NUM_THREADS {
+          self.threads[i].active = self.threads[i].next_active;
+
+          if self.threads[i].next_pc != INACTIVE_THREAD {
+            let next_pc = self.threads[i].next_pc;
+
+            if next_pc == INACTIVE_THREAD - 1 {
+              if self.threads[i].pc != INACTIVE_THREAD {
+                self.notify(|observer| observer.on_thread_killed(i as u8));
               }
-              self.threads[i].next_pc = INACTIVE_THREAD;
+
+              self.threads[i].pc = INACTIVE_THREAD;
+            } else {
+              self.threads[i].pc = next_pc;
+              self.notify(|observer| observer.on_thread_started(i as u8, next_pc));
             }
+            self.threads[i].next_pc = INACTIVE_THREAD;
           }
         }
+      }
 
-        if self.threads[idx].pc != INACTIVE_THREAD && !self.threads[idx].active {
-          self.active_thread = idx as u8;
-          break;
-        }
-
-        idx = (idx + 1) % NUM_THREADS;
+      if self.threads[idx].pc != INACTIVE_THREAD && !self.threads[idx].active {
+        self.active_thread = idx as u8;
+        break;
       }
-    }
 
-    // only return the action requested that should be managed by the host system
-    if action >= ActionRequest::Blit as u8 {
-      return action_requested;
+      idx = (idx + 1) % NUM_THREADS;
     }
-
-    0
   }
 
   pub fn stack_push(&mut self, value: u16) {
@@ -294,6 +411,17 @@ impl VirtualMachine {
       return 0;
     }
 
+    if let Some(profiler) = &mut self.profiler {
+      profiler.count(self.script_file_id, thread_id, pc);
+    }
+
+    if let Some(timeline) = &mut self.timeline {
+      timeline.count(thread_id, pc);
+    }
+
+    let script_file_id = self.script_file_id;
+    self.notify(|observer| observer.on_instruction(script_file_id, thread_id, pc));
+
     self.threads[tidx].pc += 1;
 
     let opcode_value = script[pc as usize];
@@ -312,57 +440,20 @@ impl VirtualMachine {
 
     if pc + 1 == self.threads[tidx].pc { // the instruction is not a call, ret or jmp
       self.threads[tidx].pc += opcode_len;
+    } else if self.threads[tidx].pc == INACTIVE_THREAD {
+      self.notify(|observer| observer.on_thread_killed(thread_id));
     }
 
     action_requested
   }
 
-  fn load_part(&mut self, part: u8) {
-    match part {
-      0 => { // protection screen
-        self.script_file_id = 0x15;
-        self.polys1_file_id = 0x16;
-        self.polys2_file_id = 0x0;
-        self.palette_file_id = 0x14;
-      },
-      1 => { // introduction
-        self.script_file_id = 0x18;
-        self.polys1_file_id = 0x19;
-        self.polys2_file_id = 0x0;
-        self.palette_file_id = 0x17;
-      },
-      2 => {
-        self.script_file_id = 0x1b;
-        self.polys1_file_id = 0x1c;
-        self.polys2_file_id = 0x11;
-        self.palette_file_id = 0x1a;
-      },
-      3 => {
-        self.script_file_id = 0x1e;
-        self.polys1_file_id = 0x1f;
-        self.polys2_file_id = 0x11;
-        self.palette_file_id = 0x1d;
-      },
-      4 => {
-        self.script_file_id = 0x21;
-        self.polys1_file_id = 0x22;
-        self.polys2_file_id = 0x11;
-        self.palette_file_id = 0x20;
-      },
-      5 => {
-        self.script_file_id = 0x24;
-        self.polys1_file_id = 0x25;
-        self.polys2_file_id = 0x0;
-        self.palette_file_id = 0x23;
-      },
-      6 => {
-        self.script_file_id = 0x27;
-        self.polys1_file_id = 0x28;
-        self.polys2_file_id = 0x11;
-        self.palette_file_id = 0x26;
-      },
-      _ => panic!("invalid game part")
-    }
+  fn load_part(&mut self, part: GamePart) {
+    let file_ids = part.file_ids();
+
+    self.palette_file_id = file_ids[0];
+    self.script_file_id = file_ids[1];
+    self.polys1_file_id = file_ids[2];
+    self.polys2_file_id = file_ids[3];
 
     self.registers[0xe4] = 0x14;
 
@@ -375,6 +466,8 @@ impl VirtualMachine {
 
     self.threads[0].pc = 0;
     self.active_thread = 0;
+
+    self.notify(|observer| observer.on_part_load(part));
   }
 
   fn process_input(&mut self) {
@@ -414,5 +507,23 @@ impl VirtualMachine {
       self.registers[ScriptRegs::HeroAction as usize] = 0;
       self.registers[ScriptRegs::HeroActionPosMask as usize] = mask;
     }
+
+    // the char is in the register during one frame
+    if self.last_char != 0 {
+      self.registers[ScriptRegs::LastKeyChar as usize] = self.last_char as i16;
+      self.last_char = 0;
+      self.last_char_seen = true;
+    } else if self.last_char_seen {
+      self.registers[ScriptRegs::LastKeyChar as usize] = 0;
+      self.last_char_seen = false;
+    }
   }
 }
+
+fn check_thread_id(thread_id: u8) -> Result<(), String> {
+  if thread_id as usize >= NUM_THREADS {
+    return Err(format!("the thread {:02X} doesn't exist", thread_id));
+  }
+
+  Ok(())
+}
@@ -23,22 +23,69 @@ export class AnotherWorldEngine {
 
     this.screenWidth = this.wasm.anotherworldengine_get_screen_width(this.anotherWorldEngine)
     this.screenHeight = this.wasm.anotherworldengine_get_screen_height(this.anotherWorldEngine)
+    this.frameFormat = 0 // rgba
 
     const dataPtr = this.wasm.anotherworldengine_get_shared_memory_pointer(this.anotherWorldEngine)
     let dataArray = new Uint8Array(this.wasm.memory.buffer, dataPtr, gameData.byteLength)
     dataArray.set(new Uint8Array(gameData))
 
     this.wasm.anotherworldengine_init(this.anotherWorldEngine)
+
+    Global.resourcesIdByPart = this.getPartsInfo()
   }
 
   end() {
     this.wasm.__wbg_anotherworldengine_free(this.anotherWorldEngine)
   }
 
+  // format: 0 rgba, 1 bgra, 2 rgb565, 3 indexed
+  setFrameFormat(format) {
+    if (this.wasm.anotherworldengine_set_frame_format(this.anotherWorldEngine, format)) {
+      this.frameFormat = format
+    }
+  }
+
+  // the frame buffer is apart from the shared memory, the queries don't overwrite it
   getFrameBuffer() {
-    this.wasm.anotherworldengine_get_frame_buffer(this.anotherWorldEngine)
+    const dataPtr = this.wasm.anotherworldengine_get_frame_buffer(this.anotherWorldEngine)
+    const ArrayType = [Uint32Array, Uint32Array, Uint16Array, Uint8Array][this.frameFormat]
+    return new ArrayType(this.wasm.memory.buffer, dataPtr, this.screenWidth * this.screenHeight)
+  }
+
+  // page: 0 to 3, or 0xfe and 0xff for the pages they are mapped to
+  getPage(page, rgba) {
+    const dataPtr = this.wasm.anotherworldengine_get_page_buffer(this.anotherWorldEngine, page, rgba)
+    const ArrayType = rgba ? Uint32Array : Uint8Array
+    return new ArrayType(this.wasm.memory.buffer, dataPtr, this.screenWidth * this.screenHeight)
+  }
+
+  getPagesInfo() {
+    this.wasm.anotherworldengine_build_pages_info(this.anotherWorldEngine)
+
     const dataPtr = this.wasm.anotherworldengine_get_shared_memory_pointer(this.anotherWorldEngine)
-    return new Uint32Array(this.wasm.memory.buffer, dataPtr, this.screenWidth * this.screenHeight)
+    const dataArray = new Uint8Array(this.wasm.memory.buffer, dataPtr, 3)
+
+    return {
+      frontBuffer: dataArray[0],
+      backBuffer: dataArray[1],
+      backgroundBuilder: dataArray[2]
+    }
+  }
+
+  // the scaled renderer draws the frames at other resolution (0 x 0 disables it)
+  enableScaledRenderer(width, height) {
+    this.wasm.anotherworldengine_enable_scaled_renderer(this.anotherWorldEngine, width, height)
+    this.scaledWidth = width
+    this.scaledHeight = height
+  }
+
+  setScaledAntiAliasing(enabled) {
+    this.wasm.anotherworldengine_set_scaled_anti_aliasing(this.anotherWorldEngine, enabled)
+  }
+
+  getScaledFrameBuffer() {
+    const dataPtr = this.wasm.anotherworldengine_get_scaled_frame_buffer(this.anotherWorldEngine)
+    return new Uint32Array(this.wasm.memory.buffer, dataPtr, this.scaledWidth * this.scaledHeight)
   }
 
   vmGetCurrentPC() {
@@ -49,6 +96,138 @@ export class AnotherWorldEngine {
     return this.wasm.anotherworldengine_vm_step(this.anotherWorldEngine)
   }
 
+  // the thread methods take effect in the next frame, they return the error message or null
+  setThreadPC(threadId, addr) {
+    return this.readError(this.wasm.anotherworldengine_set_thread_pc(this.anotherWorldEngine, threadId, addr))
+  }
+
+  startThread(threadId, addr) {
+    return this.readError(this.wasm.anotherworldengine_start_thread(this.anotherWorldEngine, threadId, addr))
+  }
+
+  freezeThread(threadId, frozen) {
+    return this.readError(this.wasm.anotherworldengine_freeze_thread(this.anotherWorldEngine, threadId, frozen))
+  }
+
+  killThread(threadId) {
+    return this.readError(this.wasm.anotherworldengine_kill_thread(this.anotherWorldEngine, threadId))
+  }
+
+  // restarts the game in the checkpoint (index of the list returned by getCheckpoints)
+  vmRestartCheckpoint(idx) {
+    this.wasm.anotherworldengine_vm_restart_checkpoint(this.anotherWorldEngine, idx)
+  }
+
+  getPartsInfo() {
+    this.wasm.anotherworldengine_build_parts_info(this.anotherWorldEngine)
+
+    const dataPtr = this.wasm.anotherworldengine_get_shared_memory_pointer(this.anotherWorldEngine)
+    const dataArray = new Uint8Array(this.wasm.memory.buffer, dataPtr, SharedMemorySize)
+    const textDecoder = new TextDecoder()
+    let parts = []
+    let idx = 1
+
+    for (let i = 0; i < dataArray[0]; ++i) {
+      const nameLen = dataArray[idx + 4]
+
+      parts.push({
+        palette: dataArray[idx],
+        script: dataArray[idx + 1],
+        poly1: dataArray[idx + 2],
+        poly2: dataArray[idx + 3],
+        name: textDecoder.decode(dataArray.slice(idx + 5, idx + 5 + nameLen))
+      })
+
+      idx += 5 + nameLen
+    }
+
+    return parts
+  }
+
+  getCheckpoints() {
+    this.wasm.anotherworldengine_build_checkpoints_info(this.anotherWorldEngine)
+
+    const dataPtr = this.wasm.anotherworldengine_get_shared_memory_pointer(this.anotherWorldEngine)
+    const dataView = new DataView(this.wasm.memory.buffer, dataPtr, SharedMemorySize)
+    const textDecoder = new TextDecoder()
+    const numCheckpoints = dataView.getUint16(0, true)
+    let checkpoints = []
+    let idx = 2
+
+    for (let i = 0; i < numCheckpoints; ++i) {
+      const nameLen = dataView.getUint8(idx + 1)
+      const codeLen = dataView.getUint8(idx + 2 + nameLen)
+
+      checkpoints.push({
+        part: dataView.getUint8(idx),
+        name: textDecoder.decode(new Uint8Array(this.wasm.memory.buffer, dataPtr + idx + 2, nameLen)),
+        code: codeLen > 0 ? textDecoder.decode(new Uint8Array(this.wasm.memory.buffer, dataPtr + idx + 3 + nameLen, codeLen)) : null
+      })
+
+      idx += 3 + nameLen + codeLen
+    }
+
+    return checkpoints
+  }
+
+  // [{code, checkpointIdx}] of the checkpoints with a level code
+  getLevelCodes() {
+    return this.getCheckpoints()
+      .map((checkpoint, idx) => ({code: checkpoint.code, checkpointIdx: idx}))
+      .filter(levelCode => levelCode.code !== null)
+  }
+
+  // restarts the game in the checkpoint of the code, returns the error message or null
+  enterLevelCode(code) {
+    const len = this.writeBytes(new TextEncoder().encode(code))
+    return this.readError(this.wasm.anotherworldengine_enter_level_code(this.anotherWorldEngine, len))
+  }
+
+  // adds a checkpoint with the current part and registers, returns the error message or null
+  addCheckpoint(name) {
+    const len = this.writeBytes(new TextEncoder().encode(name))
+    return this.readError(this.wasm.anotherworldengine_add_checkpoint(this.anotherWorldEngine, len))
+  }
+
+  loadCheckpoints(json) {
+    const len = this.writeBytes(new TextEncoder().encode(json))
+    return this.readError(this.wasm.anotherworldengine_load_checkpoints(this.anotherWorldEngine, len))
+  }
+
+  // json with the custom checkpoints
+  getCustomCheckpoints() {
+    const len = this.wasm.anotherworldengine_build_checkpoints(this.anotherWorldEngine)
+    return this.readText(len)
+  }
+
+  vmForceYield() {
+    this.wasm.anotherworldengine_vm_force_yield(this.anotherWorldEngine)
+  }
+
+  // a budget of 0 instructions means no limit
+  setInstructionBudget(sliceBudget, frameBudget) {
+    this.wasm.anotherworldengine_set_instruction_budget(this.anotherWorldEngine, sliceBudget, frameBudget)
+  }
+
+  getBudgetExceeded() {
+    this.wasm.anotherworldengine_build_budget_exceeded_info(this.anotherWorldEngine)
+
+    const dataPtr = this.wasm.anotherworldengine_get_shared_memory_pointer(this.anotherWorldEngine)
+    const dataView = new DataView(this.wasm.memory.buffer, dataPtr, SharedMemorySize)
+    const kind = dataView.getUint8(0)
+
+    if (kind === 0) {
+      return null
+    }
+
+    return {
+      kind: kind === 1 ? 'slice' : 'frame',
+      threadId: dataView.getUint8(1),
+      pc: dataView.getUint16(2, true),
+      instructions: dataView.getUint32(4, true)
+    }
+  }
+
   vmRestart(part) {
     this.wasm.anotherworldengine_vm_restart(this.anotherWorldEngine, part)
   }
@@ -57,6 +236,11 @@ export class AnotherWorldEngine {
     this.wasm.anotherworldengine_on_key_down(this.anotherWorldEngine, key)
   }
 
+  // text input: letters, backspace (8) and enter (13)
+  onChar(c) {
+    this.wasm.anotherworldengine_on_char(this.anotherWorldEngine, c)
+  }
+
   onKeyUp(key) {
     this.wasm.anotherworldengine_on_key_up(this.anotherWorldEngine, key)
   }
@@ -67,6 +251,12 @@ export class AnotherWorldEngine {
     return new Uint8Array(this.wasm.memory.buffer, dataPtr, this.screenWidth * this.screenHeight)
   }
 
+  // svg of the poly, with the palette paletteId of the palettes file, or null if the poly is broken
+  getPolySvg(fileId, offset, zoom, palettesFileId, paletteId) {
+    const len = this.wasm.anotherworldengine_build_poly_svg(this.anotherWorldEngine, fileId, offset, zoom, palettesFileId, paletteId)
+    return len > 0 ? this.readText(len) : null
+  }
+
   getRegisters() {
     const dataPtr = this.wasm.anotherworldengine_get_registers(this.anotherWorldEngine)
     const dataArray = new Int16Array(this.wasm.memory.buffer, dataPtr, 256)
@@ -79,24 +269,252 @@ export class AnotherWorldEngine {
     return registers
   }
 
+  setRegister(regId, value) {
+    this.wasm.anotherworldengine_set_register(this.anotherWorldEngine, regId, value)
+  }
+
+  // the register keeps the value until it's unfrozen
+  freezeRegister(regId, value) {
+    this.wasm.anotherworldengine_freeze_register(this.anotherWorldEngine, regId, value)
+  }
+
+  unfreezeRegister(regId) {
+    this.wasm.anotherworldengine_unfreeze_register(this.anotherWorldEngine, regId)
+  }
+
+  getFrozenRegisters() {
+    this.wasm.anotherworldengine_build_frozen_registers_info(this.anotherWorldEngine)
+    return this.readRegisterValues()
+  }
+
+  startRegisterSearch() {
+    this.wasm.anotherworldengine_start_register_search(this.anotherWorldEngine)
+  }
+
+  // condition: 'changed', 'unchanged', 'equals', 'increased' or 'decreased'. Returns the number of candidates left
+  filterRegisterSearch(condition, value) {
+    const conditions = ['changed', 'unchanged', 'equals', 'increased', 'decreased']
+    return this.wasm.anotherworldengine_filter_register_search(this.anotherWorldEngine, conditions.indexOf(condition), value || 0)
+  }
+
+  getRegisterSearchCandidates() {
+    this.wasm.anotherworldengine_build_register_search_info(this.anotherWorldEngine)
+    return this.readRegisterValues()
+  }
+
+  // reads a list of [register u8][value i16] written to shared_memory, returns {regId: value}
+  readRegisterValues() {
+    const dataPtr = this.wasm.anotherworldengine_get_shared_memory_pointer(this.anotherWorldEngine)
+    const dataView = new DataView(this.wasm.memory.buffer, dataPtr, SharedMemorySize)
+    const num = dataView.getUint16(0, true)
+    let values = {}
+
+    for (let i = 0; i < num; ++i) {
+      values[dataView.getUint8(2 + i * 3)] = dataView.getInt16(3 + i * 3, true)
+    }
+
+    return values
+  }
+
+  getRegistersInfo() {
+    this.wasm.anotherworldengine_build_registers_info(this.anotherWorldEngine)
+
+    const textDecoder = new TextDecoder()
+    const dataPtr = this.wasm.anotherworldengine_get_shared_memory_pointer(this.anotherWorldEngine)
+    const dataArray = new Uint8Array(this.wasm.memory.buffer, dataPtr, SharedMemorySize)
+    let info = []
+    let idx = 0
+
+    for (let i = 0; i < 256; ++i) {
+      const nameLen = dataArray[idx++]
+      const name = textDecoder.decode(dataArray.slice(idx, idx + nameLen))
+      idx += nameLen
+
+      const commentLen = dataArray[idx++] | (dataArray[idx++] << 8)
+      const comment = textDecoder.decode(dataArray.slice(idx, idx + commentLen))
+      idx += commentLen
+
+      info.push({name: name, comment: comment})
+    }
+
+    return info
+  }
+
+  // returns an error message if the symbols can't be loaded
+  loadSymbols(json) {
+    const len = this.writeBytes(new TextEncoder().encode(json))
+    return this.readError(this.wasm.anotherworldengine_load_symbols(this.anotherWorldEngine, len))
+  }
+
+  getSymbols() {
+    const len = this.wasm.anotherworldengine_build_symbols(this.anotherWorldEngine)
+    return this.readText(len)
+  }
+
   getThreadsInfo() {
     this.wasm.anotherworldengine_build_threads_info(this.anotherWorldEngine)
 
     const dataPtr = this.wasm.anotherworldengine_get_shared_memory_pointer(this.anotherWorldEngine)
-    const dataArray = new Uint16Array(this.wasm.memory.buffer, dataPtr, 65)
+    const dataView = new DataView(this.wasm.memory.buffer, dataPtr, 4 + 64 * 7)
+    const pcToHex = pc => pc >= 0xfffe ? '-' : int2Hex(pc, 4)
     let info = {
-      activeThread: dataArray[0],
-      threadsPc: []
+      activeThread: dataView.getUint16(0, true),
+      stackDepth: dataView.getUint16(2, true),
+      threadsPc: [],
+      threads: []
     }
 
-    for (let i = 1; i < dataArray.length; ++i) {
-      const value = dataArray[i]
-      info.threadsPc.push(value == 0xffff ? '-' : int2Hex(value, 4))
+    for (let i = 0; i < 64; ++i) {
+      const idx = 4 + i * 7
+      const pc = dataView.getUint16(idx, true)
+      const flags = dataView.getUint8(idx + 6)
+
+      info.threadsPc.push(pcToHex(pc))
+      info.threads.push({
+        pc: pcToHex(pc),
+        nextPc: pcToHex(dataView.getUint16(idx + 2, true)),
+        killNext: dataView.getUint16(idx + 2, true) === 0xfffe,
+        lastYieldPc: pcToHex(dataView.getUint16(idx + 4, true)),
+        paused: (flags & 1) !== 0,
+        pausedNext: (flags & 2) !== 0
+      })
     }
 
     return info
   }
 
+  enableTimeline(enabled) {
+    this.wasm.anotherworldengine_enable_timeline(this.anotherWorldEngine, enabled)
+  }
+
+  getThreadsTimeline(numFrames) {
+    this.wasm.anotherworldengine_build_threads_timeline_info(this.anotherWorldEngine, numFrames)
+
+    const dataPtr = this.wasm.anotherworldengine_get_shared_memory_pointer(this.anotherWorldEngine)
+    const dataView = new DataView(this.wasm.memory.buffer, dataPtr, SharedMemorySize)
+    const changeKinds = ['setvec', 'resume', 'pause', 'kill']
+    const numFramesWritten = dataView.getUint16(0, true)
+    let frames = []
+    let idx = 2
+
+    for (let f = 0; f < numFramesWritten; ++f) {
+      let frame = {
+        frame: dataView.getUint32(idx, true),
+        runs: [],
+        changes: []
+      }
+
+      const numRuns = dataView.getUint8(idx + 4)
+      idx += 5
+
+      for (let r = 0; r < numRuns; ++r) {
+        frame.runs.push({
+          threadId: dataView.getUint8(idx),
+          startPc: int2Hex(dataView.getUint16(idx + 1, true), 4),
+          endPc: int2Hex(dataView.getUint16(idx + 3, true), 4),
+          instructions: dataView.getUint32(idx + 5, true)
+        })
+        idx += 9
+      }
+
+      const numChanges = dataView.getUint16(idx, true)
+      idx += 2
+
+      for (let c = 0; c < numChanges; ++c) {
+        frame.changes.push({
+          threadId: dataView.getUint8(idx),
+          byThreadId: dataView.getUint8(idx + 1),
+          pc: int2Hex(dataView.getUint16(idx + 2, true), 4),
+          kind: changeKinds[dataView.getUint8(idx + 4)],
+          addr: int2Hex(dataView.getUint16(idx + 5, true), 4)
+        })
+        idx += 7
+      }
+
+      frames.push(frame)
+    }
+
+    return frames
+  }
+
+  enableDrawRecorder(enabled) {
+    this.wasm.anotherworldengine_enable_draw_recorder(this.anotherWorldEngine, enabled)
+  }
+
+  // draw commands of the current frame, or of the last one shown
+  getDrawList(last) {
+    this.wasm.anotherworldengine_build_draw_list_info(this.anotherWorldEngine, last)
+
+    const dataPtr = this.wasm.anotherworldengine_get_shared_memory_pointer(this.anotherWorldEngine)
+    const dataView = new DataView(this.wasm.memory.buffer, dataPtr, SharedMemorySize)
+    const kinds = ['fill', 'copy', 'poly', 'string', 'bitmap']
+    const numCommands = dataView.getUint16(0, true)
+    let commands = []
+    let idx = 2
+
+    for (let i = 0; i < numCommands; ++i) {
+      commands.push({
+        kind: kinds[dataView.getUint8(idx)],
+        threadId: dataView.getUint8(idx + 1),
+        pc: int2Hex(dataView.getUint16(idx + 2, true), 4),
+        page: dataView.getUint8(idx + 4),
+        color: dataView.getUint8(idx + 5),
+        buffer: dataView.getUint8(idx + 6),
+        data: dataView.getUint16(idx + 7, true),
+        x: dataView.getInt16(idx + 9, true),
+        y: dataView.getInt16(idx + 11, true),
+        zoom: dataView.getInt16(idx + 13, true)
+      })
+      idx += 15
+    }
+
+    return commands
+  }
+
+  // draws the first numCommands commands of the frame, and returns the page of the last one with its frame buffer
+  replayDrawList(last, numCommands) {
+    const page = this.wasm.anotherworldengine_replay_draw_list(this.anotherWorldEngine, last, numCommands)
+
+    if (page === 0xff) {
+      return null
+    }
+
+    const dataPtr = this.wasm.anotherworldengine_get_frame_buffer_pointer(this.anotherWorldEngine)
+    return { page, frameBuffer: new Uint32Array(this.wasm.memory.buffer, dataPtr, this.screenWidth * this.screenHeight) }
+  }
+
+  // svg of the page built from the draw commands of the frame, or null if there isn't a recorded frame
+  getFrameSvg(last, page) {
+    const len = this.wasm.anotherworldengine_build_frame_svg(this.anotherWorldEngine, last, page)
+    return len > 0 ? this.readText(len) : null
+  }
+
+  enablePixelOwners(enabled) {
+    this.wasm.anotherworldengine_enable_pixel_owners(this.anotherWorldEngine, enabled)
+  }
+
+  // the draw command that drew the pixel of the page, or null
+  whoDrew(page, x, y) {
+    if (!this.wasm.anotherworldengine_who_drew(this.anotherWorldEngine, page, x, y)) {
+      return null
+    }
+
+    const dataPtr = this.wasm.anotherworldengine_get_shared_memory_pointer(this.anotherWorldEngine)
+    const dataView = new DataView(this.wasm.memory.buffer, dataPtr, SharedMemorySize)
+    const kinds = ['fill', 'copy', 'poly', 'string', 'bitmap']
+
+    return {
+      kind: kinds[dataView.getUint8(0)],
+      threadId: dataView.getUint8(1),
+      pc: int2Hex(dataView.getUint16(2, true), 4),
+      polyOffset: int2Hex(dataView.getUint16(4, true), 4)
+    }
+  }
+
+  getScreenPage() {
+    return this.wasm.anotherworldengine_get_screen_page(this.anotherWorldEngine)
+  }
+
   getResourcesInfo() {
     this.wasm.anotherworldengine_build_resources_info(this.anotherWorldEngine)
 
@@ -211,21 +629,24 @@ export class AnotherWorldEngine {
               break
 
             case 'DRAWPOLY1':
-            case 'DRAWPOLY2': {
+            case 'DRAWPOLY2':
+            case 'DRAWPOLYS': // DRAWPOLYS is the short form of DRAWPOLY1 with the bytes as they are
+            case 'DRAWPOLYL': { // DRAWPOLYL is a DRAWPOLY1 kept in the long form
+              const bufferId = codeParts[0] === 'DRAWPOLY2' ? 2 : 1
               const offset =  codeParts[1].substring(0, 4)
               const x = codeParts[2].replace(',', '')
               const y = codeParts[3].replace(',', '')
-              const zoom = codeParts[4]
+              const zoom = codeParts[4] || '40'
 
-              line.parts[0].value = 'DRAWPOLY'
+              line.parts[0].value = codeParts[0] === 'DRAWPOLYS' || codeParts[0] === 'DRAWPOLYL' ? codeParts[0] : 'DRAWPOLY'
               line.parts.push({type: 'polyBuffer', value: offset})
               line.parts.push({type: 'text', value: `, ${codeParts.slice(2, codeParts.length).join(' ')}`})
-              line.params = {bufferId: codeParts[0] === 'DRAWPOLY1' ? 1 : 2, x: x, y: y, zoom: zoom}
+              line.params = {bufferId: bufferId, x: x, y: y, zoom: zoom}
 
               const part = Global.resourcesIdByPart.find(i => i.script === file.id)
 
               if (part) {
-                const polyFileId = codeParts[0] === 'DRAWPOLY1' ? part.poly1 : part.poly2
+                const polyFileId = bufferId === 1 ? part.poly1 : part.poly2
 
                 if (!(polyFileId in polyOffsets)) {
                   polyOffsets[polyFileId] = []
@@ -242,6 +663,10 @@ export class AnotherWorldEngine {
               line.parts.push({type: 'text', value: codeParts.slice(1, codeParts.length).join(' ')})
               break
           }
+
+          if (line.comment) {
+            line.parts.push({type: 'comment', value: `; ${line.comment}`})
+          }
         }
       }
     }
@@ -254,6 +679,171 @@ export class AnotherWorldEngine {
     return info
   }
 
+  getScriptListing(scriptId) {
+    const len = this.wasm.anotherworldengine_build_script_listing(this.anotherWorldEngine, scriptId)
+    return this.readText(len)
+  }
+
+  getScriptCfg(scriptId) {
+    const len = this.wasm.anotherworldengine_build_script_cfg(this.anotherWorldEngine, scriptId)
+    return this.readText(len)
+  }
+
+  getScriptPseudocode(scriptId) {
+    const len = this.wasm.anotherworldengine_build_script_pseudocode(this.anotherWorldEngine, scriptId)
+    return this.readText(len)
+  }
+
+  getScriptLabels(scriptId) {
+    this.wasm.anotherworldengine_build_script_labels_info(this.anotherWorldEngine, scriptId)
+
+    const textDecoder = new TextDecoder()
+    const dataPtr = this.wasm.anotherworldengine_get_shared_memory_pointer(this.anotherWorldEngine)
+    const dataArray = new Uint8Array(this.wasm.memory.buffer, dataPtr, SharedMemorySize)
+    const xrefKinds = ['jump', 'branch', 'call', 'setvec']
+    const numLabels = dataArray[0] | (dataArray[1] << 8)
+    let labels = {}
+    let idx = 2
+
+    for (let i = 0; i < numLabels; ++i) {
+      const addr = dataArray[idx++] | (dataArray[idx++] << 8)
+      const nameLen = dataArray[idx++]
+      const name = textDecoder.decode(dataArray.slice(idx, idx + nameLen))
+      idx += nameLen
+
+      const numXrefs = dataArray[idx++] | (dataArray[idx++] << 8)
+      let xrefs = []
+
+      for (let x = 0; x < numXrefs; ++x) {
+        xrefs.push({
+          from: int2Hex(dataArray[idx++] | (dataArray[idx++] << 8), 4),
+          kind: xrefKinds[dataArray[idx++]]
+        })
+      }
+
+      labels[int2Hex(addr, 4)] = {name: name, xrefs: xrefs}
+    }
+
+    return labels
+  }
+
+  enableProfiler(enabled) {
+    this.wasm.anotherworldengine_enable_profiler(this.anotherWorldEngine, enabled)
+  }
+
+  getCoverageReport(scriptId) {
+    const len = this.wasm.anotherworldengine_build_coverage_report(this.anotherWorldEngine, scriptId)
+    return this.readText(len)
+  }
+
+  getThreadsCostReport() {
+    const len = this.wasm.anotherworldengine_build_threads_cost_report(this.anotherWorldEngine)
+    return this.readText(len)
+  }
+
+  getCoverage(scriptId) {
+    this.wasm.anotherworldengine_build_coverage_info(this.anotherWorldEngine, scriptId)
+
+    const dataPtr = this.wasm.anotherworldengine_get_shared_memory_pointer(this.anotherWorldEngine)
+    const dataView = new DataView(this.wasm.memory.buffer, dataPtr, SharedMemorySize)
+    const numEntries = dataView.getUint16(0, true)
+    let coverage = {}
+
+    for (let i = 0; i < numEntries; ++i) {
+      coverage[int2Hex(dataView.getUint16(2 + i * 6, true), 4)] = dataView.getUint32(4 + i * 6, true)
+    }
+
+    return coverage
+  }
+
+  getThreadsCost() {
+    this.wasm.anotherworldengine_build_threads_cost_info(this.anotherWorldEngine)
+
+    const dataPtr = this.wasm.anotherworldengine_get_shared_memory_pointer(this.anotherWorldEngine)
+    const dataArray = new Uint32Array(this.wasm.memory.buffer, dataPtr, 64 * 4)
+    let costs = []
+
+    for (let i = 0; i < 64; ++i) {
+      costs.push({
+        instructions: dataArray[i * 4],
+        frames: dataArray[i * 4 + 1],
+        maxInstructions: dataArray[i * 4 + 2],
+        lastFrame: dataArray[i * 4 + 3]
+      })
+    }
+
+    return costs
+  }
+
+  getScriptErrors(scriptId) {
+    this.wasm.anotherworldengine_build_script_errors_info(this.anotherWorldEngine, scriptId)
+
+    const textDecoder = new TextDecoder()
+    const dataPtr = this.wasm.anotherworldengine_get_shared_memory_pointer(this.anotherWorldEngine)
+    const dataArray = new Uint8Array(this.wasm.memory.buffer, dataPtr, SharedMemorySize)
+    const numErrors = dataArray[0] | (dataArray[1] << 8)
+    let errors = []
+    let idx = 2
+
+    for (let i = 0; i < numErrors; ++i) {
+      const addr = dataArray[idx++] | (dataArray[idx++] << 8)
+      const messageLen = dataArray[idx++]
+
+      errors.push({addr: int2Hex(addr, 4), message: textDecoder.decode(dataArray.slice(idx, idx + messageLen))})
+      idx += messageLen
+    }
+
+    return errors
+  }
+
+  // the patch methods return an error message, or null if the patch has been applied
+  patchBytes(fileId, offset, bytes) {
+    const len = this.writeBytes(bytes)
+    return this.readError(this.wasm.anotherworldengine_patch_bytes(this.anotherWorldEngine, fileId, offset, len))
+  }
+
+  patchInstruction(scriptId, pc, asmCode) {
+    const len = this.writeBytes(new TextEncoder().encode(asmCode))
+    return this.readError(this.wasm.anotherworldengine_patch_instruction(this.anotherWorldEngine, scriptId, pc, len))
+  }
+
+  patchNop(scriptId, pc) {
+    return this.readError(this.wasm.anotherworldengine_patch_nop(this.anotherWorldEngine, scriptId, pc))
+  }
+
+  revertPatch(idx) {
+    return this.readError(this.wasm.anotherworldengine_revert_patch(this.anotherWorldEngine, idx))
+  }
+
+  revertAllPatches() {
+    this.wasm.anotherworldengine_revert_all_patches(this.anotherWorldEngine)
+  }
+
+  getPatches() {
+    const len = this.wasm.anotherworldengine_build_patches(this.anotherWorldEngine)
+    return this.readText(len)
+  }
+
+  applyPatches(json) {
+    const len = this.writeBytes(new TextEncoder().encode(json))
+    return this.readError(this.wasm.anotherworldengine_apply_patches(this.anotherWorldEngine, len))
+  }
+
+  writeBytes(bytes) {
+    const dataPtr = this.wasm.anotherworldengine_get_shared_memory_pointer(this.anotherWorldEngine)
+    new Uint8Array(this.wasm.memory.buffer, dataPtr, bytes.length).set(bytes)
+    return bytes.length
+  }
+
+  readError(len) {
+    return len > 0 ? this.readText(len) : null
+  }
+
+  readText(len) {
+    const dataPtr = this.wasm.anotherworldengine_get_shared_memory_pointer(this.anotherWorldEngine)
+    return new TextDecoder().decode(new Uint8Array(this.wasm.memory.buffer, dataPtr, len))
+  }
+
   getActiveScriptFileId() {
     return this.wasm.anotherworldengine_get_active_script_file_id(this.anotherWorldEngine)
   }
@@ -297,9 +887,17 @@ export class AnotherWorldEngine {
 
       const asmCodeLen = dataArray[myIdx++]
       entry.asmCode = textDecoder.decode(dataArray.slice(myIdx, myIdx + asmCodeLen))
+      myIdx += asmCodeLen
+
+      const labelLen = dataArray[myIdx++]
+      entry.label = textDecoder.decode(dataArray.slice(myIdx, myIdx + labelLen))
+      myIdx += labelLen
+
+      const commentLen = dataArray[myIdx++] | (dataArray[myIdx++] << 8)
+      entry.comment = textDecoder.decode(dataArray.slice(myIdx, myIdx + commentLen))
+      myIdx += commentLen
 
       disassembledScript.push(entry)
-      myIdx += asmCodeLen
     }
 
     return {
@@ -6,42 +6,7 @@ export default {
     resourceNameByType: ['Sound', 'Music', 'Bitmap', 'Palette', 'Script', 'Poly Buffer', 'Unknown'],
   },
 
-  resourcesIdByPart: [{
-    palette: 0x14,
-    script: 0x15,
-    poly1: 0x16,
-    poly2: 0
-  }, {
-    palette: 0x17,
-    script: 0x18,
-    poly1: 0x19,
-    poly2: 0
-  }, {
-    palette: 0x1a,
-    script: 0x1b,
-    poly1: 0x1c,
-    poly2: 0x11
-  }, {
-    palette: 0x1d,
-    script: 0x1e,
-    poly1: 0x1f,
-    poly2: 0x11
-  }, {
-    palette: 0x20,
-    script: 0x21,
-    poly1: 0x22,
-    poly2: 0x11
-  }, {
-    palette: 0x23,
-    script: 0x24,
-    poly1: 0x25,
-    poly2: 0
-  }, {
-    palette: 0x26,
-    script: 0x27,
-    poly1: 0x28,
-    poly2: 0x11
-  }],
+  resourcesIdByPart: [], // set by the engine, with the resources of each game part
 
   freqTable: [
     0x0cff, 0x0dc3, 0x0e91, 0x0f6f, 0x1056, 0x114e, 0x1259, 0x136c,